@@ -153,6 +153,7 @@ pub async fn cli() -> Result<bool, anyhow::Error> {
                             first_name: Some("root".to_owned()),
                             last_name: Some("".to_owned()),
                             token: None,
+                            allowed_networks: None,
                         },
                     )
                     .await?;
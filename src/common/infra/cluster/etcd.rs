@@ -13,6 +13,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use config::{
     cluster::*,
     get_config,
@@ -49,24 +51,15 @@ pub(crate) async fn register_and_keepalive() -> Result<()> {
             }
 
             let lease_id = unsafe { LOCAL_NODE_KEY_LEASE_ID };
+            let lease_lost = AtomicBool::new(false);
             let ret = etcd::keepalive_lease_id(
                 lease_id,
                 get_config().limit.node_heartbeat_ttl,
                 is_offline,
+                || lease_lost.store(true, Ordering::Relaxed),
             )
             .await;
-            if ret.is_ok() {
-                break;
-            }
-            let e = ret.unwrap_err();
-            let estr = e.to_string();
-            if is_offline()
-                || estr
-                    != Error::from(etcd_client::Error::LeaseKeepAliveError(
-                        "lease expired or revoked".to_string(),
-                    ))
-                    .to_string()
-            {
+            if ret.is_ok() || is_offline() || !lease_lost.load(Ordering::Relaxed) {
                 break;
             }
             log::error!("[CLUSTER] keepalive lease id expired or revoked, set node online again.");
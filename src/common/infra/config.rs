@@ -18,8 +18,9 @@ use std::sync::Arc;
 use config::{RwAHashMap, RwHashMap};
 use dashmap::DashMap;
 use hashbrown::HashMap;
+use hashlink::lru_cache::LruCache;
 use once_cell::sync::Lazy;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use vector_enrichment::TableRegistry;
 
 use crate::{
@@ -32,7 +33,7 @@ use crate::{
         pipelines::PipeLine,
         prom::ClusterLeader,
         syslog::SyslogRoute,
-        user::User,
+        user::{ApiKey, User, UserSession},
     },
     service::{
         db::scheduler as db_scheduler, enrichment::StreamTable, enrichment_table::geoip::Geoip,
@@ -53,9 +54,29 @@ pub static USERS: Lazy<RwHashMap<String, User>> = Lazy::new(DashMap::default);
 pub static USERS_RUM_TOKEN: Lazy<Arc<RwHashMap<String, User>>> =
     Lazy::new(|| Arc::new(DashMap::default()));
 pub static ROOT_USER: Lazy<RwHashMap<String, User>> = Lazy::new(DashMap::default);
+// blake3 hash of `{org}/{passcode}` -> key of the user in USERS, see `auth::cache_user`
+pub static USERS_PASSCODE: Lazy<RwHashMap<[u8; 32], String>> = Lazy::new(DashMap::default);
 pub static ORGANIZATION_SETTING: Lazy<Arc<RwAHashMap<String, OrganizationSetting>>> =
     Lazy::new(|| Arc::new(tokio::sync::RwLock::new(HashMap::new())));
-pub static PASSWORD_HASH: Lazy<RwHashMap<String, String>> = Lazy::new(DashMap::default);
+pub static PASSWORD_HASH: Lazy<Mutex<LruCache<String, String>>> = Lazy::new(|| {
+    let capacity = config::get_config().auth.password_hash_cache_size;
+    Mutex::new(LruCache::new(capacity.max(1)))
+});
+// user -> (failed password checks, first failure timestamp)
+pub static LOGIN_FAILURES: Lazy<RwHashMap<String, (u32, i64)>> = Lazy::new(DashMap::default);
+// blake3 hash of a verified bearer token -> (user email, token expiry, cached until)
+pub static BEARER_USERS: Lazy<RwHashMap<[u8; 32], (String, i64, i64)>> =
+    Lazy::new(DashMap::default);
+// user -> whether the user is root, for users not in the default org cache
+pub static ROOT_USER_CHECKS: Lazy<RwHashMap<String, bool>> = Lazy::new(DashMap::default);
+// user -> (start of the minute, denials recorded in it)
+pub static AUTH_DENIAL_AUDITS: Lazy<RwHashMap<String, (i64, u32)>> = Lazy::new(DashMap::default);
+// (user, org, object, method, parent) of a permission check
+pub type PermissionKey = (String, String, String, String, String);
+// permission check -> (allowed, cached until)
+pub static PERMISSION_DECISIONS: Lazy<RwHashMap<PermissionKey, (bool, i64)>> =
+    Lazy::new(DashMap::default);
+pub static API_KEYS: Lazy<RwHashMap<String, ApiKey>> = Lazy::new(DashMap::default);
 pub static METRIC_CLUSTER_MAP: Lazy<Arc<RwAHashMap<String, Vec<String>>>> =
     Lazy::new(|| Arc::new(tokio::sync::RwLock::new(HashMap::new())));
 pub static METRIC_CLUSTER_LEADER: Lazy<Arc<RwAHashMap<String, ClusterLeader>>> =
@@ -85,5 +106,5 @@ pub static GEOIP_CITY_TABLE: Lazy<Arc<RwLock<Option<Geoip>>>> =
 pub static GEOIP_ASN_TABLE: Lazy<Arc<RwLock<Option<Geoip>>>> =
     Lazy::new(|| Arc::new(RwLock::new(None)));
 
-pub static USER_SESSIONS: Lazy<RwHashMap<String, String>> = Lazy::new(Default::default);
+pub static USER_SESSIONS: Lazy<RwHashMap<String, UserSession>> = Lazy::new(Default::default);
 pub static STREAM_PIPELINES: Lazy<RwHashMap<String, PipeLine>> = Lazy::new(DashMap::default);
@@ -30,3 +30,28 @@ impl Authz {
         }
     }
 }
+
+/// How the permission check of a user on an object is resolved, for debugging a denied request.
+/// Building it doesn't change what is enforced.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PermissionExplanation {
+    pub org_id: String,
+    pub user_id: String,
+    /// Root users skip the check
+    pub is_root: bool,
+    /// The role of the user in the org, `None` when the user isn't a member
+    pub role: Option<String>,
+    /// The OFGA object checked, e.g. `dashboard:{id}`
+    pub object: String,
+    /// The permission checked, after the route rewrites, e.g. `LIST` for a GET of a list
+    pub method: String,
+    /// The folder considered as the parent of the object
+    pub parent: String,
+    /// The route skips the check
+    pub bypassed: bool,
+    pub openfga_enabled: bool,
+    /// The raw answer of the checker, `None` when it isn't consulted
+    pub checker_allowed: Option<bool>,
+    /// Whether a request would be allowed
+    pub allowed: bool,
+}
@@ -35,6 +35,17 @@ pub struct Dashboard {
     pub version: i32,
 }
 
+impl Dashboard {
+    pub fn dashboard_id(&self) -> Option<&str> {
+        match (&self.v1, &self.v2, &self.v3) {
+            (Some(d), ..) => Some(&d.dashboard_id),
+            (_, Some(d), _) => Some(&d.dashboard_id),
+            (.., Some(d)) => Some(&d.dashboard_id),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct Dashboards {
     pub dashboards: Vec<Dashboard>,
@@ -79,6 +79,9 @@ pub enum IngestionTokensContainer {
 pub struct IngestionPasscode {
     pub passcode: String,
     pub user: String,
+    /// Unix seconds the passcode expires at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -57,6 +57,7 @@ impl UserRequest {
                 token,
                 rum_token: Some(rum_token),
                 role: self.role.clone(),
+                ..Default::default()
             }],
             is_external,
             password_ext: Some(password_ext),
@@ -105,6 +106,9 @@ impl DBUser {
             salt: local.salt,
             is_external: self.is_external,
             password_ext: self.password_ext.clone(),
+            token_expires_at: org.token_expires_at,
+            previous_token: org.previous_token.clone(),
+            allowed_networks: org.allowed_networks.clone(),
         })
     }
 
@@ -126,6 +130,9 @@ impl DBUser {
                     salt: self.salt.clone(),
                     is_external: self.is_external,
                     password_ext: self.password_ext.clone(),
+                    token_expires_at: org.token_expires_at,
+                    previous_token: org.previous_token,
+                    allowed_networks: org.allowed_networks,
                 })
             }
             ret_val
@@ -151,6 +158,14 @@ pub struct User {
     /// Is the user authenticated and created via LDAP
     pub is_external: bool,
     pub password_ext: Option<String>,
+    /// Unix seconds the passcode expires at, never when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_token: Option<PreviousToken>,
+    /// IPs or CIDRs a service account can send requests from, any when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_networks: Vec<String>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize, ToSchema)]
@@ -162,6 +177,22 @@ pub struct UserOrg {
     pub rum_token: Option<String>,
     #[serde(default)]
     pub role: UserRole,
+    /// Unix seconds the passcode expires at, never when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_token: Option<PreviousToken>,
+    /// IPs or CIDRs a service account can send requests from, any when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_networks: Vec<String>,
+}
+
+/// The passcode replaced by a rotation, still accepted until the grace period ends
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct PreviousToken {
+    pub token: String,
+    /// Unix seconds the grace period ends at
+    pub expires_at: i64,
 }
 
 impl PartialEq for UserOrg {
@@ -191,6 +222,9 @@ pub struct UpdateUser {
     pub role: Option<UserRole>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token: Option<String>,
+    /// Replaces the networks a service account can send requests from, an empty list allows any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_networks: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, ToSchema, EnumIter)]
@@ -457,10 +491,98 @@ pub struct AuthTokens {
     pub refresh_token: String,
 }
 
+/// Access token of a `session <id>` cookie, kept in memory with its lifetime
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserSession {
+    pub token: String,
+    pub user_id: String, // empty for sessions stored before the user was recorded
+    pub created_at: i64,
+    pub last_used_at: i64,
+}
+
+impl UserSession {
+    pub fn new(token: String, now: i64) -> Self {
+        Self {
+            token,
+            user_id: "".to_string(),
+            created_at: now,
+            last_used_at: now,
+        }
+    }
+
+    /// A session expires `ttl` seconds after it was created or `idle_timeout` seconds after it
+    /// was last used, a limit of 0 is disabled
+    pub fn is_expired(&self, now: i64, ttl: i64, idle_timeout: i64) -> bool {
+        (ttl > 0 && now - self.created_at >= ttl)
+            || (idle_timeout > 0 && now - self.last_used_at >= idle_timeout)
+    }
+}
+
+/// A session of a user without its access token, for the admin API
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
 pub struct AuthTokensExt {
     pub auth_ext: String,
     pub refresh_token: String,
     pub request_time: i64,
     pub expires_in: i64,
+    /// The scope of the presigned url the cookie was issued for
+    #[serde(default)]
+    pub org_id: Option<String>,
+    #[serde(default)]
+    pub resource: Option<String>,
+    #[serde(default)]
+    pub allowed_cidr: Option<String>,
+}
+
+/// Long-lived key for programmatic access sent in the `X-Api-Key` header. It authenticates as
+/// the user who created it, within its organization only.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub key_id: String,
+    pub org_id: String,
+    pub user_email: String,
+    pub name: String,
+    pub key_hash: String,
+    pub created_at: i64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyRequest {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyResponse {
+    pub key_id: String,
+    pub name: String,
+    pub user_email: String,
+    pub created_at: i64,
+    /// Only returned once, when the key is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+impl From<&ApiKey> for ApiKeyResponse {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            key_id: key.key_id.clone(),
+            name: key.name.clone(),
+            user_email: key.user_email.clone(),
+            created_at: key.created_at,
+            api_key: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyList {
+    pub data: Vec<ApiKeyResponse>,
 }
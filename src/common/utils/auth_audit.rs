@@ -0,0 +1,183 @@
+use std::{collections::HashMap, future::Future, str::FromStr, sync::Arc};
+
+use config::{
+    get_config,
+    utils::{json, time::now_micros},
+    RwHashMap,
+};
+use jsonwebtoken::{
+    decode,
+    jwk::{self, AlgorithmParameters},
+    Algorithm, DecodingKey, TokenData, Validation,
+};
+use proto::cluster_rpc;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::RwLock;
+#[cfg(feature = "enterprise")]
+use {jsonwebtoken::decode_header, once_cell::sync::Lazy};
+
+#[cfg(feature = "enterprise")]
+use crate::common::meta::user::TokenValidationResponse;
+use crate::{common::infra::config::AUTH_DENIAL_AUDITS, service::usage::ingestion_service};
+
+/// Stream of [`ZO_USAGE_ORG`](config::Config) the denials are recorded to
+pub const AUTH_DENIALS_STREAM: &str = "auth_denials";
+
+/// Once this many users are tracked the past minutes are swept
+const AUTH_DENIAL_AUDITS_MAX: usize = 10_000;
+
+/// A request denied by the authorization checks
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AuthDenial {
+    pub _timestamp: i64,
+    pub org_id: String,
+    pub user_id: String,
+    pub o2_type: String,
+    pub method: String,
+    pub path: String,
+    pub reason: String,
+}
+
+/// Records a denied request to the [`AUTH_DENIALS_STREAM`] when `ZO_AUTH_AUDIT_DENIALS` is on.
+/// Best effort: the entry is ingested in the background and dropped on errors, and at most
+/// `ZO_AUTH_AUDIT_DENIALS_PER_MINUTE` entries are recorded per user so a client retrying a
+/// denied request can't amplify it into the stream.
+pub fn record_denial(
+    org_id: &str,
+    user_id: &str,
+    o2_type: &str,
+    method: &str,
+    path: &str,
+    reason: &str,
+) {
+    let cfg = get_config();
+    if !cfg.auth.audit_denials {
+        return;
+    }
+    let Some(denial) = new_denial(
+        &AUTH_DENIAL_AUDITS,
+        cfg.auth.audit_denials_per_minute,
+        now_micros(),
+        [org_id, user_id, o2_type, method, path, reason],
+    ) else {
+        return;
+    };
+    let usage_org = cfg.common.usage_org.clone();
+    tokio::spawn(async move {
+        let req = cluster_rpc::UsageRequest {
+            stream_name: AUTH_DENIALS_STREAM.to_owned(),
+            data: Some(cluster_rpc::UsageData::from(vec![
+                json::to_value(&denial).unwrap(),
+            ])),
+        };
+        if let Err(e) = ingestion_service::ingest(&usage_org, req).await {
+            log::error!("Error in recording auth denial of {}: {e}", denial.user_id);
+        }
+    });
+}
+
+/// The entry of a denial, `None` when the user already had `max_per_minute` denials recorded in
+/// the minute of `now`, given in microseconds
+fn new_denial(
+    audits: &RwHashMap<String, (i64, u32)>,
+    max_per_minute: u32,
+    now: i64,
+    [org_id, user_id, o2_type, method, path, reason]: [&str; 6],
+) -> Option<AuthDenial> {
+    if !allow_denial(audits, user_id, now / 1_000_000, max_per_minute) {
+        return None;
+    }
+    Some(AuthDenial {
+        _timestamp: now,
+        org_id: org_id.to_string(),
+        user_id: user_id.to_string(),
+        o2_type: o2_type.to_string(),
+        method: method.to_string(),
+        path: path.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+/// Counts a denial of the user in the minute of `now`, false once `max_per_minute` were counted
+fn allow_denial(
+    audits: &RwHashMap<String, (i64, u32)>,
+    user_id: &str,
+    now: i64,
+    max_per_minute: u32,
+) -> bool {
+    let minute = now - now % 60;
+    if audits.len() >= AUTH_DENIAL_AUDITS_MAX {
+        audits.retain(|_, (start, _)| *start == minute);
+    }
+    let mut entry = audits.entry(user_id.to_string()).or_insert((minute, 0));
+    let (start, count) = entry.value_mut();
+    if *start != minute {
+        *start = minute;
+        *count = 0;
+    }
+    if *count >= max_per_minute {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_denial() {
+        let audits = RwHashMap::default();
+        let now = 1_700_000_040;
+        for _ in 0..3 {
+            assert!(allow_denial(&audits, "a@example.com", now, 3));
+        }
+        assert!(!allow_denial(&audits, "a@example.com", now + 1, 3));
+        // other users have their own budget
+        assert!(allow_denial(&audits, "b@example.com", now, 3));
+        // and the budget is reset every minute
+        assert!(allow_denial(&audits, "a@example.com", now + 60, 3));
+        assert!(!allow_denial(&audits, "a@example.com", now, 0));
+    }
+
+    #[test]
+    fn test_new_denial() {
+        let audits = RwHashMap::default();
+        let now = 1_700_000_040_000_000;
+        let denial = [
+            "default",
+            "a@example.com",
+            "logs:default",
+            "GET",
+            "default/logs",
+            "permission denied",
+        ];
+        assert_eq!(
+            new_denial(&audits, 1, now, denial),
+            Some(AuthDenial {
+                _timestamp: now,
+                org_id: "default".to_string(),
+                user_id: "a@example.com".to_string(),
+                o2_type: "logs:default".to_string(),
+                method: "GET".to_string(),
+                path: "default/logs".to_string(),
+                reason: "permission denied".to_string(),
+            })
+        );
+        // retrying within the minute isn't recorded again
+        assert_eq!(new_denial(&audits, 1, now + 1_000_000, denial), None);
+    }
+
+    #[test]
+    fn test_allow_denial_sweeps() {
+        let audits = RwHashMap::default();
+        let now = 1_700_000_040;
+        for i in 0..AUTH_DENIAL_AUDITS_MAX {
+            audits.insert(format!("user{i}"), (now - 120, 1));
+        }
+        assert!(allow_denial(&audits, "a@example.com", now, 3));
+        assert_eq!(audits.len(), 1);
+    }
+}
@@ -410,6 +410,114 @@ pub struct Auth {
     pub cookie_secure_only: bool,
     #[env_config(name = "ZO_EXT_AUTH_SALT", default = "openobserve")]
     pub ext_auth_salt: String,
+    #[env_config(
+        name = "ZO_EMAIL_REGEX",
+        default = "",
+        help = "Regex an email id must match, overrides the default email regex"
+    )]
+    pub email_regex: String,
+    #[env_config(
+        name = "ZO_EMAIL_DOMAIN_ALLOWLIST",
+        default = "",
+        help = "Only allow email ids of these domains as comma separated values, e.g. company.com"
+    )]
+    pub email_domain_allowlist: String,
+    #[env_config(
+        name = "ZO_JWKS_CACHE_TTL",
+        default = 300,
+        help = "Seconds the JWK set of the identity provider is cached for, 0 fetches it for every token"
+    )]
+    pub jwks_cache_ttl: i64,
+    #[env_config(
+        name = "ZO_PASSWORD_HASH_CACHE_SIZE",
+        default = 10000,
+        help = "Max number of password hashes kept in memory, the least recently used are evicted"
+    )]
+    pub password_hash_cache_size: usize,
+    #[env_config(
+        name = "ZO_PRESIGNED_URL_MAX_EXP_IN",
+        default = 86400,
+        help = "Max seconds a presigned login url can be valid for"
+    )]
+    pub presigned_url_max_exp_in: i64,
+    #[env_config(
+        name = "ZO_LOGIN_MAX_FAILURES",
+        default = 5,
+        help = "Failed password checks of a user within ZO_LOGIN_FAILURE_WINDOW after which further attempts are rejected, 0 disables the lockout"
+    )]
+    pub login_max_failures: u32,
+    #[env_config(
+        name = "ZO_LOGIN_FAILURE_WINDOW",
+        default = 300,
+        help = "Seconds failed password checks are counted for, a locked out user can retry once it passed"
+    )]
+    pub login_failure_window: i64,
+    #[env_config(
+        name = "ZO_SESSION_TTL",
+        default = 2592000,
+        help = "Seconds a login session is valid for since it was created, 0 never expires it"
+    )] // 30 days
+    pub session_ttl: i64,
+    #[env_config(
+        name = "ZO_SESSION_IDLE_TIMEOUT",
+        default = 604800,
+        help = "Seconds a login session stays valid without being used, 0 never expires it"
+    )] // 7 days
+    pub session_idle_timeout: i64,
+    #[env_config(
+        name = "ZO_AUTH_AUDIT_DENIALS",
+        default = false,
+        help = "Record denied requests to the auth_denials stream of ZO_USAGE_ORG"
+    )]
+    pub audit_denials: bool,
+    #[env_config(
+        name = "ZO_AUTH_AUDIT_DENIALS_PER_MINUTE",
+        default = 10,
+        help = "Max denials recorded per user and minute, the others are dropped"
+    )]
+    pub audit_denials_per_minute: u32,
+    #[env_config(
+        name = "ZO_PASSCODE_TTL",
+        default = 0,
+        help = "Seconds a passcode issued through the passcode API is valid for, 0 never expires it"
+    )]
+    pub passcode_ttl: i64,
+    #[env_config(
+        name = "ZO_PASSCODE_ROTATION_GRACE",
+        default = 86400,
+        help = "Seconds the previous passcode stays valid after a rotation, so clients can switch to the new one"
+    )]
+    pub passcode_rotation_grace: i64,
+    #[env_config(
+        name = "ZO_PERMISSION_CACHE_TTL",
+        default = 2,
+        help = "Seconds a permission decision of the authorizer is reused for the same user, object and action, 0 disables the cache"
+    )]
+    pub permission_cache_ttl: i64,
+    #[env_config(
+        name = "ZO_PER_STREAM_INGEST_RBAC",
+        default = false,
+        help = "Check the ingest permission of the stream instead of allowing every user of the org to ingest into any stream"
+    )]
+    pub per_stream_ingest_rbac: bool,
+    #[env_config(
+        name = "ZO_MAX_SESSIONS_PER_USER",
+        default = 0,
+        help = "Max login sessions a user can have at once, 0 doesn't limit them"
+    )]
+    pub max_sessions_per_user: usize,
+    #[env_config(
+        name = "ZO_SESSION_LIMIT_POLICY",
+        default = "reject",
+        help = "What a login of a user at ZO_MAX_SESSIONS_PER_USER does: reject, rejects the login, or evict_oldest, logs the oldest session out"
+    )]
+    pub session_limit_policy: String,
+    #[env_config(
+        name = "ZO_TRUSTED_PROXIES",
+        default = "",
+        help = "Comma separated IPs or CIDRs of the proxies in front of OpenObserve, only the X-Forwarded-For entries they added are used as the client IP"
+    )]
+    pub trusted_proxies: String,
 }
 
 #[derive(EnvConfig)]
@@ -434,6 +542,12 @@ pub struct Grpc {
     pub stream_header_key: String,
     #[env_config(name = "ZO_INTERNAL_GRPC_TOKEN", default = "")]
     pub internal_grpc_token: String,
+    #[env_config(
+        name = "ZO_INTERNAL_GRPC_TOKEN_SECONDARY",
+        default = "",
+        help = "Also accepted as the internal grpc token, set it to the previous token while rotating ZO_INTERNAL_GRPC_TOKEN"
+    )]
+    pub internal_grpc_token_secondary: String,
     #[env_config(
         name = "ZO_GRPC_MAX_MESSAGE_SIZE",
         default = 16,
@@ -535,6 +649,12 @@ pub struct Common {
     pub feature_query_infer_schema: bool,
     #[env_config(name = "ZO_FEATURE_QUERY_EXCLUDE_ALL", default = true)]
     pub feature_query_exclude_all: bool,
+    #[env_config(
+        name = "ZO_FEATURE_QUERY_FOLD_IDENTIFIER_CASE",
+        default = false,
+        help = "Lowercase every identifier, alias and stream name extracted from a query, quoted ones included, for case-insensitive backends"
+    )]
+    pub feature_query_fold_identifier_case: bool,
     #[env_config(name = "ZO_UI_ENABLED", default = true)]
     pub ui_enabled: bool,
     #[env_config(name = "ZO_UI_SQL_BASE64_ENABLED", default = false)]
@@ -707,6 +827,12 @@ pub struct Common {
         help = "Discard data of last n seconds from cached results"
     )]
     pub result_cache_discard_duration: i64,
+    #[env_config(
+        name = "ZO_SQL_PARSE_SLOW_THRESHOLD",
+        default = 100,
+        help = "Milliseconds parsing a query can take before it's logged as slow with its size, 0 disables the log"
+    )]
+    pub sql_parse_slow_threshold: u64,
 }
 
 #[derive(EnvConfig)]
@@ -766,6 +892,12 @@ pub struct Limit {
     pub query_partition_min_secs: i64,
     #[env_config(name = "ZO_QUERY_GROUP_BASE_SPEED", default = 768)] // MB/s/core
     pub query_group_base_speed: usize,
+    #[env_config(
+        name = "ZO_QUERY_MAX_IN_LIST_SIZE",
+        default = 1000,
+        help = "Max values of an IN list turned into quick text filters, a longer list only adds its field, 0 means no limit"
+    )]
+    pub max_in_list_size: usize,
     #[env_config(name = "ZO_INGEST_ALLOWED_UPTO", default = 5)] // in hours - in past
     pub ingest_allowed_upto: i64,
     #[env_config(name = "ZO_INGEST_FLATTEN_LEVEL", default = 3)] // default flatten level
@@ -900,6 +1032,12 @@ pub struct Compact {
         help = "Clean the jobs which are finished more than this time"
     )]
     pub job_clean_wait_time: i64,
+    #[env_config(
+        name = "ZO_COMPACT_NODE_OWNERSHIP_TTL",
+        default = 3600, // 1 hour
+        help = "If the node owning a compact offset has not refreshed it in this time, other nodes can take it over"
+    )]
+    pub node_ownership_ttl: i64,
 }
 
 #[derive(EnvConfig)]
@@ -1007,6 +1145,12 @@ pub struct Etcd {
     pub command_timeout: u64,
     #[env_config(name = "ZO_ETCD_LOCK_WAIT_TIMEOUT", default = 3600)]
     pub lock_wait_timeout: u64,
+    #[env_config(
+        name = "ZO_ETCD_LOCK_TTL",
+        default = 60,
+        help = "Seconds a distributed lock outlives its holder, the lock is kept alive while held and released this long after the holder stops renewing it"
+    )]
+    pub lock_ttl: i64,
     #[env_config(name = "ZO_ETCD_USER", default = "")]
     pub user: String,
     #[env_config(name = "ZO_ETCD_PASSWORD", default = "")]
@@ -1019,10 +1163,20 @@ pub struct Etcd {
     pub cert_file: String,
     #[env_config(name = "ZO_ETCD_KEY_FILE", default = "")]
     pub key_file: String,
-    #[env_config(name = "ZO_ETCD_DOMAIN_NAME", default = "")]
+    #[env_config(
+        name = "ZO_ETCD_DOMAIN_NAME",
+        default = "",
+        help = "Server name the etcd certificates are verified against instead of the host of each endpoint"
+    )]
     pub domain_name: String,
     #[env_config(name = "ZO_ETCD_LOAD_PAGE_SIZE", default = 1000)]
     pub load_page_size: i64,
+    #[env_config(
+        name = "ZO_ETCD_MAX_TXN_OPS",
+        default = 128,
+        help = "Operations per transaction for batched puts and deletes, etcd rejects transactions above its --max-txn-ops, 128 by default"
+    )]
+    pub max_txn_ops: usize,
 }
 
 #[derive(Debug, EnvConfig)]
@@ -1210,6 +1364,19 @@ pub fn init() -> Config {
 }
 
 fn check_common_config(cfg: &mut Config) -> Result<(), anyhow::Error> {
+    if !cfg.auth.email_regex.is_empty() {
+        if let Err(e) = regex::Regex::new(&cfg.auth.email_regex) {
+            return Err(anyhow::anyhow!("ZO_EMAIL_REGEX is not a valid regex: {e}"));
+        }
+    }
+    cfg.auth.session_limit_policy = cfg.auth.session_limit_policy.to_lowercase();
+    if !["reject", "evict_oldest"].contains(&cfg.auth.session_limit_policy.as_str()) {
+        return Err(anyhow::anyhow!(
+            "ZO_SESSION_LIMIT_POLICY must be reject or evict_oldest, got {}",
+            cfg.auth.session_limit_policy
+        ));
+    }
+
     if cfg.limit.file_push_interval == 0 {
         cfg.limit.file_push_interval = 60;
     }
@@ -1382,6 +1549,12 @@ fn check_etcd_config(cfg: &mut Config) -> Result<(), anyhow::Error> {
     if !cfg.etcd.prefix.is_empty() && !cfg.etcd.prefix.ends_with('/') {
         cfg.etcd.prefix = format!("{}/", cfg.etcd.prefix);
     }
+    if cfg.etcd.max_txn_ops == 0 {
+        cfg.etcd.max_txn_ops = 128;
+    }
+    if cfg.etcd.lock_ttl < 1 {
+        cfg.etcd.lock_ttl = 60;
+    }
 
     if !cfg.etcd.cert_auth {
         return Ok(());
@@ -1646,6 +1819,13 @@ mod tests {
         let ret = check_common_config(&mut cfg);
         assert!(ret.is_err());
 
+        cfg.compact.data_retention_days = 10;
+        cfg.auth.session_limit_policy = "Evict_Oldest".to_string();
+        assert!(check_common_config(&mut cfg).is_ok());
+        assert_eq!(cfg.auth.session_limit_policy, "evict_oldest");
+        cfg.auth.session_limit_policy = "oldest".to_string();
+        assert!(check_common_config(&mut cfg).is_err());
+
         cfg.common.data_dir = "".to_string();
         let ret = check_path_config(&mut cfg);
         assert!(ret.is_ok());
@@ -13,16 +13,22 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use chrono::DateTime;
+use std::ops::ControlFlow;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use hashbrown::HashSet;
 use regex::Regex;
 use serde::Serialize;
 use sqlparser::{
     ast::{
-        BinaryOperator, Expr as SqlExpr, Function, FunctionArg, FunctionArgExpr, FunctionArguments,
-        GroupByExpr, Offset as SqlOffset, OrderByExpr, Query, Select, SelectItem, SetExpr,
-        Statement, TableFactor, TableWithJoins, Value,
+        visit_expressions, visit_expressions_mut, BinaryOperator, Expr as SqlExpr, Function,
+        FunctionArg, FunctionArgExpr, FunctionArguments, GroupByExpr, Ident, Offset as SqlOffset,
+        OrderByExpr, Query, Select, SelectItem, SetExpr, Statement, Subscript, TableFactor,
+        TableWithJoins, UnaryOperator, Value, WindowType,
     },
     parser::Parser,
+    tokenizer::{Location, Token, Tokenizer, Whitespace},
 };
 
 use crate::get_config;
@@ -33,18 +39,90 @@ const MAX_OFFSET: i64 = 100000;
 /// parsed sql
 #[derive(Clone, Debug, Serialize)]
 pub struct Sql {
-    pub fields: Vec<String>,           // projection, select, fields
-    pub selection: Option<SqlExpr>,    // where
-    pub source: String,                // table
-    pub order_by: Vec<(String, bool)>, // desc: true / false
-    pub group_by: Vec<String>,         // field
+    pub fields: Vec<String>,        // projection, select, fields
+    pub selection: Option<SqlExpr>, // where
+    pub source: String,             // table
+    pub order_by: Vec<(String, OrderBy)>,
+    pub group_by: Vec<String>, // field
     pub having: bool,
     pub offset: i64,
     pub limit: i64,
+    pub offset_param: Option<String>, // placeholder of `OFFSET $1`, offset is 0 then
+    pub limit_param: Option<String>,  // placeholder of `LIMIT $1`, limit is 0 then
+    pub time_range: Option<(i64, i64)>,
+    pub quick_text: Vec<(String, String, SqlOperator, MatchType, Option<String>)>, /* use text line quick filter */
+    pub field_alias: Vec<(String, String)>, // alias for select field
+    pub subquery: Option<String>,           // subquery in data source
+    pub quoted_fields: Vec<String>,         // quoted identifiers
+    pub has_wildcard: bool,                 // select * or t.*
+    pub wildcard_qualifiers: Vec<String>,   // qualifier of t.*
+    pub warnings: Vec<String>,              // non fatal parse issues
+    pub explicit_order: bool,               // the query has an ORDER BY
+    pub distinct: bool,                     // select distinct
+    pub field_paths: Vec<String>,           // root.key paths of subscripted fields
+    pub subquery_sources: Vec<String>,      // tables read by subqueries in expressions
+    pub aggregates: Vec<String>,            // aggregate calls in the projection
+    pub ignored_clauses: Vec<String>,       // clauses not taken into account when parsing
+}
+
+/// What the parser derived from a query without running it, see [`Sql::explain`]
+#[derive(Clone, Debug, Serialize)]
+pub struct SqlPlan {
+    pub source: String,
+    pub subquery_sources: Vec<String>,
     pub time_range: Option<(i64, i64)>,
-    pub quick_text: Vec<(String, String, SqlOperator)>, // use text line quick filter
-    pub field_alias: Vec<(String, String)>,             // alias for select field
-    pub subquery: Option<String>,                       // subquery in data source
+    pub fields: Vec<String>,
+    pub field_paths: Vec<String>,
+    pub aggregates: Vec<String>,
+    pub group_by: Vec<String>,
+    pub order_by: Vec<(String, OrderBy)>,
+    pub limit: i64,
+    pub offset: i64,
+    pub limit_param: Option<String>,
+    pub offset_param: Option<String>,
+    pub quick_text: Vec<(String, String, SqlOperator, MatchType, Option<String>)>,
+    pub ignored_clauses: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Functions counted as aggregates by [`Sql::explain`], lowercase
+const AGGREGATE_FUNCTIONS: [&str; 30] = [
+    "approx_distinct",
+    "approx_median",
+    "approx_percentile_cont",
+    "approx_percentile_cont_with_weight",
+    "array_agg",
+    "avg",
+    "bit_and",
+    "bit_or",
+    "bit_xor",
+    "bool_and",
+    "bool_or",
+    "corr",
+    "count",
+    "covar_pop",
+    "covar_samp",
+    "first_value",
+    "last_value",
+    "max",
+    "mean",
+    "median",
+    "min",
+    "stddev",
+    "stddev_pop",
+    "stddev_samp",
+    "string_agg",
+    "sum",
+    "var",
+    "var_pop",
+    "var_samp",
+    "variance",
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum OrderBy {
+    Asc,
+    Desc,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
@@ -58,9 +136,21 @@ pub enum SqlOperator {
     Lt,
     Lte,
     Like,
+    ILike,
     Nop,
 }
 
+/// How a quick_text value should be matched, classified from the LIKE pattern
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum MatchType {
+    Exact,    // abc
+    Prefix,   // abc%
+    Suffix,   // %abc
+    Contains, // %abc%
+    Pattern,  // a%b, a_c: wildcard inside the pattern, value keeps the raw pattern
+    Excluded, // NOT BETWEEN a AND b: the value bounds the excluded range, `< a OR >= b`
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub enum SqlValue {
     String(String),
@@ -71,6 +161,7 @@ pub struct Projection<'a>(pub &'a Vec<SelectItem>);
 pub struct Quicktext<'a>(pub &'a Option<SqlExpr>);
 pub struct Timerange<'a>(pub &'a Option<SqlExpr>);
 pub struct Source<'a>(pub &'a [TableWithJoins]);
+pub struct TableFunctions<'a>(pub &'a [TableWithJoins]);
 pub struct Order<'a>(pub &'a OrderByExpr);
 pub struct Group<'a>(pub &'a SqlExpr);
 pub struct Offset<'a>(pub &'a SqlOffset);
@@ -91,6 +182,13 @@ impl Sql {
         if statement.is_empty() {
             return Err(anyhow::anyhow!("SQL is empty"));
         }
+        if statement.len() > 1 {
+            return Err(anyhow::anyhow!(
+                "Only one SQL statement is allowed, got {} statements, the second statement starts at byte {}",
+                statement.len(),
+                get_second_statement_offset(sql).unwrap_or_default()
+            ));
+        }
         let statement = &statement[0];
         let sql: Result<Sql, anyhow::Error> = statement.try_into();
         if sql.is_err() {
@@ -99,12 +197,305 @@ impl Sql {
 
         Ok(sql.unwrap())
     }
+
+    /// Returns the ORDER BY of the query, or the default one when there is none, so that every
+    /// caller orders the results the same way
+    pub fn order_by_or_default(&self, default: (&str, OrderBy)) -> Vec<(String, OrderBy)> {
+        if self.order_by.is_empty() {
+            vec![(default.0.to_string(), default.1)]
+        } else {
+            self.order_by.clone()
+        }
+    }
+
+    /// The fields plus the `root.key` paths of their subscripted accesses, e.g. `payload` and
+    /// `payload.a.b` for `payload['a']['b']`, so JSON columns can be narrowed to the accessed
+    /// keys. `fields` only has the roots.
+    pub fn fields_with_paths(&self) -> Vec<String> {
+        let mut fields = self.fields.clone();
+        fields.extend(self.field_paths.iter().cloned());
+        fields.sort();
+        fields.dedup();
+        fields
+    }
+
+    /// The WHERE clause as a canonical predicate string for engines the filter is forwarded to:
+    /// every column identifier double quoted, unquoted ones lowercased first, and the operators
+    /// spaced the way sqlparser renders them. With `strip_time_range` the timestamp column
+    /// bounds ANDed at the top level are dropped, as the time range is passed on separately.
+    /// `None` when there is no WHERE or nothing is left of it.
+    pub fn where_clause_string(&self, strip_time_range: bool) -> Option<String> {
+        let mut expr = self.selection.clone()?;
+        if strip_time_range {
+            expr = strip_time_bounds(expr, &get_config().common.column_timestamp)?;
+        }
+        let _ = visit_expressions_mut(&mut expr, |expr| {
+            match expr {
+                SqlExpr::Identifier(ident) => quote_ident(ident),
+                SqlExpr::CompoundIdentifier(idents) => idents.iter_mut().for_each(quote_ident),
+                _ => {}
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        Some(expr.to_string())
+    }
+
+    /// Lowercases the identifiers extracted from the query, quoted ones too, and the aliases,
+    /// for backends that fold every identifier. By default only unquoted identifiers are.
+    /// The stream names are folded as well, so `FROM MyStream` reads the stream `mystream`.
+    fn fold_identifier_case(&mut self) {
+        let fold = |v: &mut String| *v = v.to_lowercase();
+        fold(&mut self.source);
+        self.subquery_sources.iter_mut().for_each(fold);
+        self.subquery_sources.sort();
+        self.subquery_sources.dedup();
+        self.fields.iter_mut().for_each(fold);
+        self.fields.sort();
+        self.fields.dedup();
+        self.quoted_fields.iter_mut().for_each(fold);
+        self.quoted_fields.sort();
+        self.quoted_fields.dedup();
+        self.field_paths.iter_mut().for_each(fold);
+        self.field_paths.sort();
+        self.field_paths.dedup();
+        for (expr, alias) in self.field_alias.iter_mut() {
+            fold(expr);
+            fold(alias);
+        }
+        for (field, ..) in self.quick_text.iter_mut() {
+            fold(field);
+        }
+        self.group_by = dedup_keys(
+            self.group_by.iter().map(|v| v.to_lowercase()).collect(),
+            |v| v,
+        );
+        self.order_by = dedup_keys(
+            self.order_by
+                .iter()
+                .map(|(v, order)| (v.to_lowercase(), *order))
+                .collect(),
+            |(v, _)| v,
+        );
+    }
+
+    /// The source, time range, fields and aggregates extracted from the query, plus the
+    /// clauses the parser ignored, e.g. to show why a query scans more than expected. Purely
+    /// introspective, the query is executed the same way.
+    pub fn explain(&self) -> SqlPlan {
+        SqlPlan {
+            source: self.source.clone(),
+            subquery_sources: self.subquery_sources.clone(),
+            time_range: self.time_range,
+            fields: self.fields.clone(),
+            field_paths: self.field_paths.clone(),
+            aggregates: self.aggregates.clone(),
+            group_by: self.group_by.clone(),
+            order_by: self.order_by.clone(),
+            limit: self.limit,
+            offset: self.offset,
+            limit_param: self.limit_param.clone(),
+            offset_param: self.offset_param.clone(),
+            quick_text: self.quick_text.clone(),
+            ignored_clauses: self.ignored_clauses.clone(),
+            warnings: self.warnings.clone(),
+        }
+    }
+
+    /// Check the fields referenced by the query against the schema fields and returns
+    /// the unknown ones. Aliases defined by the query and `*` are ignored, unquoted
+    /// identifiers match case-insensitively while quoted identifiers must match exactly.
+    pub fn validate_fields(&self, schema_fields: &HashSet<String>) -> Result<(), Vec<String>> {
+        let mut aliases: HashSet<String> = self
+            .field_alias
+            .iter()
+            .map(|(_, alias)| alias.to_string())
+            .collect();
+        if let Some(subquery) = self.subquery.as_ref() {
+            if let Ok(subquery) = Sql::new(subquery) {
+                aliases.extend(subquery.field_alias.into_iter().map(|(_, alias)| alias));
+            }
+        }
+
+        let mut unknown_fields = Vec::new();
+        for field in self.fields.iter() {
+            if field == "*"
+                || aliases
+                    .iter()
+                    .any(|a| a == field || a.to_lowercase() == *field)
+                || schema_fields.contains(field)
+            {
+                continue;
+            }
+            if !self.quoted_fields.contains(field)
+                && schema_fields.iter().any(|f| f.eq_ignore_ascii_case(field))
+            {
+                continue;
+            }
+            unknown_fields.push(field.to_string());
+        }
+        if unknown_fields.is_empty() {
+            Ok(())
+        } else {
+            Err(unknown_fields)
+        }
+    }
+}
+
+/// The widest time range covering all the queries, a 0 bound or a missing time range is
+/// unbounded. `None` when there are no queries.
+pub fn union_time_ranges(sqls: &[Sql]) -> Option<(i64, i64)> {
+    let ranges = sqls.iter().map(|sql| sql.time_range.unwrap_or((0, 0)));
+    ranges.reduce(|(start, end), (s, e)| {
+        let start = if start == 0 || s == 0 {
+            0
+        } else {
+            start.min(s)
+        };
+        let end = if end == 0 || e == 0 { 0 } else { end.max(e) };
+        (start, end)
+    })
+}
+
+/// The narrowest time range shared by all the queries, a 0 bound or a missing time range is
+/// unbounded. `None` when there are no queries or the time ranges don't overlap.
+pub fn intersect_time_ranges(sqls: &[Sql]) -> Option<(i64, i64)> {
+    let ranges = sqls.iter().map(|sql| sql.time_range.unwrap_or((0, 0)));
+    let (start, end) = ranges.reduce(|(start, end), (s, e)| {
+        let start = start.max(s);
+        let end = match (end, e) {
+            (0, e) => e,
+            (end, 0) => end,
+            (end, e) => end.min(e),
+        };
+        (start, end)
+    })?;
+    if end != 0 && start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Removes the `-- ...` and `/* ... */` comments, optimizer hints like `/*+ ... */` included, and
+/// the trailing `;` of the query. The search rewrites the query text with regexes and flattens it
+/// to one line, a comment left in would swallow the rest of the query. Each comment is replaced
+/// with a space, so the tokens around it stay apart. A query the tokenizer rejects is returned
+/// trimmed, the parser reports the error.
+pub fn strip_comments(sql: &str) -> String {
+    let dialect = sqlparser::dialect::GenericDialect {};
+    let Ok(tokens) = Tokenizer::new(&dialect, sql).tokenize_with_location() else {
+        return sql.trim().to_string();
+    };
+    let mut stripped = String::with_capacity(sql.len());
+    let mut last = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if let Token::Whitespace(
+            Whitespace::SingleLineComment { .. } | Whitespace::MultiLineComment(_),
+        ) = token.token
+        {
+            let start = location_to_offset(sql, &token.location);
+            let end = tokens
+                .get(i + 1)
+                .map(|next| location_to_offset(sql, &next.location))
+                .unwrap_or(sql.len());
+            stripped.push_str(&sql[last..start]);
+            stripped.push(' ');
+            last = end;
+        }
+    }
+    stripped.push_str(&sql[last..]);
+    stripped
+        .trim()
+        .trim_end_matches(|c: char| c == ';' || c.is_whitespace())
+        .to_string()
+}
+
+/// Returns the byte offset of the first token after the first statement delimiter
+fn get_second_statement_offset(sql: &str) -> Option<usize> {
+    let dialect = sqlparser::dialect::GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize_with_location()
+        .ok()?;
+    let mut after_delimiter = false;
+    for token in tokens {
+        match token.token {
+            Token::SemiColon => after_delimiter = true,
+            Token::Whitespace(_) | Token::EOF => {}
+            _ if after_delimiter => return Some(location_to_offset(sql, &token.location)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Convert the tokenizer location (1-based line and char column) to byte offset
+fn location_to_offset(sql: &str, location: &Location) -> usize {
+    let mut offset = 0;
+    for (i, line) in sql.split_inclusive('\n').enumerate() {
+        if i + 1 == location.line as usize {
+            return offset
+                + line
+                    .chars()
+                    .take(location.column.saturating_sub(1) as usize)
+                    .map(|c| c.len_utf8())
+                    .sum::<usize>();
+        }
+        offset += line.len();
+    }
+    offset
+}
+
+/// Logs the size of the query when converting it took longer than
+/// `ZO_SQL_PARSE_SLOW_THRESHOLD`, the filters are walked once per extracted field
+struct SlowParseGuard<'a> {
+    sql: &'a Statement,
+    start: std::time::Instant,
+}
+
+impl Drop for SlowParseGuard<'_> {
+    fn drop(&mut self) {
+        let threshold = get_config().common.sql_parse_slow_threshold;
+        let took = self.start.elapsed().as_millis();
+        if threshold == 0 || took < threshold as u128 {
+            return;
+        }
+        let stats = parse_stats(self.sql);
+        log::warn!(
+            "[SQL] slow parse: took_ms={took} query_len={} nodes={} in_lists={} max_in_list={}",
+            self.sql.to_string().len(),
+            stats.nodes,
+            stats.in_lists.len(),
+            stats.in_lists.iter().max().unwrap_or(&0)
+        );
+    }
+}
+
+/// Size of a query, see [`SlowParseGuard`]
+#[derive(Debug, Default, PartialEq)]
+struct ParseStats {
+    nodes: usize,         // expressions, nested ones included
+    in_lists: Vec<usize>, // number of values of each IN list
+}
+
+fn parse_stats(sql: &Statement) -> ParseStats {
+    let mut stats = ParseStats::default();
+    let _ = visit_expressions(sql, |expr| {
+        stats.nodes += 1;
+        if let SqlExpr::InList { list, .. } = expr {
+            stats.in_lists.push(list.len());
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    stats
 }
 
 impl TryFrom<&Statement> for Sql {
     type Error = anyhow::Error;
 
     fn try_from(sql: &Statement) -> Result<Self, Self::Error> {
+        let _guard = SlowParseGuard {
+            sql,
+            start: std::time::Instant::now(),
+        };
         match sql {
             // just take case of: query (select ... from ... where ...)
             Statement::Query(q) => {
@@ -117,6 +508,14 @@ impl TryFrom<&Statement> for Sql {
                     projection,
                     group_by: groups,
                     having,
+                    distinct,
+                    top,
+                    lateral_views,
+                    cluster_by,
+                    distribute_by,
+                    sort_by,
+                    named_window,
+                    qualify,
                     ..
                 } = match &q.body.as_ref() {
                     SetExpr::Select(statement) => statement.as_ref(),
@@ -128,6 +527,8 @@ impl TryFrom<&Statement> for Sql {
                 };
 
                 let (source, subquery) = Source(table_with_joins).try_into()?;
+                let (table_fn_fields, table_fn_aliases): (Vec<String>, Vec<String>) =
+                    TableFunctions(&table_with_joins[1..]).try_into()?;
 
                 let mut order_by = Vec::new();
                 for expr in orders {
@@ -137,22 +538,57 @@ impl TryFrom<&Statement> for Sql {
                 // TODO: support Group by all
                 // https://docs.snowflake.com/en/sql-reference/constructs/group-by#label-group-by-all-columns
                 let mut group_by = Vec::new();
-                if let GroupByExpr::Expressions(exprs) = groups {
-                    for expr in exprs {
-                        group_by.push(Group(expr).try_into()?);
+                let mut ignored_clauses = Vec::new();
+                match groups {
+                    GroupByExpr::Expressions(exprs) => {
+                        for expr in exprs {
+                            group_by.push(Group(expr).try_into()?);
+                        }
+                    }
+                    GroupByExpr::All => ignored_clauses.push("GROUP BY ALL".to_string()),
+                }
+                for (clause, present) in [
+                    ("WITH", q.with.is_some()),
+                    ("LIMIT BY", !q.limit_by.is_empty()),
+                    ("FETCH", q.fetch.is_some()),
+                    ("TOP", top.is_some()),
+                    ("LATERAL VIEW", !lateral_views.is_empty()),
+                    ("CLUSTER BY", !cluster_by.is_empty()),
+                    ("DISTRIBUTE BY", !distribute_by.is_empty()),
+                    ("SORT BY", !sort_by.is_empty()),
+                    ("WINDOW", !named_window.is_empty()),
+                    ("QUALIFY", qualify.is_some()),
+                ] {
+                    if present {
+                        ignored_clauses.push(clause.to_string());
                     }
                 }
 
-                let offset = offset.map_or(0, |v| Offset(v).into());
-                let limit = limit.map_or(0, |v| Limit(v).into());
+                // a placeholder is bound by the caller, it isn't a missing limit
+                let offset_param = offset.and_then(|v| get_placeholder(&v.value));
+                let limit_param = limit.and_then(get_placeholder);
+                let offset = match offset {
+                    Some(_) if offset_param.is_some() => 0,
+                    Some(v) => Offset(v).try_into()?,
+                    None => 0,
+                };
+                let limit = match limit {
+                    Some(_) if limit_param.is_some() => 0,
+                    Some(v) => Limit(v).try_into()?,
+                    None => 0,
+                };
 
                 let mut fields: Vec<String> = Projection(projection).try_into()?;
                 let selection = selection.as_ref().cloned();
                 let field_alias: Vec<(String, String)> = Projection(projection).try_into()?;
-                let time_range: Option<(i64, i64)> = Timerange(&selection).try_into()?;
-                let quick_text: Vec<(String, String, SqlOperator)> =
-                    Quicktext(&selection).try_into()?;
-                let where_fields: Vec<String> = Where(&selection).try_into()?;
+                let SelectionFields {
+                    time_filters,
+                    quick_text_filters,
+                    field_names,
+                } = SelectionFields::new(&selection);
+                let time_range = time_range_from_filters(&time_filters?)?;
+                let quick_text = quick_text_from_filters(&quick_text_filters?);
+                let where_fields = field_names?;
 
                 if subquery.is_some() {
                     fields.extend(
@@ -161,12 +597,143 @@ impl TryFrom<&Statement> for Sql {
                 }
 
                 fields.extend(where_fields);
+                // columns produced by UNNEST or table functions are not stream fields
+                fields.retain(|f| !table_fn_aliases.contains(f));
+                fields.extend(table_fn_fields);
+
+                let mut quoted_fields = Vec::new();
+                let mut unquoted_fields = Vec::new();
+                let _ = visit_expressions(sql, |expr| {
+                    if let SqlExpr::Identifier(ident) = expr {
+                        if ident.quote_style.is_some() {
+                            quoted_fields.push(ident.value.to_string());
+                        } else {
+                            unquoted_fields.push(ident.value.to_string());
+                        }
+                    }
+                    ControlFlow::<()>::Continue(())
+                });
+                quoted_fields.sort();
+                quoted_fields.dedup();
+
+                // the visitor also reaches the inner links of `a['x']['y']`, only the outermost
+                // one yields the full path
+                let mut field_paths = Vec::new();
+                let mut inner_links = HashSet::new();
+                let _ = visit_expressions(sql, |expr| {
+                    if !is_subscript(expr) || inner_links.contains(&(expr as *const SqlExpr)) {
+                        return ControlFlow::<()>::Continue(());
+                    }
+                    let mut inner = expr;
+                    while let SqlExpr::Subscript { expr: next, .. }
+                    | SqlExpr::MapAccess { column: next, .. } = inner
+                    {
+                        inner = next.as_ref();
+                        inner_links.insert(inner as *const SqlExpr);
+                    }
+                    if let Some(path) = get_field_path_from_expr(expr) {
+                        field_paths.push(path);
+                    }
+                    ControlFlow::<()>::Continue(())
+                });
+                field_paths.sort();
+                field_paths.dedup();
+
+                // e.g. `where exists (select 1 from other)`, nested subqueries are visited too
+                let mut subquery_sources = Vec::new();
+                let _ = visit_expressions(sql, |expr| {
+                    if let SqlExpr::Exists { subquery, .. }
+                    | SqlExpr::InSubquery { subquery, .. }
+                    | SqlExpr::Subquery(subquery) = expr
+                    {
+                        get_sources_from_query(subquery, &mut subquery_sources);
+                    }
+                    ControlFlow::<()>::Continue(())
+                });
+                subquery_sources.sort();
+                subquery_sources.dedup();
+
+                let mut aggregates = Vec::new();
+                for item in projection.iter() {
+                    let (SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. }) =
+                        item
+                    else {
+                        continue;
+                    };
+                    let _ = visit_expressions(expr, |expr| {
+                        if let SqlExpr::Function(f) = expr {
+                            let name = f.name.to_string().to_lowercase();
+                            if f.over.is_none() && AGGREGATE_FUNCTIONS.contains(&name.as_str()) {
+                                aggregates.push(f.to_string());
+                            }
+                        }
+                        ControlFlow::<()>::Continue(())
+                    });
+                }
+                let aggregates = dedup_keys(aggregates, |v| v);
+
+                let mut fields = normalize_fields(fields, &quoted_fields, &unquoted_fields);
                 fields.sort();
                 fields.dedup();
 
+                // an unquoted alias keeps the casing it was declared with
+                let group_by = dedup_keys(
+                    group_by
+                        .into_iter()
+                        .map(|v| resolve_alias_case(v, &field_alias))
+                        .collect(),
+                    |v| v,
+                );
+                let explicit_order = !order_by.is_empty();
+                let order_by = dedup_keys(
+                    order_by
+                        .into_iter()
+                        .map(|(v, desc)| (resolve_alias_case(v, &field_alias), desc))
+                        .collect(),
+                    |(v, _)| v,
+                );
+
                 let subquery = subquery.map(|subquery| subquery.to_string());
 
-                Ok(Sql {
+                let mut has_wildcard = false;
+                let mut wildcard_qualifiers = Vec::new();
+                for item in projection.iter() {
+                    match item {
+                        SelectItem::Wildcard(_) => has_wildcard = true,
+                        SelectItem::QualifiedWildcard(name, _) => {
+                            has_wildcard = true;
+                            wildcard_qualifiers.push(
+                                name.0
+                                    .iter()
+                                    .map(|v| v.value.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join("."),
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+
+                let mut warnings = Vec::new();
+                if let Some(expr) = selection.as_ref() {
+                    let _ = visit_expressions(expr, |expr| {
+                        if let SqlExpr::BinaryOp { left, .. } = expr {
+                            if let SqlExpr::Value(
+                                Value::SingleQuotedString(s) | Value::DoubleQuotedString(s),
+                            ) = left.as_ref()
+                            {
+                                if is_quoted_timestamp_column(s) {
+                                    warnings.push(format!(
+                                        "'{s}' is quoted as a string, treated as the column {s}"
+                                    ));
+                                }
+                            }
+                        }
+                        ControlFlow::<()>::Continue(())
+                    });
+                }
+
+                let mut parsed = Sql {
                     fields,
                     selection,
                     source,
@@ -175,17 +742,54 @@ impl TryFrom<&Statement> for Sql {
                     having: having.is_some(),
                     offset,
                     limit,
+                    offset_param,
+                    limit_param,
                     time_range,
                     quick_text,
                     field_alias,
                     subquery,
-                })
+                    quoted_fields,
+                    has_wildcard,
+                    wildcard_qualifiers,
+                    warnings,
+                    explicit_order,
+                    distinct: distinct.is_some(),
+                    field_paths,
+                    subquery_sources,
+                    aggregates,
+                    ignored_clauses,
+                };
+                if get_config().common.feature_query_fold_identifier_case {
+                    parsed.fold_identifier_case();
+                }
+                Ok(parsed)
             }
             _ => Err(anyhow::anyhow!("We only support Query at the moment")),
         }
     }
 }
 
+/// A timestamp number whose unit can't be detected from its magnitude
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmbiguousTimestamp(pub i64);
+
+impl std::fmt::Display for AmbiguousTimestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let detected = if self.0 < 0 {
+            "negative timestamps are not supported"
+        } else {
+            "too small to be seconds, the smallest accepted is 1000000000 (2001-09-09)"
+        };
+        write!(
+            f,
+            "Invalid timestamp: {}, {detected}. The unit is detected from the magnitude: seconds in [1e9, 1e12], milliseconds in (1e12, 1e15], microseconds in (1e15, 1e18], nanoseconds above 1e18. Use to_timestamp_seconds, to_timestamp_millis or to_timestamp_micros to state the unit",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for AmbiguousTimestamp {}
+
 impl std::fmt::Display for SqlValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -195,36 +799,74 @@ impl std::fmt::Display for SqlValue {
     }
 }
 
-impl<'a> From<Offset<'a>> for i64 {
-    fn from(offset: Offset) -> Self {
-        match offset.0 {
-            SqlOffset {
-                value: SqlExpr::Value(Value::Number(v, _b)),
-                ..
-            } => {
-                let mut v: i64 = v.parse().unwrap_or(0);
-                if v > MAX_OFFSET {
-                    v = MAX_OFFSET;
-                }
-                v
-            }
-            _ => 0,
-        }
+impl<'a> TryFrom<Offset<'a>> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(offset: Offset) -> Result<Self, Self::Error> {
+        let v = eval_const_number(&offset.0.value, "OFFSET")?;
+        check_not_negative(v, "OFFSET")?;
+        Ok(v.min(MAX_OFFSET))
     }
 }
 
-impl<'a> From<Limit<'a>> for i64 {
-    fn from(l: Limit<'a>) -> Self {
-        match l.0 {
-            SqlExpr::Value(Value::Number(v, _b)) => {
-                let mut v: i64 = v.parse().unwrap_or(0);
-                if v > MAX_LIMIT {
-                    v = MAX_LIMIT;
-                }
-                v
+impl<'a> TryFrom<Limit<'a>> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(l: Limit<'a>) -> Result<Self, Self::Error> {
+        let v = eval_const_number(l.0, "LIMIT")?;
+        check_not_negative(v, "LIMIT")?;
+        Ok(v.min(MAX_LIMIT))
+    }
+}
+
+/// The name of the placeholder of a `LIMIT $1` / `OFFSET $1`
+fn get_placeholder(expr: &SqlExpr) -> Option<String> {
+    match expr {
+        SqlExpr::Value(Value::Placeholder(p)) => Some(p.to_string()),
+        SqlExpr::Nested(e) => get_placeholder(e),
+        _ => None,
+    }
+}
+
+fn check_not_negative(v: i64, clause: &str) -> Result<(), anyhow::Error> {
+    if v < 0 {
+        return Err(anyhow::anyhow!("{clause} must not be negative, got {v}"));
+    }
+    Ok(())
+}
+
+/// Evaluate a constant integer expression used by LIMIT / OFFSET, like: `10`, `10*10`, `(5+5)*2`.
+/// Numbers and results out of the i64 range saturate, the callers clamp them to their max.
+fn eval_const_number(expr: &SqlExpr, clause: &str) -> Result<i64, anyhow::Error> {
+    match expr {
+        SqlExpr::Value(Value::Number(v, _b)) => match v.parse::<i64>() {
+            Ok(v) => Ok(v),
+            Err(_) if !v.is_empty() && v.bytes().all(|c| c.is_ascii_digit()) => Ok(i64::MAX),
+            Err(_) => Err(anyhow::anyhow!("{clause} must be an integer, got {v}")),
+        },
+        SqlExpr::Value(Value::Placeholder(p)) => Err(anyhow::anyhow!(
+            "{clause} only supports a placeholder on its own, got {p} in an expression"
+        )),
+        SqlExpr::Nested(e) => eval_const_number(e, clause),
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => Ok(eval_const_number(expr, clause)?.saturating_neg()),
+        SqlExpr::BinaryOp { left, op, right } => {
+            let left = eval_const_number(left, clause)?;
+            let right = eval_const_number(right, clause)?;
+            match op {
+                BinaryOperator::Plus => Ok(left.saturating_add(right)),
+                BinaryOperator::Minus => Ok(left.saturating_sub(right)),
+                BinaryOperator::Multiply => Ok(left.saturating_mul(right)),
+                _ => Err(anyhow::anyhow!(
+                    "{clause} only supports +, - and * in constant expressions, got {expr}"
+                )),
             }
-            _ => 0,
         }
+        _ => Err(anyhow::anyhow!(
+            "{clause} only supports constant integer expressions, got {expr}"
+        )),
     }
 }
 
@@ -232,11 +874,15 @@ impl<'a> TryFrom<Source<'a>> for (String, Option<Query>) {
     type Error = anyhow::Error;
 
     fn try_from(source: Source<'a>) -> Result<Self, Self::Error> {
-        if source.0.len() != 1 {
+        if source.0.is_empty() {
             return Err(anyhow::anyhow!(
                 "We only support single data source at the moment"
             ));
         }
+        // the other FROM items can only be UNNEST or table functions over the stream
+        if !source.0[1..].iter().all(is_table_function) {
+            return Err(comma_join_error(source.0));
+        }
 
         let table = &source.0[0];
         if !table.joins.is_empty() {
@@ -264,11 +910,14 @@ impl<'a> TryFrom<Source<'a>> for (String, Option<Query>) {
                     }
                 };
 
-                if table_with_joins.len() != 1 {
+                if table_with_joins.is_empty() {
                     return Err(anyhow::anyhow!(
                         "We only support single data source at the moment"
                     ));
                 }
+                if table_with_joins.len() > 1 {
+                    return Err(comma_join_error(table_with_joins));
+                }
 
                 let table = &table_with_joins[0];
                 if !table.joins.is_empty() {
@@ -289,12 +938,92 @@ impl<'a> TryFrom<Source<'a>> for (String, Option<Query>) {
     }
 }
 
-impl<'a> TryFrom<Order<'a>> for (String, bool) {
+impl<'a> TryFrom<TableFunctions<'a>> for (Vec<String>, Vec<String>) {
+    type Error = anyhow::Error;
+
+    /// returns the fields used by the table functions and the aliases they define
+    fn try_from(tables: TableFunctions<'a>) -> Result<Self, Self::Error> {
+        let mut fields = Vec::new();
+        let mut aliases = Vec::new();
+        for table in tables.0.iter() {
+            let (exprs, alias) = match &table.relation {
+                TableFactor::UNNEST {
+                    array_exprs, alias, ..
+                } => (array_exprs.iter().collect::<Vec<_>>(), alias),
+                TableFactor::TableFunction { expr, alias } => (vec![expr], alias),
+                TableFactor::Function { args, alias, .. }
+                | TableFactor::Table {
+                    args: Some(args),
+                    alias,
+                    ..
+                } => (
+                    args.iter()
+                        .filter_map(|arg| match arg {
+                            FunctionArg::Named {
+                                arg: FunctionArgExpr::Expr(expr),
+                                ..
+                            }
+                            | FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Some(expr),
+                            _ => None,
+                        })
+                        .collect(),
+                    alias,
+                ),
+                _ => continue,
+            };
+            for expr in exprs {
+                fields.extend(get_field_name_from_expr(expr)?.unwrap_or_default());
+            }
+            if let Some(alias) = alias {
+                aliases.push(alias.name.value.to_string());
+                aliases.extend(alias.columns.iter().map(|v| v.value.to_string()));
+            }
+        }
+        Ok((fields, aliases))
+    }
+}
+
+/// `from a, b` is an implicit cross join, name the tables so it's clear what was rejected
+fn comma_join_error(from: &[TableWithJoins]) -> anyhow::Error {
+    let tables = from
+        .iter()
+        .filter(|table| !is_table_function(table))
+        .map(|table| match &table.relation {
+            TableFactor::Table { name, .. } => name.to_string(),
+            TableFactor::Derived { .. } => "(subquery)".to_string(),
+            relation => relation.to_string(),
+        })
+        .collect::<Vec<_>>();
+    anyhow::anyhow!(
+        "implicit comma joins are not supported; use explicit JOIN (found tables: {})",
+        tables.join(", ")
+    )
+}
+
+fn is_table_function(table: &TableWithJoins) -> bool {
+    table.joins.is_empty()
+        && matches!(
+            table.relation,
+            TableFactor::UNNEST { .. }
+                | TableFactor::TableFunction { .. }
+                | TableFactor::Function { .. }
+                | TableFactor::Table { args: Some(_), .. }
+        )
+}
+
+impl<'a> TryFrom<Order<'a>> for (String, OrderBy) {
     type Error = anyhow::Error;
 
     fn try_from(order: Order) -> Result<Self, Self::Error> {
         match &order.0.expr {
-            SqlExpr::Identifier(id) => Ok((id.to_string(), !order.0.asc.unwrap_or(true))),
+            SqlExpr::Identifier(id) => {
+                let order_by = if order.0.asc.unwrap_or(true) {
+                    OrderBy::Asc
+                } else {
+                    OrderBy::Desc
+                };
+                Ok((normalize_ident(id), order_by))
+            }
             expr => Err(anyhow::anyhow!(
                 "We only support identifier for order by, got {expr}"
             )),
@@ -307,7 +1036,7 @@ impl<'a> TryFrom<Group<'a>> for String {
 
     fn try_from(g: Group) -> Result<Self, Self::Error> {
         match &g.0 {
-            SqlExpr::Identifier(id) => Ok(id.to_string()),
+            SqlExpr::Identifier(id) => Ok(normalize_ident(id)),
             expr => Err(anyhow::anyhow!(
                 "We only support identifier for group by, got {expr}"
             )),
@@ -355,93 +1084,94 @@ impl<'a> TryFrom<Timerange<'a>> for Option<(i64, i64)> {
     type Error = anyhow::Error;
 
     fn try_from(selection: Timerange<'a>) -> Result<Self, Self::Error> {
-        let mut fields = Vec::new();
-        match selection.0 {
-            Some(expr) => parse_expr_for_field(
-                expr,
-                &SqlOperator::And,
-                &get_config().common.column_timestamp,
-                &mut fields,
-            )?,
-            None => {}
-        }
+        time_range_from_filters(&SelectionFields::new(selection.0).time_filters?)
+    }
+}
 
-        let mut time_min = Vec::new();
-        for (_field, value, op, _operator) in fields.iter() {
-            match op {
-                SqlOperator::Gt => match parse_timestamp(value) {
-                    Ok(v) => time_min.push(v.unwrap_or_default()),
-                    Err(e) => return Err(e),
-                },
-                SqlOperator::Gte => match parse_timestamp(value) {
-                    Ok(v) => time_min.push(v.unwrap_or_default()),
-                    Err(e) => return Err(e),
-                },
-                _ => {}
-            }
+fn time_range_from_filters(fields: &[FieldFilter]) -> Result<Option<(i64, i64)>, anyhow::Error> {
+    // an excluded range doesn't bound the time range, the rows on either side of it match
+    let fields = fields
+        .iter()
+        .filter(|(_, _, _, _, match_type, _)| *match_type != MatchType::Excluded)
+        .collect::<Vec<_>>();
+    let mut time_min = Vec::new();
+    for (_field, value, op, _operator, _match_type, _wrapper) in fields.iter() {
+        match op {
+            SqlOperator::Gt => match parse_timestamp(value) {
+                Ok(v) => time_min.push(v.unwrap_or_default()),
+                Err(e) => return Err(e),
+            },
+            SqlOperator::Gte => match parse_timestamp(value) {
+                Ok(v) => time_min.push(v.unwrap_or_default()),
+                Err(e) => return Err(e),
+            },
+            _ => {}
         }
+    }
 
-        let mut time_max = Vec::new();
-        for (_field, value, op, _operator) in fields.iter() {
-            match op {
-                SqlOperator::Lt => match parse_timestamp(value) {
-                    Ok(v) => time_max.push(v.unwrap_or_default()),
-                    Err(e) => return Err(e),
-                },
-                SqlOperator::Lte => match parse_timestamp(value) {
-                    Ok(v) => time_max.push(v.unwrap_or_default()),
-                    Err(e) => return Err(e),
-                },
-                _ => {}
-            }
+    let mut time_max = Vec::new();
+    for (_field, value, op, _operator, _match_type, _wrapper) in fields.iter() {
+        match op {
+            SqlOperator::Lt => match parse_timestamp(value) {
+                Ok(v) => time_max.push(v.unwrap_or_default()),
+                Err(e) => return Err(e),
+            },
+            SqlOperator::Lte => match parse_timestamp(value) {
+                Ok(v) => time_max.push(v.unwrap_or_default()),
+                Err(e) => return Err(e),
+            },
+            _ => {}
         }
-
-        let time_min = {
-            if !time_min.is_empty() {
-                time_min.iter().min().unwrap().to_owned()
-            } else {
-                0
-            }
-        };
-        let time_max = {
-            if !time_max.is_empty() {
-                time_max.iter().max().unwrap().to_owned()
-            } else {
-                0
-            }
-        };
-        Ok(Some((time_min, time_max)))
     }
+
+    let time_min = {
+        if !time_min.is_empty() {
+            time_min.iter().min().unwrap().to_owned()
+        } else {
+            0
+        }
+    };
+    let time_max = {
+        if !time_max.is_empty() {
+            time_max.iter().max().unwrap().to_owned()
+        } else {
+            0
+        }
+    };
+    Ok(Some((time_min, time_max)))
 }
 
-impl<'a> TryFrom<Quicktext<'a>> for Vec<(String, String, SqlOperator)> {
+impl<'a> TryFrom<Quicktext<'a>> for Vec<(String, String, SqlOperator, MatchType, Option<String>)> {
     type Error = anyhow::Error;
 
     fn try_from(selection: Quicktext<'a>) -> Result<Self, Self::Error> {
-        let mut fields = Vec::new();
-        match selection.0 {
-            Some(expr) => parse_expr_for_field(expr, &SqlOperator::And, "*", &mut fields)?,
-            None => {}
-        }
-        let fields = fields
-            .iter()
-            .filter_map(|(field, value, op, operator)| {
-                if op == &SqlOperator::Eq || op == &SqlOperator::Like {
-                    Some((
-                        field.to_string(),
-                        value.to_owned().to_string(),
-                        operator.to_owned(),
-                    ))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        Ok(fields)
+        Ok(quick_text_from_filters(
+            &SelectionFields::new(selection.0).quick_text_filters?,
+        ))
     }
 }
 
+fn quick_text_from_filters(
+    fields: &[FieldFilter],
+) -> Vec<(String, String, SqlOperator, MatchType, Option<String>)> {
+    fields
+        .iter()
+        .filter_map(|(field, value, op, operator, match_type, wrapper)| {
+            if matches!(op, SqlOperator::Eq | SqlOperator::Like | SqlOperator::ILike) {
+                Some((
+                    field.to_string(),
+                    value.to_owned().to_string(),
+                    operator.to_owned(),
+                    match_type.to_owned(),
+                    wrapper.to_owned(),
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
 impl<'a> TryFrom<Where<'a>> for Vec<String> {
     type Error = anyhow::Error;
 
@@ -460,15 +1190,21 @@ fn parse_timestamp(s: &SqlValue) -> Result<Option<i64>, anyhow::Error> {
         SqlValue::String(s) => {
             let s = s.to_lowercase();
             let mut s = s.as_str();
+            // the multiplier to microseconds of a number passed to the function
+            let mut unit = None;
             if s.starts_with("to_timestamp") {
                 if s.starts_with("to_timestamp_seconds(") {
                     s = s.strip_prefix("to_timestamp_seconds(").unwrap();
+                    unit = Some(1_000_000);
                 } else if s.starts_with("to_timestamp_micros(") {
                     s = s.strip_prefix("to_timestamp_micros(").unwrap();
+                    unit = Some(1);
                 } else if s.starts_with("to_timestamp_millis(") {
                     s = s.strip_prefix("to_timestamp_millis(").unwrap();
+                    unit = Some(1_000);
                 } else if s.starts_with("to_timestamp(") {
                     s = s.strip_prefix("to_timestamp(").unwrap();
+                    unit = Some(1_000_000);
                 } else {
                     return Err(anyhow::anyhow!(
                         "Only support timestamp functions [to_timestamp|to_timestamp_millis|to_timestamp_micros|to_timestamp_seconds]"
@@ -477,6 +1213,12 @@ fn parse_timestamp(s: &SqlValue) -> Result<Option<i64>, anyhow::Error> {
                 s = s.strip_suffix(')').unwrap();
                 s = s.trim_matches(|v| v == '\'' || v == '"');
             }
+            if let (Some(unit), Ok(n)) = (unit, s.parse::<i64>()) {
+                return match n.checked_mul(unit) {
+                    Some(v) => Ok(Some(v)),
+                    None => Err(anyhow::anyhow!("Invalid timestamp: {s} is out of range")),
+                };
+            }
             let v = DateTime::parse_from_rfc3339(s)?;
             Ok(Some(v.timestamp_micros()))
         }
@@ -489,59 +1231,277 @@ fn parse_timestamp(s: &SqlValue) -> Result<Option<i64>, anyhow::Error> {
                 Ok(Some(*n))
             } else if *n > (1e12 as i64) {
                 Ok(Some(*n * 1000))
-            } else if *n > (1e9 as i64) {
+            } else if *n >= (1e9 as i64) {
                 Ok(Some(*n * 1000 * 1000))
             } else {
-                Err(anyhow::anyhow!("Invalid timestamp: {}", n))
+                Err(AmbiguousTimestamp(*n).into())
             }
         }
     }
 }
 
-fn parse_expr_for_field(
+/// A filter on a field found in the WHERE clause:
+/// (field, value, operator, operator combining it with the next filter, match type, wrapper)
+type FieldFilter = (
+    String,
+    SqlValue,
+    SqlOperator,
+    SqlOperator,
+    MatchType,
+    Option<String>,
+);
+
+/// The filters on `field` collected by [`walk_selection`]. Collecting stops at the first error,
+/// like returning it from a walk of its own would.
+struct FieldTarget<'a> {
+    field: &'a str,
+    filters: Vec<FieldFilter>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> FieldTarget<'a> {
+    fn new(field: &'a str) -> Self {
+        FieldTarget {
+            field,
+            filters: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn into_result(self) -> Result<Vec<FieldFilter>, anyhow::Error> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.filters),
+        }
+    }
+}
+
+/// The field names read by the WHERE clause, the same as [`get_field_name_from_expr`] returns
+#[derive(Default)]
+struct FieldNames {
+    names: Vec<String>,
+    error: Option<anyhow::Error>,
+}
+
+impl FieldNames {
+    fn extend_from(&mut self, expr: &SqlExpr) {
+        if self.error.is_some() {
+            return;
+        }
+        match get_field_name_from_expr(expr) {
+            Ok(names) => self.names.extend(names.unwrap_or_default()),
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    fn into_result(self) -> Result<Vec<String>, anyhow::Error> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.names),
+        }
+    }
+}
+
+/// The time range filters, the quick text filters and the field names of the WHERE clause,
+/// collected in a single walk, see [`Timerange`], [`Quicktext`] and [`Where`]
+struct SelectionFields {
+    time_filters: Result<Vec<FieldFilter>, anyhow::Error>,
+    quick_text_filters: Result<Vec<FieldFilter>, anyhow::Error>,
+    field_names: Result<Vec<String>, anyhow::Error>,
+}
+
+impl SelectionFields {
+    fn new(selection: &Option<SqlExpr>) -> Self {
+        let cfg = get_config();
+        let mut time = FieldTarget::new(&cfg.common.column_timestamp);
+        let mut quick_text = FieldTarget::new("*");
+        let mut names = FieldNames::default();
+        if let Some(expr) = selection {
+            walk_selection(
+                expr,
+                &SqlOperator::And,
+                &mut [&mut time, &mut quick_text],
+                &mut names,
+            );
+        }
+        SelectionFields {
+            time_filters: time.into_result(),
+            quick_text_filters: quick_text.into_result(),
+            field_names: names.into_result(),
+        }
+    }
+}
+
+/// Collects the filters of every target and the field names of the expression. The walk only
+/// descends into nested and binary expressions for the targets, the leaves are handled by
+/// [`parse_leaf_for_field`] and their field names by [`get_field_name_from_expr`].
+fn walk_selection(
     expr: &SqlExpr,
     expr_op: &SqlOperator,
-    field: &str,
-    fields: &mut Vec<(String, SqlValue, SqlOperator, SqlOperator)>,
-) -> Result<(), anyhow::Error> {
-    // println!("! parse_expr -> {:?}", expr);
+    targets: &mut [&mut FieldTarget],
+    names: &mut FieldNames,
+) {
     match expr {
-        SqlExpr::Nested(e) => parse_expr_for_field(e, expr_op, field, fields)?,
+        SqlExpr::Nested(e) => walk_selection(e, expr_op, targets, names),
         SqlExpr::BinaryOp { left, op, right } => {
-            let next_op: SqlOperator = op.try_into()?;
-            if let SqlExpr::Identifier(ident) = &**left {
-                let eq = parse_expr_check_field_name(&ident.value, field);
-                if ident.value == field || (eq && next_op == SqlOperator::Eq) {
-                    let val = get_value_from_expr(right);
-                    if matches!(right.as_ref(), SqlExpr::Subquery(_)) {
-                        return Ok(());
-                    }
-                    if val.is_none() {
-                        return Err(anyhow::anyhow!(
-                            "SqlExpr::Identifier: We only support Identifier at the moment"
-                        ));
+            let mut next_op = *expr_op;
+            let mut nested = Vec::with_capacity(targets.len());
+            for target in targets.iter_mut().filter(|t| t.error.is_none()) {
+                match parse_binary_op_for_field(
+                    left,
+                    op,
+                    right,
+                    expr_op,
+                    target.field,
+                    &mut target.filters,
+                ) {
+                    Ok(Some(op)) => {
+                        next_op = op;
+                        nested.push(&mut **target);
                     }
-                    fields.push((ident.value.to_string(), val.unwrap(), next_op, *expr_op));
+                    Ok(None) => {}
+                    Err(e) => target.error = Some(e),
                 }
-            } else {
-                parse_expr_for_field(left, &next_op, field, fields)?;
-                parse_expr_for_field(right, expr_op, field, fields)?;
             }
+            if nested.is_empty() {
+                names.extend_from(expr);
+                return;
+            }
+            walk_selection(left, &next_op, &mut nested, names);
+            walk_selection(right, expr_op, &mut nested, names);
         }
-        SqlExpr::Like {
-            negated,
-            expr,
+        _ => {
+            for target in targets.iter_mut().filter(|t| t.error.is_none()) {
+                if let Err(e) =
+                    parse_leaf_for_field(expr, expr_op, target.field, &mut target.filters)
+                {
+                    target.error = Some(e);
+                }
+            }
+            names.extend_from(expr);
+        }
+    }
+}
+
+/// Collects the filter of `left op right` on `field`, or returns the operator the left side is
+/// combined with when both sides are to be walked instead
+fn parse_binary_op_for_field(
+    left: &SqlExpr,
+    op: &BinaryOperator,
+    right: &SqlExpr,
+    expr_op: &SqlOperator,
+    field: &str,
+    fields: &mut Vec<FieldFilter>,
+) -> Result<Option<SqlOperator>, anyhow::Error> {
+    let next_op: SqlOperator = op.try_into()?;
+    // _timestamp AT TIME ZONE 'America/New_York' >= '2024-06-01 00:00:00'
+    if let SqlExpr::AtTimeZone {
+        timestamp,
+        time_zone,
+    } = left
+    {
+        if matches!(timestamp.as_ref(), SqlExpr::Identifier(ident) if ident.value == field) {
+            let val = parse_expr_at_time_zone(time_zone, right)?;
+            fields.push((
+                field.to_string(),
+                val,
+                next_op,
+                *expr_op,
+                MatchType::Exact,
+                None,
+            ));
+            return Ok(None);
+        }
+    }
+    // date_trunc('day', _timestamp) >= '2024-06-01'
+    if matches!(next_op, SqlOperator::Gt | SqlOperator::Gte) {
+        if let Some(val) = parse_date_trunc_lower_bound(left, right, field) {
+            fields.push((
+                field.to_string(),
+                val,
+                next_op,
+                *expr_op,
+                MatchType::Exact,
+                None,
+            ));
+            return Ok(None);
+        }
+    }
+    let left_name = match left {
+        SqlExpr::Identifier(ident) => Some(ident.value.as_str()),
+        // '_timestamp' >= ..., the timestamp column quoted as a string by mistake
+        SqlExpr::Value(Value::SingleQuotedString(s) | Value::DoubleQuotedString(s))
+            if s == field && is_quoted_timestamp_column(s) =>
+        {
+            Some(s.as_str())
+        }
+        _ => None,
+    };
+    let Some(left_name) = left_name else {
+        return Ok(Some(next_op));
+    };
+    let eq = parse_expr_check_field_name(left_name, field);
+    if left_name == field || (eq && next_op == SqlOperator::Eq) {
+        let val = get_value_from_expr(right);
+        if matches!(right, SqlExpr::Subquery(_)) {
+            return Ok(None);
+        }
+        if val.is_none() {
+            return Err(anyhow::anyhow!(
+                "SqlExpr::Identifier: We only support Identifier at the moment"
+            ));
+        }
+        fields.push((
+            left_name.to_string(),
+            val.unwrap(),
+            next_op,
+            *expr_op,
+            MatchType::Exact,
+            None,
+        ));
+    }
+    Ok(None)
+}
+
+/// Collects the filters on `field` of an expression that isn't nested or binary
+fn parse_leaf_for_field(
+    expr: &SqlExpr,
+    expr_op: &SqlOperator,
+    field: &str,
+    fields: &mut Vec<FieldFilter>,
+) -> Result<(), anyhow::Error> {
+    match expr {
+        SqlExpr::Like {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+        } => {
+            parse_expr_like(negated, expr, pattern, escape_char, expr_op, field, fields)?;
+        }
+        SqlExpr::ILike {
+            negated,
+            expr,
             pattern,
             escape_char,
         } => {
-            parse_expr_like(negated, expr, pattern, escape_char, expr_op, field, fields).unwrap();
+            let start = fields.len();
+            parse_expr_like(negated, expr, pattern, escape_char, expr_op, field, fields)?;
+            // ILIKE matches like lower(field) LIKE lower(pattern)
+            for (_, val, op, _, _, wrapper) in fields[start..].iter_mut() {
+                if let SqlValue::String(s) = val {
+                    *s = s.to_lowercase();
+                }
+                *op = SqlOperator::ILike;
+                *wrapper = Some("lower".to_string());
+            }
         }
         SqlExpr::InList {
             expr,
             list,
             negated,
         } => {
-            parse_expr_in_list(expr, list, negated, expr_op, field, fields).unwrap();
+            parse_expr_in_list(expr, list, negated, expr_op, field, fields)?;
         }
         SqlExpr::Between {
             expr,
@@ -549,7 +1509,7 @@ fn parse_expr_for_field(
             low,
             high,
         } => {
-            let ret = parse_expr_between(expr, negated, low, high, field, fields);
+            let ret = parse_expr_between(expr, negated, low, high, expr_op, field, fields);
             if ret.is_err() {
                 return Err(anyhow::anyhow!("{:?}", ret.err()));
             }
@@ -568,6 +1528,8 @@ fn parse_expr_for_field(
                         SqlValue::String("".to_string()),
                         SqlOperator::Eq,
                         *expr_op,
+                        MatchType::Exact,
+                        None,
                     ));
                 }
             }
@@ -580,6 +1542,8 @@ fn parse_expr_for_field(
                         SqlValue::String("".to_string()),
                         SqlOperator::Eq,
                         *expr_op,
+                        MatchType::Exact,
+                        None,
                     ));
                 }
             }
@@ -590,6 +1554,107 @@ fn parse_expr_for_field(
     Ok(())
 }
 
+/// Converts the literal compared to `field AT TIME ZONE 'zone'` into microseconds, a datetime
+/// without offset is taken as the local time of the zone
+fn parse_expr_at_time_zone(
+    time_zone: &SqlExpr,
+    value: &SqlExpr,
+) -> Result<SqlValue, anyhow::Error> {
+    let tz: Tz = match get_value_from_expr(time_zone) {
+        Some(SqlValue::String(name)) => name
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Unknown time zone: {name}"))?,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "AT TIME ZONE only supports a time zone name, got {time_zone}"
+            ));
+        }
+    };
+    let value = match get_value_from_expr(value) {
+        Some(SqlValue::String(s)) => s,
+        Some(v) => return Ok(v),
+        None => {
+            return Err(anyhow::anyhow!(
+                "AT TIME ZONE only supports a literal to compare, got {value}"
+            ));
+        }
+    };
+    if let Ok(t) = DateTime::parse_from_rfc3339(&value) {
+        return Ok(SqlValue::Number(t.timestamp_micros()));
+    }
+    let local = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(&value, fmt).ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid timestamp: {value}"))?;
+    match tz.from_local_datetime(&local).earliest() {
+        Some(t) => Ok(SqlValue::Number(t.timestamp_micros())),
+        None => Err(anyhow::anyhow!(
+            "Invalid timestamp: {value} does not exist in time zone {tz}"
+        )),
+    }
+}
+
+/// The units `date_trunc` accepts
+const DATE_TRUNC_UNITS: [&str; 10] = [
+    "microsecond",
+    "millisecond",
+    "second",
+    "minute",
+    "hour",
+    "day",
+    "week",
+    "month",
+    "quarter",
+    "year",
+];
+
+/// The value `field` is compared to in `date_trunc('day', field) > value`, a lower bound of the
+/// field too as truncating only moves a timestamp back. A unit `date_trunc` doesn't know or a
+/// value that isn't a timestamp or a date gives no bound.
+fn parse_date_trunc_lower_bound(left: &SqlExpr, right: &SqlExpr, field: &str) -> Option<SqlValue> {
+    let SqlExpr::Function(f) = left else {
+        return None;
+    };
+    let FunctionArguments::List(list) = &f.args else {
+        return None;
+    };
+    if !f.name.to_string().eq_ignore_ascii_case("date_trunc") {
+        return None;
+    }
+    let [
+        FunctionArg::Unnamed(FunctionArgExpr::Expr(unit)),
+        FunctionArg::Unnamed(FunctionArgExpr::Expr(SqlExpr::Identifier(column))),
+    ] = list.args.as_slice()
+    else {
+        return None;
+    };
+    if column.value != field {
+        return None;
+    }
+    let Some(SqlValue::String(unit)) = get_value_from_expr(unit) else {
+        return None;
+    };
+    let unit = unit.to_lowercase();
+    if !DATE_TRUNC_UNITS.contains(&unit.trim_end_matches('s')) {
+        return None;
+    }
+    let value = get_value_from_expr(right)?;
+    if let Ok(Some(_)) = parse_timestamp(&value) {
+        return Some(value);
+    }
+    let SqlValue::String(date) = value else {
+        return None;
+    };
+    let day = NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()?;
+    Some(SqlValue::String(
+        day.and_hms_opt(0, 0, 0)?.and_utc().to_rfc3339(),
+    ))
+}
+
+fn is_quoted_timestamp_column(s: &str) -> bool {
+    s == get_config().common.column_timestamp
+}
+
 fn parse_expr_check_field_name(s: &str, field: &str) -> bool {
     if s == field {
         return true;
@@ -607,10 +1672,10 @@ fn parse_expr_like(
     _negated: &bool,
     expr: &SqlExpr,
     pattern: &SqlExpr,
-    _escape_char: &Option<String>,
+    escape_char: &Option<String>,
     next_op: &SqlOperator,
     field: &str,
-    fields: &mut Vec<(String, SqlValue, SqlOperator, SqlOperator)>,
+    fields: &mut Vec<FieldFilter>,
 ) -> Result<(), anyhow::Error> {
     if let SqlExpr::Identifier(ident) = expr {
         if parse_expr_check_field_name(&ident.value, field) {
@@ -620,24 +1685,86 @@ fn parse_expr_like(
                     "SqlExpr::Like: We only support Identifier at the moment"
                 ));
             }
+            let (val, match_type) = match val.unwrap() {
+                SqlValue::String(s) => {
+                    let escape_char = match escape_char.as_deref() {
+                        Some(v) if v.chars().count() == 1 => v.chars().next().unwrap(),
+                        Some(v) => {
+                            return Err(anyhow::anyhow!(
+                                "SqlExpr::Like: ESCAPE must be a single character, got '{v}'"
+                            ));
+                        }
+                        None => '\\',
+                    };
+                    let (s, match_type) = classify_like_pattern(&s, escape_char);
+                    (SqlValue::String(s), match_type)
+                }
+                v => (v, MatchType::Exact),
+            };
             fields.push((
                 ident.value.to_string(),
-                val.unwrap(),
+                val,
                 SqlOperator::Like,
                 *next_op,
+                match_type,
+                None,
             ));
         }
     }
     Ok(())
 }
 
+/// Classify a LIKE pattern by the placement of its `%` wildcards and return the
+/// unescaped search term, e.g. `abc%` -> (`abc`, Prefix).
+pub fn classify_like_pattern(pattern: &str, escape_char: char) -> (String, MatchType) {
+    // (char, is_wildcard)
+    let mut chars = Vec::with_capacity(pattern.len());
+    let mut escaped = false;
+    for c in pattern.chars() {
+        if escaped {
+            chars.push((c, false));
+            escaped = false;
+        } else if c == escape_char {
+            escaped = true;
+        } else {
+            chars.push((c, c == '%' || c == '_'));
+        }
+    }
+    if escaped {
+        // trailing escape char has nothing to escape, keep it as literal
+        chars.push((escape_char, false));
+    }
+
+    let starts_with_any = matches!(chars.first(), Some(('%', true)));
+    let ends_with_any = chars.len() > 1 && matches!(chars.last(), Some(('%', true)));
+    let start = if starts_with_any { 1 } else { 0 };
+    let end = if ends_with_any {
+        chars.len() - 1
+    } else {
+        chars.len()
+    };
+    let term = &chars[start..end.max(start)];
+    if term.iter().any(|(_, is_wildcard)| *is_wildcard) {
+        return (pattern.to_string(), MatchType::Pattern);
+    }
+
+    let term = term.iter().map(|(c, _)| c).collect::<String>();
+    let match_type = match (starts_with_any, ends_with_any) {
+        (false, false) => MatchType::Exact,
+        (false, true) => MatchType::Prefix,
+        (true, false) => MatchType::Suffix,
+        (true, true) => MatchType::Contains,
+    };
+    (term, match_type)
+}
+
 fn parse_expr_in_list(
     expr: &SqlExpr,
     list: &[SqlExpr],
     negated: &bool,
     next_op: &SqlOperator,
     field: &str,
-    fields: &mut Vec<(String, SqlValue, SqlOperator, SqlOperator)>,
+    fields: &mut Vec<FieldFilter>,
 ) -> Result<(), anyhow::Error> {
     if *negated {
         return Ok(());
@@ -645,10 +1772,23 @@ fn parse_expr_in_list(
     if list.is_empty() {
         return Ok(());
     }
-    let field_name = get_value_from_expr(expr).unwrap().to_string();
+    // e.g. `(a + b) IN (1, 2)`, there is no field to filter on
+    let Some(field_name) = get_value_from_expr(expr) else {
+        return Ok(());
+    };
+    let field_name = field_name.to_string();
     if !parse_expr_check_field_name(&field_name, field) {
         return Ok(());
     }
+    // the filter stays in the selection, only the hints for the search are skipped
+    let max_size = get_config().limit.max_in_list_size;
+    if max_size > 0 && list.len() > max_size {
+        log::warn!(
+            "IN list on {field_name} has {} values, more than ZO_QUERY_MAX_IN_LIST_SIZE {max_size}, skip it as quick text filters",
+            list.len()
+        );
+        return Ok(());
+    }
     let exprs_len = list.len();
     for (i, item) in list.iter().enumerate() {
         let op = if i + 1 == exprs_len {
@@ -657,7 +1797,14 @@ fn parse_expr_in_list(
             SqlOperator::Or
         };
         if let Some(val) = get_value_from_expr(item) {
-            fields.push((field_name.to_string(), val, SqlOperator::Eq, op));
+            fields.push((
+                field_name.to_string(),
+                val,
+                SqlOperator::Eq,
+                op,
+                MatchType::Exact,
+                None,
+            ));
         }
     }
     Ok(())
@@ -668,18 +1815,53 @@ fn parse_expr_between(
     negated: &bool,
     low: &SqlExpr,
     high: &SqlExpr,
+    next_op: &SqlOperator,
     field: &str,
-    fields: &mut Vec<(String, SqlValue, SqlOperator, SqlOperator)>,
+    fields: &mut Vec<FieldFilter>,
 ) -> Result<(), anyhow::Error> {
-    if *negated {
+    let Some(f_name) = get_value_from_expr(expr) else {
         return Ok(());
-    }
-    let f_name = get_value_from_expr(expr).unwrap().to_string();
-    if parse_expr_check_field_name(&f_name, field) {
-        let min = get_value_from_expr(low).unwrap();
-        let max = get_value_from_expr(high).unwrap();
-        fields.push((field.to_string(), min, SqlOperator::Gte, SqlOperator::And));
-        fields.push((field.to_string(), max, SqlOperator::Lt, SqlOperator::And));
+    };
+    if parse_expr_check_field_name(&f_name.to_string(), field) {
+        // bounds that aren't literals, e.g. `a BETWEEN b + 1 AND 10`, can't be filtered on
+        let (Some(min), Some(max)) = (get_value_from_expr(low), get_value_from_expr(high)) else {
+            return Ok(());
+        };
+        if *negated {
+            fields.push((
+                field.to_string(),
+                min,
+                SqlOperator::Lt,
+                SqlOperator::Or,
+                MatchType::Excluded,
+                None,
+            ));
+            fields.push((
+                field.to_string(),
+                max,
+                SqlOperator::Gte,
+                *next_op,
+                MatchType::Excluded,
+                None,
+            ));
+            return Ok(());
+        }
+        fields.push((
+            field.to_string(),
+            min,
+            SqlOperator::Gte,
+            SqlOperator::And,
+            MatchType::Exact,
+            None,
+        ));
+        fields.push((
+            field.to_string(),
+            max,
+            SqlOperator::Lt,
+            SqlOperator::And,
+            MatchType::Exact,
+            None,
+        ));
     }
     Ok(())
 }
@@ -687,13 +1869,15 @@ fn parse_expr_between(
 fn parse_expr_function(
     f: &Function,
     field: &str,
-    fields: &mut Vec<(String, SqlValue, SqlOperator, SqlOperator)>,
+    fields: &mut Vec<FieldFilter>,
 ) -> Result<(), anyhow::Error> {
     let f_name = f.name.to_string().to_lowercase();
     if ![
         "strpos",
         "contains",
         "match",
+        "str_match",
+        "str_match_ignore_case",
         "time_range",
         "to_timestamp",
         "to_timestamp_millis",
@@ -722,6 +1906,40 @@ fn parse_expr_function(
         return Ok(());
     }
 
+    // str_match(lower(message), 'error'), the field is matched as a substring after applying
+    // the wrapper function
+    if f_name == "str_match" || f_name == "str_match_ignore_case" {
+        let (field_name, wrapper) = match args.first().unwrap() {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => unwrap_field_function(expr),
+            _ => return Ok(()),
+        };
+        let Some(field_name) = field_name else {
+            return Ok(());
+        };
+        if !parse_expr_check_field_name(&field_name, field) {
+            return Ok(());
+        }
+        let val = match args.get(1).unwrap() {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))
+            | FunctionArg::Named {
+                arg: FunctionArgExpr::Expr(expr),
+                ..
+            } => get_value_from_expr(expr),
+            _ => None,
+        };
+        if let Some(val) = val {
+            fields.push((
+                field_name,
+                val,
+                SqlOperator::Like,
+                SqlOperator::And,
+                MatchType::Contains,
+                wrapper,
+            ));
+        }
+        return Ok(());
+    }
+
     let nop = SqlOperator::And;
     let next_op = SqlOperator::And;
     let field_name = args.first().unwrap().to_string();
@@ -740,7 +1958,14 @@ fn parse_expr_function(
                             "SqlExpr::Function<Named>: We only support Identifier at the moment"
                         ));
                     }
-                    fields.push((field.to_string(), val.unwrap(), nop, next_op));
+                    fields.push((
+                        field.to_string(),
+                        val.unwrap(),
+                        nop,
+                        next_op,
+                        MatchType::Exact,
+                        None,
+                    ));
                 }
                 _ => return Err(anyhow::anyhow!("We only support String at the moment")),
             },
@@ -752,7 +1977,14 @@ fn parse_expr_function(
                             "SqlExpr::Function<Unnamed>: We only support Identifier at the moment"
                         ));
                     }
-                    fields.push((field.to_string(), val.unwrap(), nop, next_op));
+                    fields.push((
+                        field.to_string(),
+                        val.unwrap(),
+                        nop,
+                        next_op,
+                        MatchType::Exact,
+                        None,
+                    ));
                 }
                 _ => return Err(anyhow::anyhow!("We only support String at the moment")),
             },
@@ -762,10 +1994,43 @@ fn parse_expr_function(
     Ok(())
 }
 
+/// Returns the field name of an identifier, also when wrapped once in lower, upper or trim,
+/// together with the name of the wrapper function
+fn unwrap_field_function(expr: &SqlExpr) -> (Option<String>, Option<String>) {
+    match expr {
+        SqlExpr::Identifier(ident) => (Some(ident.value.to_string()), None),
+        SqlExpr::Function(f) => {
+            let f_name = f.name.to_string().to_lowercase();
+            if !["lower", "upper", "trim"].contains(&f_name.as_str()) {
+                return (None, None);
+            }
+            let FunctionArguments::List(list) = &f.args else {
+                return (None, None);
+            };
+            match list.args.as_slice() {
+                [FunctionArg::Unnamed(FunctionArgExpr::Expr(SqlExpr::Identifier(ident)))] => {
+                    (Some(ident.value.to_string()), Some(f_name))
+                }
+                _ => (None, None),
+            }
+        }
+        SqlExpr::Trim {
+            expr,
+            trim_where: None,
+            trim_what: None,
+            trim_characters: None,
+        } => match expr.as_ref() {
+            SqlExpr::Identifier(ident) => (Some(ident.value.to_string()), Some("trim".to_string())),
+            _ => (None, None),
+        },
+        _ => (None, None),
+    }
+}
+
 fn parse_expr_fun_time_range(
     f: &Function,
     field: &str,
-    fields: &mut Vec<(String, SqlValue, SqlOperator, SqlOperator)>,
+    fields: &mut Vec<FieldFilter>,
 ) -> Result<(), anyhow::Error> {
     let args = match &f.args {
         FunctionArguments::None => return Ok(()),
@@ -822,12 +2087,16 @@ fn parse_expr_fun_time_range(
             vals.get(1).unwrap().to_owned(),
             SqlOperator::Gte,
             next_op,
+            MatchType::Exact,
+            None,
         ));
         fields.push((
             field_name.to_string(),
             vals.get(2).unwrap().to_owned(),
             SqlOperator::Lt,
             next_op,
+            MatchType::Exact,
+            None,
         ));
     }
 
@@ -887,6 +2156,15 @@ fn get_field_name_from_expr(expr: &SqlExpr) -> Result<Option<Vec<String>>, anyho
                     _ => {}
                 }
             }
+            // window functions also read the PARTITION BY and ORDER BY columns
+            if let Some(WindowType::WindowSpec(spec)) = &f.over {
+                let order_by = spec.order_by.iter().map(|order| &order.expr);
+                for expr in spec.partition_by.iter().chain(order_by) {
+                    if let Some(v) = get_field_name_from_expr(expr)? {
+                        fields.extend(v);
+                    }
+                }
+            }
             Ok((!fields.is_empty()).then_some(fields))
         }
         SqlExpr::Nested(expr) => get_field_name_from_expr(expr),
@@ -956,12 +2234,195 @@ fn get_field_name_from_expr(expr: &SqlExpr) -> Result<Option<Vec<String>>, anyho
     }
 }
 
+/// Collects the tables in the FROM and JOIN clauses of the query and of its derived tables
+fn get_sources_from_query(query: &Query, sources: &mut Vec<String>) {
+    fn from_set_expr(body: &SetExpr, sources: &mut Vec<String>) {
+        match body {
+            SetExpr::Select(select) => {
+                for table in select.from.iter() {
+                    from_relation(&table.relation, sources);
+                    for join in table.joins.iter() {
+                        from_relation(&join.relation, sources);
+                    }
+                }
+            }
+            SetExpr::Query(query) => from_set_expr(&query.body, sources),
+            SetExpr::SetOperation { left, right, .. } => {
+                from_set_expr(left, sources);
+                from_set_expr(right, sources);
+            }
+            _ => {}
+        }
+    }
+    fn from_relation(relation: &TableFactor, sources: &mut Vec<String>) {
+        match relation {
+            TableFactor::Table { name, .. } => {
+                if let Some(name) = name.0.first() {
+                    sources.push(name.value.to_string());
+                }
+            }
+            TableFactor::Derived { subquery, .. } => from_set_expr(&subquery.body, sources),
+            _ => {}
+        }
+    }
+    from_set_expr(&query.body, sources);
+}
+
+fn is_subscript(expr: &SqlExpr) -> bool {
+    matches!(expr, SqlExpr::Subscript { .. } | SqlExpr::MapAccess { .. })
+}
+
+/// The `root.key1.key2` path of a subscript chain like `root['key1']['key2']`, up to the first
+/// key that isn't a string or number literal. `None` when the chain doesn't start at a column
+/// or its first key isn't a literal.
+fn get_field_path_from_expr(expr: &SqlExpr) -> Option<String> {
+    let literal_key = |key: &SqlExpr| match key {
+        SqlExpr::Value(Value::SingleQuotedString(s) | Value::DoubleQuotedString(s)) => {
+            Some(s.to_string())
+        }
+        SqlExpr::Value(Value::Number(n, _)) => Some(n.to_string()),
+        _ => None,
+    };
+    // walk from the outermost subscript down to the column, collecting the keys in reverse
+    let mut keys = Vec::new();
+    let mut expr = expr;
+    let root = loop {
+        match expr {
+            SqlExpr::Subscript {
+                expr: inner,
+                subscript,
+            } => {
+                keys.push(match subscript.as_ref() {
+                    Subscript::Index { index } => literal_key(index),
+                    _ => None,
+                });
+                expr = inner;
+            }
+            SqlExpr::MapAccess {
+                column,
+                keys: map_keys,
+            } => {
+                keys.extend(map_keys.iter().rev().map(|k| literal_key(&k.key)));
+                expr = column;
+            }
+            SqlExpr::Identifier(ident) => break normalize_ident(ident),
+            _ => return None,
+        }
+    };
+    let keys = keys.into_iter().rev().map_while(|k| k).collect::<Vec<_>>();
+    (!keys.is_empty()).then(|| format!("{root}.{}", keys.join(".")))
+}
+
+/// Double quotes the identifier with its normalized name
+fn quote_ident(ident: &mut Ident) {
+    ident.value = normalize_ident(ident);
+    ident.quote_style = Some('"');
+}
+
+/// Drops the conjuncts of the top level AND chain that bound the timestamp column, `None` when
+/// the expression has nothing else
+fn strip_time_bounds(expr: SqlExpr, column: &str) -> Option<SqlExpr> {
+    match expr {
+        SqlExpr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => match (
+            strip_time_bounds(*left, column),
+            strip_time_bounds(*right, column),
+        ) {
+            (Some(left), Some(right)) => Some(SqlExpr::BinaryOp {
+                left: Box::new(left),
+                op: BinaryOperator::And,
+                right: Box::new(right),
+            }),
+            (left, right) => left.or(right),
+        },
+        SqlExpr::Nested(expr) => {
+            strip_time_bounds(*expr, column).map(|expr| SqlExpr::Nested(Box::new(expr)))
+        }
+        expr if is_time_bound(&expr, column) => None,
+        expr => Some(expr),
+    }
+}
+
+/// `column > x`, `x <= column`, `column BETWEEN x AND y` and the like
+fn is_time_bound(expr: &SqlExpr, column: &str) -> bool {
+    let is_column =
+        |expr: &SqlExpr| matches!(expr, SqlExpr::Identifier(ident) if ident.value == column);
+    match expr {
+        SqlExpr::BinaryOp {
+            left,
+            op:
+                BinaryOperator::Gt
+                | BinaryOperator::GtEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Eq,
+            right,
+        } => is_column(left) || is_column(right),
+        SqlExpr::Between {
+            expr,
+            negated: false,
+            ..
+        } => is_column(expr),
+        _ => false,
+    }
+}
+
+/// Unquoted identifiers are case-insensitive and canonicalized to lowercase, quoted identifiers
+/// keep their exact case
+fn normalize_ident(ident: &Ident) -> String {
+    if ident.quote_style.is_some() {
+        ident.value.to_string()
+    } else {
+        ident.value.to_lowercase()
+    }
+}
+
+/// Lowercases the fields that are only referenced unquoted. A field referenced both quoted and
+/// unquoted yields both its exact and its lowercase form.
+fn normalize_fields(
+    fields: Vec<String>,
+    quoted_fields: &[String],
+    unquoted_fields: &[String],
+) -> Vec<String> {
+    let mut normalized = Vec::with_capacity(fields.len());
+    for field in fields {
+        if quoted_fields.contains(&field) {
+            if unquoted_fields.contains(&field) {
+                normalized.push(field.to_lowercase());
+            }
+            normalized.push(field);
+        } else {
+            normalized.push(field.to_lowercase());
+        }
+    }
+    normalized
+}
+
+fn resolve_alias_case(key: String, field_alias: &[(String, String)]) -> String {
+    field_alias
+        .iter()
+        .find(|(_, alias)| alias.to_lowercase() == key)
+        .map_or(key, |(_, alias)| alias.to_string())
+}
+
+/// Removes duplicated keys keeping the first occurrence, the order of group by and order by
+/// is significant
+fn dedup_keys<T>(keys: Vec<T>, key: impl Fn(&T) -> &String) -> Vec<T> {
+    let mut seen = HashSet::with_capacity(keys.len());
+    keys.into_iter()
+        .filter(|v| seen.insert(key(v).to_string()))
+        .collect()
+}
+
 fn get_field_name_from_query(query: &Query) -> Result<Option<Vec<String>>, anyhow::Error> {
     let Select {
         from: _table_with_joins,
         selection,
         projection,
-        group_by: _groups,
+        group_by: groups,
         having: _,
         ..
     } = match &query.body.as_ref() {
@@ -974,6 +2435,17 @@ fn get_field_name_from_query(query: &Query) -> Result<Option<Vec<String>>, anyho
     let where_fields: Vec<String> = Where(&selection).try_into()?;
 
     fields.extend(where_fields);
+    // the subquery can group or sort by columns it doesn't select
+    let group_exprs = match groups {
+        GroupByExpr::Expressions(exprs) => exprs.as_slice(),
+        GroupByExpr::All => &[],
+    };
+    let order_exprs = query.order_by.iter().map(|order| &order.expr);
+    for expr in group_exprs.iter().chain(order_exprs) {
+        if let Some(v) = get_field_name_from_expr(expr)? {
+            fields.extend(v);
+        }
+    }
     fields.sort();
     fields.dedup();
 
@@ -1016,7 +2488,7 @@ mod tests {
         assert_eq!(sql.source, table);
         assert_eq!(sql.limit, 5);
         assert_eq!(sql.offset, 10);
-        assert_eq!(sql.order_by, vec![("c".into(), true)]);
+        assert_eq!(sql.order_by, vec![("c".into(), OrderBy::Desc)]);
         assert_eq!(sql.fields, vec!["a", "b", "c"]);
     }
 
@@ -1031,7 +2503,7 @@ mod tests {
         assert_eq!(local_sql.source, table);
         assert_eq!(local_sql.limit, 5);
         assert_eq!(local_sql.offset, 10);
-        assert_eq!(local_sql.order_by, vec![("c".into(), true)]);
+        assert_eq!(local_sql.order_by, vec![("c".into(), OrderBy::Desc)]);
         assert_eq!(local_sql.fields, vec!["a", "b", "c"]);
     }
 
@@ -1074,6 +2546,51 @@ mod tests {
         assert!(ts_str.is_err());
     }
 
+    #[test]
+    fn test_sql_parse_timestamp_boundary() {
+        let samples = [
+            (1_000_000_000, 1_000_000_000_000_000),
+            (1_700_000_000, 1_700_000_000_000_000),
+            (1_000_000_000_000, 1_000_000_000_000_000_000),
+            (1_000_000_000_001, 1_000_000_000_001_000),
+            (1_000_000_000_000_000, 1_000_000_000_000_000_000),
+            (1_000_000_000_000_001, 1_000_000_000_000_001),
+            (1_000_000_000_000_000_000, 1_000_000_000_000_000_000),
+            (1_000_000_000_000_001_000, 1_000_000_000_000_001),
+        ];
+        for (n, micros) in samples {
+            assert_eq!(
+                parse_timestamp(&SqlValue::Number(n)).unwrap(),
+                Some(micros),
+                "{n}"
+            );
+        }
+        assert_eq!(parse_timestamp(&SqlValue::Number(0)).unwrap(), None);
+
+        for n in [999_999_999, 1, -1_700_000_000] {
+            let err = parse_timestamp(&SqlValue::Number(n)).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<AmbiguousTimestamp>(),
+                Some(&AmbiguousTimestamp(n))
+            );
+        }
+
+        let samples = [
+            ("to_timestamp_seconds(1700000000)", 1_700_000_000_000_000),
+            ("to_timestamp(1700000000)", 1_700_000_000_000_000),
+            ("to_timestamp_millis(1700000000)", 1_700_000_000_000),
+            ("to_timestamp_micros(1700000000)", 1_700_000_000),
+            ("to_timestamp_seconds(10)", 10_000_000),
+        ];
+        for (s, micros) in samples {
+            assert_eq!(
+                parse_timestamp(&SqlValue::String(s.to_string())).unwrap(),
+                Some(micros),
+                "{s}"
+            );
+        }
+    }
+
     #[test]
     fn test_sql_parse_timerange() {
         let samples = [
@@ -1171,4 +2688,1048 @@ mod tests {
             assert_eq!(actual, fields);
         }
     }
+
+    #[test]
+    fn test_sql_parse_like_match_type() {
+        let samples = [
+            (
+                "select * from tbl where a like 'abc%'",
+                "abc",
+                MatchType::Prefix,
+            ),
+            (
+                "select * from tbl where a like '%abc'",
+                "abc",
+                MatchType::Suffix,
+            ),
+            (
+                "select * from tbl where a like '%abc%'",
+                "abc",
+                MatchType::Contains,
+            ),
+            (
+                "select * from tbl where a like 'abc'",
+                "abc",
+                MatchType::Exact,
+            ),
+            (
+                r"select * from tbl where a like '100\%'",
+                "100%",
+                MatchType::Exact,
+            ),
+            (
+                "select * from tbl where a like '%100!%' escape '!'",
+                "100%",
+                MatchType::Suffix,
+            ),
+            (
+                "select * from tbl where a like 'a%c'",
+                "a%c",
+                MatchType::Pattern,
+            ),
+        ];
+        for (sql, value, match_type) in samples {
+            let quick_text = Sql::new(sql).unwrap().quick_text;
+            assert_eq!(quick_text.len(), 1);
+            assert_eq!(quick_text[0].1, value);
+            assert_eq!(quick_text[0].3, match_type);
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_limit_expr() {
+        let samples = [
+            ("select * from tbl limit 10", 10, 0),
+            ("select * from tbl limit 10*10", 100, 0),
+            ("select * from tbl limit (5+5)*2 offset 2*3", 20, 6),
+            ("select * from tbl limit 10-1 offset 1+1", 9, 2),
+            ("select * from tbl limit 1000*1000", MAX_LIMIT, 0),
+            // out of the i64 range
+            ("select * from tbl limit 99999999999999999999", MAX_LIMIT, 0),
+            (
+                "select * from tbl limit 10 offset 99999999999999999999*2",
+                10,
+                MAX_OFFSET,
+            ),
+            (
+                "select * from tbl limit 9223372036854775807+1",
+                MAX_LIMIT,
+                0,
+            ),
+        ];
+        for (sql, limit, offset) in samples {
+            let sql = Sql::new(sql).unwrap();
+            assert_eq!(sql.limit, limit);
+            assert_eq!(sql.offset, offset);
+        }
+
+        let samples = [
+            ("select * from tbl limit $1", Some("$1"), None),
+            ("select * from tbl limit ($1)", Some("$1"), None),
+            ("select * from tbl limit 10 offset $1", None, Some("$1")),
+            (
+                "select * from tbl limit $1 offset $2",
+                Some("$1"),
+                Some("$2"),
+            ),
+            ("select * from tbl limit ? offset 5", Some("?"), None),
+            ("select * from tbl limit 10", None, None),
+        ];
+        for (sql, limit_param, offset_param) in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert_eq!(parsed.limit_param.as_deref(), limit_param, "{sql}");
+            assert_eq!(parsed.offset_param.as_deref(), offset_param, "{sql}");
+            let plan = parsed.explain();
+            assert_eq!(plan.limit_param.as_deref(), limit_param, "{sql}");
+            assert_eq!(plan.offset_param.as_deref(), offset_param, "{sql}");
+        }
+
+        let samples = [
+            "select * from tbl limit $1*10",
+            "select * from tbl limit 10 offset $1+1",
+            "select * from tbl limit a",
+            "select * from tbl limit 10/2",
+            "select * from tbl offset abs(10)",
+            "select * from tbl limit 'ten'",
+            "select * from tbl limit 1.5",
+            "select * from tbl limit -1",
+            "select * from tbl limit 10 offset 1-2",
+        ];
+        for sql in samples {
+            assert!(Sql::new(sql).is_err(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_subquery_fields() {
+        let samples = [
+            (
+                "select * from (select a from tbl order by b limit 1)",
+                vec!["a", "b"],
+            ),
+            (
+                "select * from (select a, count(*) from tbl group by a, c)",
+                vec!["a", "c"],
+            ),
+            (
+                "select * from (select a from tbl where c=1 order by abs(b) desc)",
+                vec!["a", "b", "c"],
+            ),
+            (
+                "select x FROM tbl where y = (select a from tbl2 order by b limit 1)",
+                vec!["a", "b", "x", "y"],
+            ),
+            (
+                "select x FROM tbl where y in (select a from tbl2 group by a, c order by b)",
+                vec!["a", "b", "c", "x", "y"],
+            ),
+        ];
+        for (sql, fields) in samples {
+            let mut parsed = Sql::new(sql).unwrap().fields;
+            parsed.sort();
+            assert_eq!(parsed, fields, "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_fields_case() {
+        let samples = [
+            (
+                "select Level, level, LEVEL from tbl",
+                vec!["level"],
+                vec![],
+                vec![],
+            ),
+            (
+                r#"select "Level", level from tbl group by "Level", level order by level"#,
+                vec!["Level", "level"],
+                vec!["Level", "level"],
+                vec![("level", OrderBy::Asc)],
+            ),
+            (
+                r#"select "Level" from tbl where Level = 'error'"#,
+                vec!["Level", "level"],
+                vec![],
+                vec![],
+            ),
+            (
+                "select Host, count(*) as Cnt from tbl group by Host, host order by Cnt desc, CNT",
+                vec!["host"],
+                vec!["host"],
+                vec![("Cnt", OrderBy::Desc)],
+            ),
+        ];
+        for (sql, fields, group_by, order_by) in samples {
+            let sql = Sql::new(sql).unwrap();
+            assert_eq!(sql.fields, fields);
+            assert_eq!(sql.group_by, group_by);
+            assert_eq!(
+                sql.order_by,
+                order_by
+                    .into_iter()
+                    .map(|(f, desc)| (f.to_string(), desc))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_order_by_or_default() {
+        let default = ("_timestamp", OrderBy::Desc);
+        let samples = [
+            (
+                "select * from tbl",
+                false,
+                vec![("_timestamp", OrderBy::Desc)],
+            ),
+            (
+                "select * from tbl order by _timestamp",
+                true,
+                vec![("_timestamp", OrderBy::Asc)],
+            ),
+            (
+                "select _timestamp as ts from tbl order by ts desc",
+                true,
+                vec![("ts", OrderBy::Desc)],
+            ),
+            (
+                "select histogram(_timestamp) as zo_sql_key, count(*) as zo_sql_num from tbl group by zo_sql_key order by zo_sql_key",
+                true,
+                vec![("zo_sql_key", OrderBy::Asc)],
+            ),
+        ];
+        for (sql, explicit_order, order_by) in samples {
+            let sql = Sql::new(sql).unwrap();
+            assert_eq!(sql.explicit_order, explicit_order);
+            assert_eq!(
+                sql.order_by_or_default(default),
+                order_by
+                    .into_iter()
+                    .map(|(f, o)| (f.to_string(), o))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_comma_join() {
+        let samples = [
+            ("select * from a, b", "a, b"),
+            ("select * from a, \"b\", c where a.x = c.x", "a, \"b\", c"),
+            ("select * from (select * from a, b)", "a, b"),
+            ("select * from a, unnest(a.tags) as t(tag), b", "a, b"),
+        ];
+        for (sql, tables) in samples {
+            let err = Sql::new(sql).unwrap_err().to_string();
+            assert_eq!(
+                err,
+                format!(
+                    "implicit comma joins are not supported; use explicit JOIN (found tables: {tables})"
+                ),
+                "{sql}"
+            );
+        }
+        // an explicit join keeps its own message
+        let err = Sql::new("select * from a join b on a.x = b.x")
+            .unwrap_err()
+            .to_string();
+        assert!(!err.contains("comma"), "{err}");
+    }
+
+    #[test]
+    fn test_sql_parse_distinct() {
+        let samples = [
+            ("select host from tbl", false),
+            ("select distinct host from tbl", true),
+            (
+                "select distinct host, level from tbl where level = 'error'",
+                true,
+            ),
+            ("select count(distinct host) from tbl", false),
+        ];
+        for (sql, distinct) in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert_eq!(parsed.distinct, distinct, "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_non_field_in_between() {
+        let samples = [
+            "select * from tbl where (a+b) in (1,2)",
+            "select * from tbl where a+b in (1,2) and c = 'x'",
+            "select * from tbl where (a+b) between 1 and 2",
+            "select * from tbl where a between b+1 and 10",
+            "select * from tbl where a between 1 and abs(b)-1",
+            "select * from tbl where cast(a as int) in (1,2) or (a*2) between 3 and 4",
+        ];
+        for sql in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert!(
+                parsed.quick_text.iter().all(|(field, ..)| field == "c"),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_not_between() {
+        let expr = Parser::parse_sql(
+            &sqlparser::dialect::GenericDialect {},
+            "select * from tbl where _timestamp NOT BETWEEN 1 AND 2 AND a = 'x'",
+        )
+        .unwrap();
+        let Statement::Query(query) = &expr[0] else {
+            unreachable!()
+        };
+        let SetExpr::Select(select) = query.body.as_ref() else {
+            unreachable!()
+        };
+        let filters = SelectionFields::new(&select.selection)
+            .time_filters
+            .unwrap();
+        let filters = filters
+            .iter()
+            .map(|(field, value, op, next_op, match_type, _)| {
+                (
+                    field.as_str(),
+                    value.to_string(),
+                    *op,
+                    *next_op,
+                    *match_type,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            filters,
+            [
+                (
+                    "_timestamp",
+                    "1".to_string(),
+                    SqlOperator::Lt,
+                    SqlOperator::Or,
+                    MatchType::Excluded
+                ),
+                (
+                    "_timestamp",
+                    "2".to_string(),
+                    SqlOperator::Gte,
+                    SqlOperator::And,
+                    MatchType::Excluded
+                ),
+            ]
+        );
+
+        let samples = [
+            (
+                "select * from tbl where _timestamp NOT BETWEEN 1 AND 2",
+                (0, 0),
+            ),
+            (
+                "select * from tbl where _timestamp >= 1717200000000000 AND _timestamp NOT BETWEEN 1717210000000000 AND 1717220000000000",
+                (1717200000000000, 0),
+            ),
+            (
+                "select * from tbl where _timestamp BETWEEN 1717200000000000 AND 1717286400000000",
+                (1717200000000000, 1717286400000000),
+            ),
+        ];
+        for (sql, time_range) in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert_eq!(parsed.time_range, Some(time_range), "{sql}");
+            assert!(parsed.fields.contains(&"_timestamp".to_string()), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_fold_identifier_case() {
+        let sql = r#"select Host, "Level", count(*) as "Total" from tbl where "Code" = 'E1' group by Host, "Level" order by "Total" desc"#;
+        let mut parsed = Sql::new(sql).unwrap();
+        assert_eq!(parsed.fields, vec!["Code", "Level", "host"]);
+        assert_eq!(parsed.group_by, vec!["host", "Level"]);
+        assert_eq!(parsed.order_by, vec![("Total".to_string(), OrderBy::Desc)]);
+        assert_eq!(
+            parsed.field_alias,
+            vec![("count(*)".to_string(), "Total".to_string())]
+        );
+
+        parsed.fold_identifier_case();
+        assert_eq!(parsed.fields, vec!["code", "host", "level"]);
+        assert_eq!(parsed.quoted_fields, vec!["code", "level", "total"]);
+        assert_eq!(parsed.group_by, vec!["host", "level"]);
+        assert_eq!(parsed.order_by, vec![("total".to_string(), OrderBy::Desc)]);
+        assert_eq!(
+            parsed.field_alias,
+            vec![("count(*)".to_string(), "total".to_string())]
+        );
+        assert_eq!(parsed.quick_text[0].0, "code");
+        // `select host` and `SELECT Host` agree
+        let mut lower = Sql::new(&sql.to_lowercase()).unwrap();
+        lower.fold_identifier_case();
+        assert_eq!(lower.fields, parsed.fields);
+    }
+
+    #[test]
+    fn test_sql_fold_stream_names() {
+        let samples = [
+            ("select * from MyStream", "MyStream", vec![]),
+            (r#"select * from "MyStream""#, "MyStream", vec![]),
+            ("select * from (select * from MyStream)", "MyStream", vec![]),
+            (
+                "select * from MyStream where exists (select 1 from Other) and a in (select a from OTHER)",
+                "MyStream",
+                vec!["OTHER", "Other"],
+            ),
+        ];
+        for (sql, source, subquery_sources) in samples {
+            let mut parsed = Sql::new(sql).unwrap();
+            // as written unless folding is enabled
+            assert_eq!(parsed.source, source, "{sql}");
+            assert_eq!(parsed.subquery_sources, subquery_sources, "{sql}");
+
+            parsed.fold_identifier_case();
+            assert_eq!(parsed.source, "mystream", "{sql}");
+            let folded = if subquery_sources.is_empty() {
+                vec![]
+            } else {
+                vec!["other"]
+            };
+            assert_eq!(parsed.subquery_sources, folded, "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_time_ranges() {
+        const T: i64 = 1_700_000_000_000_000;
+        // a query filtering on the bounds that aren't 0
+        let query = |&(start, end): &(i64, i64)| {
+            let mut filters = Vec::new();
+            if start != 0 {
+                filters.push(format!("_timestamp >= {}", T + start));
+            }
+            if end != 0 {
+                filters.push(format!("_timestamp < {}", T + end));
+            }
+            let sql = if filters.is_empty() {
+                "select * from t".to_string()
+            } else {
+                format!("select * from t where {}", filters.join(" and "))
+            };
+            Sql::new(&sql).unwrap()
+        };
+        let shift = |range: Option<(i64, i64)>| {
+            range.map(|(start, end)| {
+                let shift = |v: i64| if v == 0 { 0 } else { T + v };
+                (shift(start), shift(end))
+            })
+        };
+        let samples = [
+            // (query time ranges, union, intersection), 0 is unbounded
+            (vec![], None, None),
+            (vec![(10, 20)], Some((10, 20)), Some((10, 20))),
+            (vec![(10, 20), (15, 30)], Some((10, 30)), Some((15, 20))),
+            // no time filter widens the union to unbounded
+            (vec![(10, 20), (0, 0)], Some((0, 0)), Some((10, 20))),
+            (vec![(10, 20), (15, 0)], Some((10, 0)), Some((15, 20))),
+            (vec![(0, 20), (0, 30)], Some((0, 30)), Some((0, 20))),
+            // disjoint
+            (vec![(10, 20), (30, 40)], Some((10, 40)), None),
+        ];
+        for (ranges, union, intersection) in samples {
+            let sqls = ranges.iter().map(query).collect::<Vec<_>>();
+            assert_eq!(union_time_ranges(&sqls), shift(union), "{ranges:?}");
+            assert_eq!(
+                intersect_time_ranges(&sqls),
+                shift(intersection),
+                "{ranges:?}"
+            );
+        }
+
+        // a missing time range is unbounded as well
+        let sql = query(&(10, 20));
+        let sqls = [
+            sql.clone(),
+            Sql {
+                time_range: None,
+                ..sql
+            },
+        ];
+        assert_eq!(union_time_ranges(&sqls), Some((0, 0)));
+        assert_eq!(intersect_time_ranges(&sqls), shift(Some((10, 20))));
+    }
+
+    #[test]
+    fn test_sql_explain() {
+        let sql = "select host, count(*) as cnt, sum(took) / count(*) as avg_took, row_number() over (order by host) from tbl where _timestamp >= 1717200000000000 and _timestamp < 1717286400000000 and host like '%web%' group by host order by cnt desc limit 10";
+        let plan = Sql::new(sql).unwrap().explain();
+        assert_eq!(plan.source, "tbl");
+        assert_eq!(plan.time_range, Some((1717200000000000, 1717286400000000)));
+        assert_eq!(plan.fields, vec!["_timestamp", "host", "took"]);
+        assert_eq!(plan.aggregates, vec!["count(*)", "sum(took)"]);
+        assert_eq!(plan.group_by, vec!["host"]);
+        assert_eq!(plan.limit, 10);
+        assert_eq!(plan.quick_text.len(), 1);
+        assert!(plan.ignored_clauses.is_empty());
+
+        let samples = [
+            ("select * from tbl group by all", vec!["GROUP BY ALL"]),
+            (
+                "with t as (select * from tbl) select * from tbl qualify a > 1",
+                vec!["WITH", "QUALIFY"],
+            ),
+            (
+                "select * from tbl order by a fetch first 5 rows only",
+                vec!["FETCH"],
+            ),
+        ];
+        for (sql, ignored_clauses) in samples {
+            let plan = Sql::new(sql).unwrap().explain();
+            assert_eq!(plan.ignored_clauses, ignored_clauses, "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_where_clause_string() {
+        let samples = [
+            (
+                "select * from tbl where Level = 'error' and \"Host\"='a' AND _timestamp >= 1717200000000000 and _timestamp<1717286400000000",
+                true,
+                Some(r#""level" = 'error' AND "Host" = 'a'"#),
+            ),
+            (
+                "select * from tbl where Level = 'error' and _timestamp >= 1717200000000000",
+                false,
+                Some(r#""level" = 'error' AND "_timestamp" >= 1717200000000000"#),
+            ),
+            (
+                "select * from tbl where (a=1 or `B`>2) and c in (1,2) and t.d=3 and msg like '%it''s%'",
+                true,
+                Some(
+                    r#"("a" = 1 OR "B" > 2) AND "c" IN (1, 2) AND "t"."d" = 3 AND "msg" LIKE '%it''s%'"#,
+                ),
+            ),
+            (
+                "select * from tbl where (_timestamp >= 1717200000000000 and level = 'error')",
+                true,
+                Some(r#"("level" = 'error')"#),
+            ),
+            (
+                "select * from tbl where _timestamp >= 1717200000000000 or level = 'error'",
+                true,
+                Some(r#""_timestamp" >= 1717200000000000 OR "level" = 'error'"#),
+            ),
+            (
+                "select * from tbl where _timestamp between 1717200000000000 and 1717286400000000",
+                true,
+                None,
+            ),
+            ("select * from tbl", false, None),
+        ];
+        let dialect = sqlparser::dialect::GenericDialect {};
+        for (sql, strip_time_range, expected) in samples {
+            let predicate = Sql::new(sql).unwrap().where_clause_string(strip_time_range);
+            assert_eq!(predicate.as_deref(), expected, "{sql}");
+            let Some(predicate) = predicate else {
+                continue;
+            };
+            // the output re-parses to the same expression and renders the same again
+            let reparsed = Parser::new(&dialect)
+                .try_with_sql(&predicate)
+                .unwrap()
+                .parse_expr()
+                .unwrap();
+            assert_eq!(reparsed.to_string(), predicate, "{sql}");
+            let sql = format!("select * from tbl where {predicate}");
+            assert_eq!(
+                Sql::new(&sql)
+                    .unwrap()
+                    .where_clause_string(false)
+                    .as_deref(),
+                Some(predicate.as_str()),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_ilike_quick_text() {
+        let samples = [
+            (
+                "select * from tbl where body ILIKE '%error%'",
+                "error",
+                MatchType::Contains,
+            ),
+            (
+                "select * from tbl where body ilike 'Timeout%'",
+                "timeout",
+                MatchType::Prefix,
+            ),
+            (
+                "select * from tbl where body ilike 'ERROR'",
+                "error",
+                MatchType::Exact,
+            ),
+        ];
+        for (sql, value, match_type) in samples {
+            let quick_text = Sql::new(sql).unwrap().quick_text;
+            assert_eq!(
+                quick_text,
+                vec![(
+                    "body".to_string(),
+                    value.to_string(),
+                    SqlOperator::And,
+                    match_type,
+                    Some("lower".to_string())
+                )],
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_str_match_wrapper() {
+        let samples = [
+            (
+                "select * from tbl where str_match(lower(message), 'error')",
+                "message",
+                Some("lower"),
+            ),
+            (
+                "select * from tbl where str_match(UPPER(message), 'error')",
+                "message",
+                Some("upper"),
+            ),
+            (
+                "select * from tbl where str_match(trim(message), 'error')",
+                "message",
+                Some("trim"),
+            ),
+            (
+                "select * from tbl where str_match(message, 'error')",
+                "message",
+                None,
+            ),
+            (
+                "select * from tbl where str_match_ignore_case(message, 'error') and a = 1",
+                "message",
+                None,
+            ),
+        ];
+        for (sql, field, wrapper) in samples {
+            let quick_text = Sql::new(sql).unwrap().quick_text;
+            assert_eq!(
+                quick_text[0],
+                (
+                    field.to_string(),
+                    "error".to_string(),
+                    SqlOperator::And,
+                    MatchType::Contains,
+                    wrapper.map(|v| v.to_string())
+                ),
+                "{sql}"
+            );
+        }
+
+        // nested or unknown wrappers are not unwrapped
+        for sql in [
+            "select * from tbl where str_match(lower(trim(message)), 'error')",
+            "select * from tbl where str_match(reverse(message), 'error')",
+        ] {
+            assert!(Sql::new(sql).unwrap().quick_text.is_empty(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_fields() {
+        let schema: HashSet<String> = ["_timestamp", "message", "status", "Host"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let samples = [
+            ("select * from tbl", vec![]),
+            ("select message, status from tbl where status = 200", vec![]),
+            (
+                "select count(*) as cnt, status from tbl group by status",
+                vec![],
+            ),
+            ("select MESSAGE, host from tbl", vec![]),
+            (r#"select "Host" from tbl"#, vec![]),
+            (r#"select "host" from tbl"#, vec!["host"]),
+            (
+                "select mesage from tbl where stauts = 200",
+                vec!["mesage", "stauts"],
+            ),
+            ("select msg from (select message as msg from tbl)", vec![]),
+            (
+                "select message from tbl where str_match(mesage, 'error')",
+                vec!["mesage"],
+            ),
+        ];
+        for (sql, unknown) in samples {
+            let ret = Sql::new(sql).unwrap().validate_fields(&schema);
+            if unknown.is_empty() {
+                assert!(ret.is_ok(), "{sql}");
+            } else {
+                assert_eq!(ret.unwrap_err(), unknown, "{sql}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_wildcard() {
+        let samples = [
+            ("select * from tbl", true, vec![]),
+            ("select count(*) from tbl", false, vec![]),
+            ("select a, b from tbl", false, vec![]),
+            ("select tbl.* from tbl", true, vec!["tbl"]),
+            (r#"select "t1".*, a from tbl as t1"#, true, vec!["t1"]),
+            ("select *, count(*) over () from tbl", true, vec![]),
+        ];
+        for (sql, has_wildcard, qualifiers) in samples {
+            let sql = Sql::new(sql).unwrap();
+            assert_eq!(sql.has_wildcard, has_wildcard);
+            assert_eq!(sql.wildcard_qualifiers, qualifiers);
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_timerange_quoted_column() {
+        let samples = [
+            (
+                "select * from tbl where '_timestamp' >= '2024-06-01T00:00:00Z'",
+                (1717200000000000, 0),
+                1,
+            ),
+            (
+                r#"select * from tbl where "_timestamp" >= '2024-06-01T00:00:00Z'"#,
+                (1717200000000000, 0),
+                0,
+            ),
+            (
+                "select * from tbl where '_timestamp' >= 1717200000000000 and '_timestamp' < 1717286400000000",
+                (1717200000000000, 1717286400000000),
+                2,
+            ),
+            (
+                "select * from tbl where 'abc' >= 1717200000000000",
+                (0, 0),
+                0,
+            ),
+        ];
+        for (sql, time_range, warnings) in samples {
+            let sql = Sql::new(sql).unwrap();
+            assert_eq!(sql.time_range, Some(time_range));
+            assert_eq!(sql.warnings.len(), warnings);
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_timerange_at_time_zone() {
+        let samples = [
+            (
+                "select * from tbl where _timestamp AT TIME ZONE 'America/New_York' >= '2024-06-01 00:00:00'",
+                (1717214400000000, 0),
+            ),
+            (
+                "select * from tbl where _timestamp AT TIME ZONE 'Asia/Kolkata' >= '2024-06-01 05:30:00' and _timestamp AT TIME ZONE 'Asia/Kolkata' < '2024-06-02T05:30:00'",
+                (1717200000000000, 1717286400000000),
+            ),
+            (
+                "select * from tbl where _timestamp AT TIME ZONE 'Asia/Kolkata' >= '2024-06-01T00:00:00Z'",
+                (1717200000000000, 0),
+            ),
+        ];
+        for (sql, time_range) in samples {
+            let sql = Sql::new(sql).unwrap();
+            assert_eq!(sql.time_range, Some(time_range));
+        }
+
+        let sql = "select * from tbl where _timestamp AT TIME ZONE 'Mars/Olympus' >= '2024-06-01 00:00:00'";
+        let err = Sql::new(sql).unwrap_err().to_string();
+        assert!(err.contains("Unknown time zone: Mars/Olympus"), "{err}");
+    }
+
+    #[test]
+    fn test_sql_parse_in_list_size() {
+        let max_size = get_config().limit.max_in_list_size;
+        for (len, quick_text_len) in [(3, 3), (max_size, max_size), (max_size + 1, 0)] {
+            let values = (0..len).map(|v| v.to_string()).collect::<Vec<_>>();
+            let sql = format!(
+                "select a from tbl where status in ({}) and b = 'x'",
+                values.join(",")
+            );
+            let parsed = Sql::new(&sql).unwrap();
+            let status = parsed
+                .quick_text
+                .iter()
+                .filter(|(field, ..)| field == "status")
+                .count();
+            assert_eq!(status, quick_text_len, "{len}");
+            // the other filters and the fields aren't affected
+            assert!(parsed.quick_text.iter().any(|(field, ..)| field == "b"));
+            assert_eq!(parsed.fields, vec!["a", "b", "status"], "{len}");
+            assert!(parsed.selection.is_some());
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_timerange_date_trunc() {
+        let samples = [
+            (
+                "select * from tbl where date_trunc('day', _timestamp) >= '2024-06-01'",
+                (1717200000000000, 0),
+            ),
+            (
+                "select * from tbl where date_trunc('HOUR', _timestamp) > '2024-06-01T00:00:00Z' and _timestamp < '2024-06-02T00:00:00Z'",
+                (1717200000000000, 1717286400000000),
+            ),
+            (
+                "select * from tbl where DATE_TRUNC('days', _timestamp) >= 1717200000000000",
+                (1717200000000000, 0),
+            ),
+            // only lower bounds are derived
+            (
+                "select * from tbl where date_trunc('day', _timestamp) < '2024-06-01'",
+                (0, 0),
+            ),
+            // unknown units and values that aren't dates are ignored
+            (
+                "select * from tbl where date_trunc('fortnight', _timestamp) >= '2024-06-01'",
+                (0, 0),
+            ),
+            (
+                "select * from tbl where date_trunc('day', _timestamp) >= 'yesterday'",
+                (0, 0),
+            ),
+            (
+                "select * from tbl where date_trunc('day', other) >= '2024-06-01'",
+                (0, 0),
+            ),
+            (
+                "select * from tbl where extract(hour from _timestamp) = 3",
+                (0, 0),
+            ),
+        ];
+        for (sql, time_range) in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert_eq!(parsed.time_range, Some(time_range), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_unnest() {
+        let samples = [
+            ("select v from logs, unnest(tags) as t(v)", vec!["tags"]),
+            (
+                "select v from logs, unnest(tags) as t(v) where v = 'a' and code = 200",
+                vec!["code", "tags"],
+            ),
+            ("select tags from logs, unnest(tags)", vec!["tags"]),
+            ("select a from logs, unnest(tags) as t", vec!["a", "tags"]),
+        ];
+        for (sql, fields) in samples {
+            let sql = Sql::new(sql).unwrap();
+            assert_eq!(sql.source, "logs");
+            assert_eq!(sql.fields, fields);
+        }
+
+        assert!(Sql::new("select * from logs, tbl2").is_err());
+        assert!(Sql::new("select * from logs, unnest(tags) as t(v), tbl2").is_err());
+    }
+
+    #[test]
+    fn test_sql_parse_window_function() {
+        let samples = [
+            (
+                "select host, row_number() over (partition by host order by _timestamp) rn from t",
+                vec!["_timestamp", "host"],
+            ),
+            (
+                "select sum(bytes) over (partition by region, zone) from t",
+                vec!["bytes", "region", "zone"],
+            ),
+            (
+                "select rank() over (order by abs(latency) desc) as r from t",
+                vec!["latency"],
+            ),
+        ];
+        for (sql, fields) in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert_eq!(parsed.fields, fields, "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_field_paths() {
+        let samples = [
+            ("select payload['a']['b'] from t", vec!["payload.a.b"]),
+            ("SELECT map['key'] from tbl", vec!["map.key"]),
+            ("select tags[1], host from t", vec!["tags.1"]),
+            // the path stops at the first key that isn't a literal
+            ("select payload['a'][idx]['c'] from t", vec!["payload.a"]),
+            ("select payload[idx] from t", vec![]),
+            (
+                "select \"Payload\"['a'] from t where Meta['x']['y'] = 1",
+                vec!["Payload.a", "meta.x.y"],
+            ),
+            ("select host from t", vec![]),
+        ];
+        for (sql, paths) in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert_eq!(parsed.field_paths, paths, "{sql}");
+        }
+
+        // the roots are still the only fields, the paths come on request
+        let parsed = Sql::new("select payload['a']['b'], host from t").unwrap();
+        assert_eq!(parsed.fields, vec!["host", "payload"]);
+        assert_eq!(
+            parsed.fields_with_paths(),
+            vec!["host", "payload", "payload.a.b"]
+        );
+    }
+
+    #[test]
+    fn test_sql_parse_subquery_sources() {
+        let samples = [
+            ("select * from t", vec![]),
+            (
+                "select * from t where exists (select 1 from other where other.id = t.id)",
+                vec!["other"],
+            ),
+            (
+                "select * from t where host in (select host from hosts) and not exists (select 1 from blocked)",
+                vec!["blocked", "hosts"],
+            ),
+            (
+                "select *, (select max(code) from codes) as m from t",
+                vec!["codes"],
+            ),
+            (
+                "select * from t where host in (select host from a join b on a.id = b.id union select host from (select host from c))",
+                vec!["a", "b", "c"],
+            ),
+            (
+                "select * from t where exists (select 1 from a where a.x in (select x from b))",
+                vec!["a", "b"],
+            ),
+            (
+                "select * from (select * from t where exists (select 1 from a))",
+                vec!["a"],
+            ),
+        ];
+        for (sql, sources) in samples {
+            let parsed = Sql::new(sql).unwrap();
+            assert_eq!(parsed.source, "t", "{sql}");
+            assert_eq!(parsed.subquery_sources, sources, "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_multiple_statements() {
+        assert!(Sql::new("select * from tbl;").is_ok());
+        assert!(Sql::new("select * from tbl;;\n").is_ok());
+
+        let err = Sql::new("select * from tbl; select * from tbl2")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("got 2 statements"), "{err}");
+        assert!(err.contains("starts at byte 19"), "{err}");
+
+        let err = Sql::new("select * from tbl where a = ';';\nselect 1; select 2")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("got 3 statements"), "{err}");
+        assert!(err.contains("starts at byte 33"), "{err}");
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let samples = [
+            ("select * from tbl", "select * from tbl"),
+            ("select * from tbl;", "select * from tbl"),
+            ("select * from tbl ; ;\n", "select * from tbl"),
+            ("-- errors\nselect * from tbl", "select * from tbl"),
+            (
+                "-- errors\n-- of today\nselect * from tbl where a = 1; -- done",
+                "select * from tbl where a = 1",
+            ),
+            (
+                "select /*+ PARALLEL(4) */ * from tbl",
+                "select   * from tbl",
+            ),
+            ("select a/**/from tbl", "select a from tbl"),
+            (
+                "select * from tbl /* where a = 1\n */ where b = 2",
+                "select * from tbl   where b = 2",
+            ),
+            // comment markers in literals and quoted identifiers are kept
+            (
+                "select '-- a', \"/* b */\" from tbl -- c",
+                "select '-- a', \"/* b */\" from tbl",
+            ),
+            ("select 'é' -- ü\n, b from tbl", "select 'é'  , b from tbl"),
+            // left to the parser to reject
+            ("select 'unterminated -- x", "select 'unterminated -- x"),
+        ];
+        for (sql, expected) in samples {
+            assert_eq!(strip_comments(sql), expected, "{sql}");
+        }
+
+        // parsing a query with or without comments gives the same fields
+        for sql in [
+            "-- recent errors\nselect a, b from tbl where a = 1 limit 5;",
+            "/*+ hint */ select a, b from tbl where a = 1 limit 5 -- done",
+            "select a, /* the other */ b from tbl where a = 1 limit 5;\n",
+        ] {
+            let stripped = strip_comments(sql);
+            for sql in [sql, stripped.as_str()] {
+                let parsed = Sql::new(sql).unwrap();
+                assert_eq!(parsed.fields, vec!["a", "b"], "{sql}");
+                assert_eq!(parsed.source, "tbl", "{sql}");
+                assert_eq!(parsed.limit, 5, "{sql}");
+            }
+            // the search flattens the query to one line
+            assert!(Sql::new(&stripped.replace('\n', " ")).is_ok(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let dialect = sqlparser::dialect::GenericDialect {};
+        let samples = [
+            ("select * from tbl", 0, vec![]),
+            // a = 1: the binary op, a and 1
+            ("select * from tbl where a = 1", 3, vec![]),
+            (
+                "select a from tbl where a in (1, 2, 3) or (b in ('x') and c = 1)",
+                15,
+                vec![3, 1],
+            ),
+        ];
+        for (sql, nodes, in_lists) in samples {
+            let statement = &Parser::parse_sql(&dialect, sql).unwrap()[0];
+            assert_eq!(
+                parse_stats(statement),
+                ParseStats { nodes, in_lists },
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_parse_like_not_literal() {
+        // used to panic while collecting the quick text filters
+        for (sql, err) in [
+            (
+                "select * from tbl where a like 1 + 1",
+                "SqlExpr::Like: We only support Identifier at the moment",
+            ),
+            (
+                "select * from tbl where a like 'x' escape 'ab'",
+                "SqlExpr::Like: ESCAPE must be a single character, got 'ab'",
+            ),
+        ] {
+            assert_eq!(Sql::new(sql).unwrap_err().to_string(), err, "{sql}");
+        }
+    }
 }
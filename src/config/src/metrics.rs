@@ -63,6 +63,44 @@ pub static HTTP_RESPONSE_TIME: Lazy<HistogramVec> = Lazy::new(|| {
     .expect("Metric created")
 });
 
+// auth extractor decisions, the route has the org id and object names replaced
+pub static AUTH_EXTRACTOR_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "auth_extractor_requests",
+            "Requests authenticated by the auth extractor, by outcome.",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &["method", "route", "outcome"],
+    )
+    .expect("Metric created")
+});
+pub static AUTH_EXTRACTOR_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "auth_extractor_time",
+            "Time the auth extractor took to extract the credentials, in seconds.",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &["method", "route", "outcome"],
+    )
+    .expect("Metric created")
+});
+pub static AUTH_PERMISSION_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "auth_permission_cache",
+            "Permission checks answered from the decision cache (hit) or by the authorizer (miss).",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &["result"],
+    )
+    .expect("Metric created")
+});
+
 // grpc latency
 pub static GRPC_INCOMING_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
     IntCounterVec::new(
@@ -456,6 +494,41 @@ pub static STORAGE_TIME: Lazy<CounterVec> = Lazy::new(|| {
     .expect("Metric created")
 });
 
+// meta store operations
+pub static DB_OPERATION_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "infra_db_operation_duration_seconds",
+            "Time a meta store operation took, in seconds.",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &["backend", "op"],
+    )
+    .expect("Metric created")
+});
+pub static DB_OPERATION_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "infra_db_operation_errors",
+            "Meta store operations that failed.",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &["backend", "op"],
+    )
+    .expect("Metric created")
+});
+pub static DB_LIST_PAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("infra_db_list_pages", "Pages read by meta store listings.")
+            .namespace(NAMESPACE)
+            .const_labels(create_const_labels()),
+        &["backend"],
+    )
+    .expect("Metric created")
+});
+
 // metadata stats
 pub static META_STORAGE_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
     IntGaugeVec::new(
@@ -520,6 +593,30 @@ pub static META_NUM_USERS: Lazy<IntGaugeVec> = Lazy::new(|| {
     )
     .expect("Metric created")
 });
+pub static META_NUM_PASSWORD_HASHES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "meta_num_password_hashes",
+            "Metadata cached password hash nums",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static META_NUM_LOGIN_FAILURES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "meta_num_login_failures",
+            "Metadata users with recent failed logins nums",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &["state"],
+    )
+    .expect("Metric created")
+});
 pub static META_NUM_FUNCTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
     IntGaugeVec::new(
         Opts::new(
@@ -573,6 +670,17 @@ fn register_metrics(registry: &Registry) {
         .register(Box::new(HTTP_RESPONSE_TIME.clone()))
         .expect("Metric registered");
 
+    // auth extractor
+    registry
+        .register(Box::new(AUTH_EXTRACTOR_REQUESTS.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(AUTH_EXTRACTOR_TIME.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(AUTH_PERMISSION_CACHE.clone()))
+        .expect("Metric registered");
+
     // grpc latency
     registry
         .register(Box::new(GRPC_INCOMING_REQUESTS.clone()))
@@ -675,6 +783,17 @@ fn register_metrics(registry: &Registry) {
     registry
         .register(Box::new(STORAGE_WRITE_REQUESTS.clone()))
         .expect("Metric registered");
+    // meta store operations
+    registry
+        .register(Box::new(DB_OPERATION_TIME.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(DB_OPERATION_ERRORS.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(DB_LIST_PAGES.clone()))
+        .expect("Metric registered");
+
     // metadata stats
     registry
         .register(Box::new(META_STORAGE_BYTES.clone()))
@@ -697,6 +816,12 @@ fn register_metrics(registry: &Registry) {
     registry
         .register(Box::new(META_NUM_USERS.clone()))
         .expect("Metric registered");
+    registry
+        .register(Box::new(META_NUM_PASSWORD_HASHES.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(META_NUM_LOGIN_FAILURES.clone()))
+        .expect("Metric registered");
     registry
         .register(Box::new(META_NUM_FUNCTIONS.clone()))
         .expect("Metric registered");
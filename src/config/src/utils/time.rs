@@ -13,6 +13,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cell::Cell;
+
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use once_cell::sync::Lazy;
 
@@ -34,20 +36,47 @@ static TIME_UNITS: [(char, u64); 7] = [
     ('y', 365 * 24 * 3600 * 1000),
 ];
 
+thread_local! {
+    // set by `override_now`, `None` reads the system clock
+    static NOW_OVERRIDE: Cell<Option<DateTime<Utc>>> = const { Cell::new(None) };
+}
+
+/// The current time, or the time set by [`override_now`] on this thread
 #[inline(always)]
 pub fn now() -> DateTime<Utc> {
-    Utc::now()
+    NOW_OVERRIDE.with(Cell::get).unwrap_or_else(Utc::now)
 }
 
 #[inline(always)]
 pub fn now_micros() -> i64 {
-    Utc::now().timestamp_micros()
+    now().timestamp_micros()
+}
+
+/// Makes [`now`] return `time` on the current thread until the guard is dropped, so tests of
+/// code relative to the current time are deterministic. Other threads, e.g. the workers of a
+/// multi-threaded runtime, still read the system clock.
+pub fn override_now(time: DateTime<Utc>) -> NowOverride {
+    NowOverride {
+        previous: NOW_OVERRIDE.with(|v| v.replace(Some(time))),
+    }
+}
+
+/// Restores the previous time of [`now`] when dropped, see [`override_now`]
+#[must_use]
+pub struct NowOverride {
+    previous: Option<DateTime<Utc>>,
+}
+
+impl Drop for NowOverride {
+    fn drop(&mut self) {
+        NOW_OVERRIDE.with(|v| v.set(self.previous));
+    }
 }
 
 #[inline(always)]
 pub fn parse_i64_to_timestamp_micros(v: i64) -> i64 {
     if v == 0 {
-        return Utc::now().timestamp_micros();
+        return now_micros();
     }
     let mut duration = v;
     if duration > BASE_TIME.timestamp_nanos_opt().unwrap_or_default() {
@@ -217,6 +246,27 @@ pub fn parse_str_to_timestamp_micros_as_option(v: &str) -> Option<i64> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_override_now() {
+        let t1 = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let t2 = Utc.with_ymd_and_hms(2022, 6, 1, 12, 0, 0).unwrap();
+        {
+            let _outer = override_now(t1);
+            assert_eq!(now(), t1);
+            assert_eq!(now_micros(), 1609459200000000);
+            assert_eq!(parse_i64_to_timestamp_micros(0), 1609459200000000);
+            {
+                let _inner = override_now(t2);
+                assert_eq!(now(), t2);
+                // other threads aren't affected
+                let other = std::thread::spawn(now).join().unwrap();
+                assert!(other > t2);
+            }
+            assert_eq!(now(), t1);
+        }
+        assert!(now() > t2);
+    }
+
     #[test]
     fn test_parse_i64_to_timestamp_micros() {
         let v = 1609459200000000000;
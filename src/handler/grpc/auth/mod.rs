@@ -17,11 +17,10 @@ use http_auth_basic::Credentials;
 use tonic::{metadata::MetadataValue, Request, Status};
 
 use crate::common::{
-    infra::{
-        cluster::get_internal_grpc_token,
-        config::{ROOT_USER, USERS},
+    infra::config::{ROOT_USER, USERS},
+    utils::auth::{
+        check_passcode, is_internal_grpc_token, is_root_user, verify_password, PasscodeCheck,
     },
-    utils::auth::{get_hash, is_root_user},
 };
 
 pub fn check_auth(req: Request<()>) -> Result<Request<()>, Status> {
@@ -38,7 +37,7 @@ pub fn check_auth(req: Request<()>) -> Result<Request<()>, Status> {
         .to_str()
         .unwrap()
         .to_string();
-    if token.eq(get_internal_grpc_token().as_str()) {
+    if is_internal_grpc_token(&token) {
         Ok(req)
     } else {
         let org_id = metadata.get(&cfg.grpc.org_header_key);
@@ -70,12 +69,14 @@ pub fn check_auth(req: Request<()>) -> Result<Request<()>, Status> {
             return Err(Status::unauthenticated("No valid auth token"));
         };
 
-        if user.token.eq(&credentials.password) {
+        if check_passcode(&user, &credentials.password, chrono::Utc::now().timestamp())
+            == PasscodeCheck::Valid
+        {
             return Ok(req);
         }
-        let in_pass = get_hash(&credentials.password, &user.salt);
         if user_id.eq(&user.email)
-            && (credentials.password.eq(&user.password) || in_pass.eq(&user.password))
+            && (credentials.password.eq(&user.password)
+                || verify_password(&credentials.password, &user.password))
         {
             let mut req = req;
             let user_id_metadata = MetadataValue::try_from(&user_id).unwrap();
@@ -112,6 +113,9 @@ mod tests {
                 org: "dummy".to_owned(),
                 is_external: false,
                 password_ext: Some("Complexpass#123".to_string()),
+                token_expires_at: None,
+                previous_token: None,
+                allowed_networks: vec![],
             },
         );
 
@@ -142,6 +146,9 @@ mod tests {
                 org: "dummy".to_owned(),
                 is_external: false,
                 password_ext: Some("Complexpass#123".to_string()),
+                token_expires_at: None,
+                previous_token: None,
+                allowed_networks: vec![],
             },
         );
 
@@ -170,6 +177,9 @@ mod tests {
                 org: "dummy".to_owned(),
                 is_external: false,
                 password_ext: Some("Complexpass#123".to_string()),
+                token_expires_at: None,
+                previous_token: None,
+                allowed_networks: vec![],
             },
         );
         let mut request = tonic::Request::new(());
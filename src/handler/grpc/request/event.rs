@@ -25,7 +25,7 @@ use hashbrown::HashSet;
 use infra::{file_list as infra_file_list, schema::STREAM_SCHEMAS_FIELDS};
 use opentelemetry::global;
 use proto::cluster_rpc::{event_server::Event, EmptyResponse, FileList};
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::common::infra::cluster::get_node_from_consistent_hash;
@@ -44,92 +44,140 @@ impl Event for Eventer {
         });
         tracing::Span::current().set_parent(parent_cx);
 
-        let req = req.get_ref();
-        let put_items = req
-            .items
-            .iter()
-            .filter(|v| !v.deleted)
-            .map(FileKey::from)
-            .collect::<Vec<_>>();
-        let del_items = req
-            .items
-            .iter()
-            .filter(|v| v.deleted)
-            .map(|v| v.key.clone())
-            .collect::<Vec<_>>();
-        let cfg = get_config();
-        // Warning: external meta store should not accept any file list
-        // querier and compactor can accept add new files
-        // ingester only accept remove old files
-        if !cfg.common.meta_store_external {
-            if is_querier(&LOCAL_NODE_ROLE) || is_compactor(&LOCAL_NODE_ROLE) {
-                if let Err(e) = infra_file_list::batch_add(&put_items).await {
-                    // metrics
-                    let time = start.elapsed().as_secs_f64();
-                    metrics::GRPC_RESPONSE_TIME
-                        .with_label_values(&["/event/send_file_list", "500", "", "", ""])
-                        .observe(time);
-                    metrics::GRPC_INCOMING_REQUESTS
-                        .with_label_values(&["/event/send_file_list", "500", "", "", ""])
-                        .inc();
-                    return Err(Status::internal(e.to_string()));
+        let mut cached_field_stream = HashSet::new();
+        if let Err(e) = process_file_list(req.get_ref(), &mut cached_field_stream).await {
+            record_metrics("/event/send_file_list", "500", start);
+            return Err(e);
+        }
+
+        record_metrics("/event/send_file_list", "200", start);
+        Ok(Response::new(EmptyResponse {}))
+    }
+
+    async fn send_file_list_stream(
+        &self,
+        req: Request<Streaming<FileList>>,
+    ) -> Result<Response<EmptyResponse>, Status> {
+        let start = std::time::Instant::now();
+        let parent_cx = global::get_text_map_propagator(|prop| {
+            prop.extract(&super::MetadataMap(req.metadata()))
+        });
+        tracing::Span::current().set_parent(parent_cx);
+
+        // batches are processed as they arrive, the metrics cover the whole stream
+        let mut stream = req.into_inner();
+        let mut cached_field_stream = HashSet::new();
+        loop {
+            let batch = match stream.message().await {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                Err(e) => {
+                    record_metrics("/event/send_file_list_stream", "500", start);
+                    return Err(e);
                 }
+            };
+            if let Err(e) = process_file_list(&batch, &mut cached_field_stream).await {
+                record_metrics("/event/send_file_list_stream", "500", start);
+                return Err(e);
             }
-            if let Err(e) = infra_file_list::batch_remove(&del_items).await {
-                // metrics
-                let time = start.elapsed().as_secs_f64();
-                metrics::GRPC_RESPONSE_TIME
-                    .with_label_values(&["/event/send_file_list", "500", "", "", ""])
-                    .observe(time);
-                metrics::GRPC_INCOMING_REQUESTS
-                    .with_label_values(&["/event/send_file_list", "500", "", "", ""])
-                    .inc();
+        }
+
+        record_metrics("/event/send_file_list_stream", "200", start);
+        Ok(Response::new(EmptyResponse {}))
+    }
+}
+
+fn record_metrics(path: &str, code: &str, start: std::time::Instant) {
+    let time = start.elapsed().as_secs_f64();
+    metrics::GRPC_RESPONSE_TIME
+        .with_label_values(&[path, code, "", "", ""])
+        .observe(time);
+    metrics::GRPC_INCOMING_REQUESTS
+        .with_label_values(&[path, code, "", "", ""])
+        .inc();
+}
+
+/// Adds and removes the files of the list and caches the latest files for querier,
+/// `cached_field_stream` holds the streams whose fields were already cached
+async fn process_file_list(
+    req: &FileList,
+    cached_field_stream: &mut HashSet<String>,
+) -> Result<(), Status> {
+    let put_items = req
+        .items
+        .iter()
+        .filter(|v| !v.deleted)
+        .map(FileKey::from)
+        .collect::<Vec<_>>();
+    let del_items = req
+        .items
+        .iter()
+        .filter(|v| v.deleted)
+        .map(|v| v.key.clone())
+        .collect::<Vec<_>>();
+    let cfg = get_config();
+    // Warning: external meta store should not accept any file list
+    // querier and compactor can accept add new files
+    // ingester only accept remove old files
+    if !cfg.common.meta_store_external {
+        if is_querier(&LOCAL_NODE_ROLE) || is_compactor(&LOCAL_NODE_ROLE) {
+            if let Err(e) = infra_file_list::batch_add(&put_items).await {
                 return Err(Status::internal(e.to_string()));
             }
         }
+        if let Err(e) = infra_file_list::batch_remove(&del_items).await {
+            return Err(Status::internal(e.to_string()));
+        }
+    }
 
-        // cache latest files for querier
-        if cfg.memory_cache.cache_latest_files && is_querier(&LOCAL_NODE_ROLE) {
-            let mut cached_field_stream = HashSet::new();
-            for item in put_items.iter() {
-                let Some(node) = get_node_from_consistent_hash(&item.key, &Role::Querier).await
-                else {
-                    continue; // no querier node
-                };
-                if LOCAL_NODE_UUID.ne(&node) {
-                    continue; // not this node
-                }
-                if infra::cache::file_data::download("download", &item.key)
+    // cache latest files for querier
+    if cfg.memory_cache.cache_latest_files && is_querier(&LOCAL_NODE_ROLE) {
+        let mut skipped = 0;
+        for item in put_items.iter() {
+            let Some(node) = get_node_from_consistent_hash(&item.key, &Role::Querier).await else {
+                continue; // no querier node
+            };
+            if LOCAL_NODE_UUID.ne(&node) {
+                continue; // not this node
+            }
+            // a restarted querier already has most of the files
+            let cached = if infra::cache::file_data::is_cached(
+                "download",
+                &item.key,
+                item.meta.compressed_size as usize,
+            )
+            .await
+            {
+                skipped += 1;
+                true
+            } else {
+                infra::cache::file_data::download("download", &item.key)
                     .await
                     .is_ok()
-                    && cfg.limit.quick_mode_file_list_enabled
-                {
-                    let columns = item.key.split('/').collect::<Vec<&str>>();
-                    if columns[2] != "logs" {
-                        continue; // only cache fields for logs
-                    }
-                    let stream_key = columns[1..4].join("/");
-                    if cached_field_stream.contains(&stream_key) {
-                        continue;
-                    }
-                    if cache_latest_fields(&stream_key, &item.key).await.is_ok() {
-                        cached_field_stream.insert(stream_key);
-                    }
+            };
+            if cached && cfg.limit.quick_mode_file_list_enabled {
+                let columns = item.key.split('/').collect::<Vec<&str>>();
+                if columns[2] != "logs" {
+                    continue; // only cache fields for logs
+                }
+                let stream_key = columns[1..4].join("/");
+                if cached_field_stream.contains(&stream_key) {
+                    continue;
+                }
+                if cache_latest_fields(&stream_key, &item.key).await.is_ok() {
+                    cached_field_stream.insert(stream_key);
                 }
             }
         }
-
-        // metrics
-        let time = start.elapsed().as_secs_f64();
-        metrics::GRPC_RESPONSE_TIME
-            .with_label_values(&["/event/send_file_list", "200", "", "", ""])
-            .observe(time);
-        metrics::GRPC_INCOMING_REQUESTS
-            .with_label_values(&["/event/send_file_list", "200", "", "", ""])
-            .inc();
-
-        Ok(Response::new(EmptyResponse {}))
+        if skipped > 0 {
+            log::info!(
+                "[send_file_list] skipped downloading {skipped} of {} files already cached",
+                put_items.len()
+            );
+        }
     }
+
+    Ok(())
 }
 
 async fn cache_latest_fields(stream: &str, file: &str) -> Result<(), anyhow::Error> {
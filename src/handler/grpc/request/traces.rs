@@ -16,9 +16,9 @@
 use opentelemetry_proto::tonic::collector::trace::v1::{
     trace_service_server::TraceService, ExportTraceServiceRequest, ExportTraceServiceResponse,
 };
-use tonic::{codegen::*, Response, Status};
+use tonic::{codegen::*, metadata::MetadataMap, Response, Status};
 
-use crate::service::traces::handle_trace_request;
+use crate::service::{format_stream_name, traces::handle_trace_request};
 
 #[derive(Default)]
 pub struct TraceServer {}
@@ -45,17 +45,13 @@ impl TraceService for TraceServer {
             return Err(Status::invalid_argument(msg));
         }
 
-        let stream_name = metadata.get(&cfg.grpc.stream_header_key);
-        let mut in_stream_name: Option<&str> = None;
-        if let Some(stream_name) = stream_name {
-            in_stream_name = Some(stream_name.to_str().unwrap());
-        };
+        let in_stream_name = get_stream_name(&metadata, &cfg.grpc.stream_header_key)?;
 
         let resp = handle_trace_request(
             org_id.unwrap().to_str().unwrap(),
             in_req,
             true,
-            in_stream_name,
+            in_stream_name.as_deref(),
         )
         .await;
         if resp.is_ok() {
@@ -67,3 +63,55 @@ impl TraceService for TraceServer {
         }
     }
 }
+
+/// Reads the stream name from the request metadata, rejects non-UTF8 or empty
+/// values and normalizes characters not allowed in a stream name.
+fn get_stream_name(metadata: &MetadataMap, key: &str) -> Result<Option<String>, Status> {
+    let Some(stream_name) = metadata.get(key) else {
+        return Ok(None);
+    };
+    let stream_name = stream_name.to_str().map_err(|_| {
+        Status::invalid_argument(format!(
+            "Invalid stream name in header key '{key}', only visible ASCII characters are allowed"
+        ))
+    })?;
+    let stream_name = format_stream_name(stream_name.trim());
+    if stream_name.is_empty() || stream_name.chars().all(|c| c == '_') {
+        return Err(Status::invalid_argument(format!(
+            "Invalid stream name in header key '{key}'"
+        )));
+    }
+    Ok(Some(stream_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::metadata::MetadataValue;
+
+    use super::*;
+
+    #[test]
+    fn test_get_stream_name() {
+        let key = "stream-name";
+        let mut metadata = MetadataMap::new();
+        assert_eq!(get_stream_name(&metadata, key).unwrap(), None);
+
+        metadata.insert(key, MetadataValue::from_static("my stream"));
+        assert_eq!(
+            get_stream_name(&metadata, key).unwrap(),
+            Some("my_stream".to_string())
+        );
+
+        metadata.insert(key, MetadataValue::from_static("a/b"));
+        assert_eq!(
+            get_stream_name(&metadata, key).unwrap(),
+            Some("a_b".to_string())
+        );
+
+        metadata.insert(key, MetadataValue::from_static("//"));
+        assert!(get_stream_name(&metadata, key).is_err());
+
+        metadata.insert(key, MetadataValue::try_from(&b"\xfftraces"[..]).unwrap());
+        assert!(get_stream_name(&metadata, key).is_err());
+    }
+}
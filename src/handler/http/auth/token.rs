@@ -19,12 +19,10 @@ use actix_web::{
     error::ErrorUnauthorized,
     http::{header, Method},
 };
-#[cfg(feature = "enterprise")]
-use o2_enterprise::enterprise::{common::infra::config::O2_CONFIG, dex::service::auth::get_jwks};
 
-use crate::common::utils::auth::AuthExtractor;
 #[cfg(feature = "enterprise")]
-use crate::common::utils::jwt;
+use crate::common::utils::auth::get_bearer_user;
+use crate::common::utils::auth::AuthExtractor;
 #[cfg(feature = "enterprise")]
 use crate::service::{db, users};
 
@@ -38,7 +36,6 @@ pub async fn token_validator(
     use super::validator::check_permissions;
 
     let user;
-    let keys = get_jwks().await;
     let path = match req
         .request()
         .path()
@@ -49,61 +46,49 @@ pub async fn token_validator(
     };
     let path_columns = path.split('/').collect::<Vec<&str>>();
 
-    match jwt::verify_decode_token(
-        auth_info.auth.strip_prefix("Bearer").unwrap().trim(),
-        &keys,
-        &O2_CONFIG.dex.client_id,
-        false,
-    )
-    .await
-    {
-        Ok(res) => {
-            let user_id = &res.0.user_email;
-            if res.0.is_valid {
-                let path_suffix = path_columns.last().unwrap_or(&"");
-                if path_suffix.eq(&"organizations") || path_suffix.eq(&"clusters") {
-                    let db_user = db::user::get_db_user(user_id).await;
-                    user = match db_user {
-                        Ok(user) => {
-                            let all_users = user.get_all_users();
-                            if all_users.is_empty() {
-                                None
-                            } else {
-                                all_users.first().cloned()
-                            }
+    match get_bearer_user(auth_info.auth.strip_prefix("Bearer").unwrap().trim()).await {
+        Ok((user_email, _)) => {
+            let user_id = &user_email;
+            let path_suffix = path_columns.last().unwrap_or(&"");
+            if path_suffix.eq(&"organizations") || path_suffix.eq(&"clusters") {
+                let db_user = db::user::get_db_user(user_id).await;
+                user = match db_user {
+                    Ok(user) => {
+                        let all_users = user.get_all_users();
+                        if all_users.is_empty() {
+                            None
+                        } else {
+                            all_users.first().cloned()
                         }
-                        Err(_) => None,
                     }
-                } else {
-                    user = match path.find('/') {
-                        Some(index) => {
-                            let org_id = &path[0..index];
-                            users::get_user(Some(org_id), user_id).await
-                        }
-                        None => users::get_user(None, user_id).await,
+                    Err(_) => None,
+                }
+            } else {
+                user = match path.find('/') {
+                    Some(index) => {
+                        let org_id = &path[0..index];
+                        users::get_user(Some(org_id), user_id).await
                     }
-                };
-                if user.is_some() {
-                    // / Hack for prometheus, need support POST and check the header
-                    let mut req = req;
+                    None => users::get_user(None, user_id).await,
+                }
+            };
+            if user.is_some() {
+                // / Hack for prometheus, need support POST and check the header
+                let mut req = req;
 
-                    if req.method().eq(&Method::POST) && !req.headers().contains_key("content-type")
-                    {
-                        req.headers_mut().insert(
-                            header::CONTENT_TYPE,
-                            header::HeaderValue::from_static("application/x-www-form-urlencoded"),
-                        );
-                    }
+                if req.method().eq(&Method::POST) && !req.headers().contains_key("content-type") {
                     req.headers_mut().insert(
-                        header::HeaderName::from_static("user_id"),
-                        header::HeaderValue::from_str(&res.0.user_email).unwrap(),
+                        header::CONTENT_TYPE,
+                        header::HeaderValue::from_static("application/x-www-form-urlencoded"),
                     );
-                    // send user role as None as it applies only to internal users
-                    if auth_info.bypass_check || check_permissions(user_id, auth_info, None).await {
-                        Ok(req)
-                    } else {
-                        Err((ErrorForbidden("Unauthorized Access"), req))
-                    }
+                }
+                req.headers_mut().insert(
+                    header::HeaderName::from_static("user_id"),
+                    header::HeaderValue::from_str(user_id).unwrap(),
+                );
+                // send user role as None as it applies only to internal users
+                if auth_info.bypass_check || check_permissions(user_id, auth_info, None).await {
+                    Ok(req)
                 } else {
                     Err((ErrorForbidden("Unauthorized Access"), req))
                 }
@@ -117,24 +102,10 @@ pub async fn token_validator(
 
 #[cfg(feature = "enterprise")]
 pub async fn get_user_name_from_token(auth_str: &str) -> Option<String> {
-    let keys = get_jwks().await;
-    match jwt::verify_decode_token(
-        auth_str.strip_prefix("Bearer").unwrap().trim(),
-        &keys,
-        &O2_CONFIG.dex.client_id,
-        false,
-    )
-    .await
-    {
-        Ok(res) => {
-            if res.0.is_valid {
-                Some(res.0.user_email)
-            } else {
-                None
-            }
-        }
-        Err(_) => None,
-    }
+    get_bearer_user(auth_str.strip_prefix("Bearer").unwrap().trim())
+        .await
+        .ok()
+        .map(|(user_email, _)| user_email)
 }
 
 #[cfg(not(feature = "enterprise"))]
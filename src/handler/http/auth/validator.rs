@@ -15,7 +15,7 @@
 
 use actix_web::{
     dev::ServiceRequest,
-    error::{ErrorForbidden, ErrorUnauthorized},
+    error::{ErrorBadRequest, ErrorForbidden, ErrorTooManyRequests, ErrorUnauthorized},
     http::{header, Method},
     web, Error,
 };
@@ -31,9 +31,13 @@ use crate::{
                 UserRole,
             },
         },
-        utils::auth::{get_hash, is_root_user, AuthExtractor},
+        utils::auth::{
+            check_passcode, get_hash, hash_password, is_root_user, is_root_user_async,
+            needs_rehash, verify_credentials, verify_presigned_url, AuthExtractor,
+            CredentialsError, PasscodeCheck, PresignedError, PresignedScope,
+        },
     },
-    service::{db, users},
+    service::{api_keys, db, users},
 };
 
 pub const PKCE_STATE_ORG: &str = "o2_pkce_state";
@@ -60,7 +64,10 @@ pub async fn validator(
     match if auth_info.auth.starts_with("{\"auth_ext\":") {
         let auth_token: AuthTokensExt =
             config::utils::json::from_str(&auth_info.auth).unwrap_or_default();
-        validate_credentials_ext(user_id, password, path, auth_token).await
+        let client_ip = crate::common::utils::auth::client_ip(req.request());
+        validate_credentials_ext(user_id, path, auth_token, client_ip).await
+    } else if auth_info.auth.starts_with("ApiKey") {
+        validate_api_key(user_id, password, path).await
     } else {
         validate_credentials(user_id, password.trim(), path).await
     } {
@@ -121,7 +128,7 @@ pub async fn validate_credentials(
     }
 
     // this is only applicable for super admin user
-    if is_root_user(user_id) {
+    if is_root_user_async(user_id).await {
         user = users::get_user(None, user_id).await;
         if user.is_none() {
             return Ok(TokenValidationResponse {
@@ -170,8 +177,10 @@ pub async fn validate_credentials(
     }
     let user = user.unwrap();
 
-    if (path_columns.len() == 1 || INGESTION_EP.iter().any(|s| path_columns.contains(s)))
-        && user.token.eq(&user_password)
+    let is_passcode = check_passcode(&user, user_password, chrono::Utc::now().timestamp())
+        == PasscodeCheck::Valid;
+    if is_passcode
+        && (path_columns.len() == 1 || INGESTION_EP.iter().any(|s| path_columns.contains(s)))
     {
         return Ok(TokenValidationResponse {
             is_valid: true,
@@ -184,22 +193,41 @@ pub async fn validate_credentials(
         });
     }
 
-    let in_pass = get_hash(user_password, &user.salt);
-    if !user.password.eq(&in_pass)
-        && !user
-            .password_ext
-            .unwrap_or("".to_string())
-            .eq(&user_password)
-    {
-        return Ok(TokenValidationResponse {
-            is_valid: false,
-            user_email: "".to_string(),
-            is_internal_user: false,
-            user_role: None,
-            user_name: "".to_string(),
-            family_name: "".to_string(),
-            given_name: "".to_string(),
-        });
+    // a passcode, e.g. one resolved from a `Token` header, stands in for the password
+    let verified = if is_passcode {
+        Ok(())
+    } else {
+        verify_credentials(
+            &user.email,
+            user_password,
+            &user.password,
+            user.password_ext.as_deref(),
+        )
+    };
+    match verified {
+        Ok(()) => {
+            if !is_passcode && needs_rehash(&user.password) {
+                let user_email = user.email.clone();
+                let user_password = user_password.to_string();
+                tokio::task::spawn(async move {
+                    users::upgrade_password_hash(&user_email, &user_password).await
+                });
+            }
+        }
+        Err(e @ CredentialsError::Throttled(_)) => {
+            return Err(ErrorTooManyRequests(e.to_string()));
+        }
+        Err(CredentialsError::Invalid) => {
+            return Ok(TokenValidationResponse {
+                is_valid: false,
+                user_email: "".to_string(),
+                is_internal_user: false,
+                user_role: None,
+                user_name: "".to_string(),
+                family_name: "".to_string(),
+                given_name: "".to_string(),
+            });
+        }
     }
     if !path.contains("/user")
         || (path.contains("/user")
@@ -221,12 +249,37 @@ pub async fn validate_credentials(
     }
 }
 
+/// Validates an api key sent in the `X-Api-Key` header, the key is only valid for the org it was
+/// created in and acts as the user who created it.
+pub async fn validate_api_key(
+    user_id: &str,
+    api_key: &str,
+    path: &str,
+) -> Result<TokenValidationResponse, Error> {
+    let Some((org_id, _)) = path.split_once('/') else {
+        return Ok(TokenValidationResponse::default());
+    };
+    let key = match api_keys::get(api_key) {
+        Some(key) if key.org_id == org_id && key.user_email == user_id => key,
+        _ => return Ok(TokenValidationResponse::default()),
+    };
+    let user = if is_root_user_async(&key.user_email).await {
+        users::get_user(None, &key.user_email).await
+    } else {
+        users::get_user(Some(org_id), &key.user_email).await
+    };
+    match user {
+        Some(user) => Ok(TokenValidationResponseBuilder::from_user(&user).build()),
+        None => Ok(TokenValidationResponse::default()),
+    }
+}
+
 #[cfg(feature = "enterprise")]
 pub async fn validate_credentials_ext(
     user_id: &str,
-    in_password: &str,
     path: &str,
     auth_token: AuthTokensExt,
+    client_ip: Option<std::net::IpAddr>,
 ) -> Result<TokenValidationResponse, Error> {
     let user;
     let config = get_config();
@@ -239,7 +292,7 @@ pub async fn validate_credentials_ext(
     }
 
     // this is only applicable for super admin user
-    if is_root_user(user_id) {
+    if is_root_user_async(user_id).await {
         user = users::get_user(None, user_id).await;
         if user.is_none() {
             return Ok(TokenValidationResponse {
@@ -280,20 +333,42 @@ pub async fn validate_credentials_ext(
     }
     let user = user.unwrap();
 
-    let hashed_pass = get_hash(
-        &format!(
-            "{}{}",
-            get_hash(
-                &format!("{}{}", user.password_ext.unwrap(), auth_token.request_time),
-                password_ext_salt
-            ),
-            auth_token.expires_in
-        ),
-        password_ext_salt,
-    );
-    if !hashed_pass.eq(&in_password) {
+    let scope = PresignedScope {
+        org_id: auth_token.org_id.as_deref(),
+        resource: auth_token.resource.as_deref(),
+        allowed_cidr: auth_token.allowed_cidr.as_deref(),
+    };
+    let Some(password_ext) = user.password_ext.as_deref() else {
+        return Ok(TokenValidationResponse::default());
+    };
+    if !scope.allows_path(path) {
         return Ok(TokenValidationResponse::default());
     }
+    let auth_b64 = auth_token
+        .auth_ext
+        .strip_prefix("auth_ext")
+        .unwrap_or_default();
+    match verify_presigned_url(
+        user_id,
+        auth_b64,
+        auth_token.request_time,
+        auth_token.expires_in,
+        password_ext_salt,
+        password_ext,
+        scope,
+        client_ip,
+    ) {
+        Ok(()) => {}
+        Err(PresignedError::OutsideNetwork) => {
+            return Err(ErrorForbidden(
+                crate::common::utils::auth::OUTSIDE_ALLOWED_NETWORKS,
+            ));
+        }
+        Err(e) => {
+            log::debug!("auth_ext cookie rejected for user {user_id}: {e}");
+            return Ok(TokenValidationResponse::default());
+        }
+    }
     if !path.contains("/user")
         || (path.contains("/user")
             && (user.role.eq(&UserRole::Admin)
@@ -317,9 +392,9 @@ pub async fn validate_credentials_ext(
 #[cfg(not(feature = "enterprise"))]
 pub async fn validate_credentials_ext(
     _user_id: &str,
-    _in_password: &str,
     _path: &str,
     _auth_token: AuthTokensExt,
+    _client_ip: Option<std::net::IpAddr>,
 ) -> Result<TokenValidationResponse, Error> {
     Err(ErrorForbidden("Not allowed"))
 }
@@ -327,48 +402,32 @@ pub async fn validate_credentials_ext(
 async fn validate_user_from_db(
     db_user: Result<DBUser, anyhow::Error>,
     user_password: &str,
-    req_time: Option<&String>,
-    exp_in: i64,
     password_ext_salt: &str,
 ) -> Result<TokenValidationResponse, Error> {
     // let db_user = db::user::get_db_user(user_id).await;
     match db_user {
         Ok(mut user) => {
-            let in_pass = get_hash(user_password, &user.salt);
-            if req_time.is_none() && user.password.eq(&in_pass) {
-                log::debug!("Validating internal user");
-                if user.password_ext.is_none() {
-                    let password_ext = get_hash(user_password, password_ext_salt);
-                    user.password_ext = Some(password_ext);
-                    let _ = db::user::set(&user).await;
-                }
-                let resp = TokenValidationResponseBuilder::from_db_user(&user).build();
-                Ok(resp)
-            } else if user.password_ext.is_some() && req_time.is_some() {
-                log::debug!("Validating user for query params");
-                let hashed_pass = get_hash(
-                    &format!(
-                        "{}{}",
-                        get_hash(
-                            &format!(
-                                "{}{}",
-                                user.password_ext.as_ref().unwrap(),
-                                req_time.unwrap()
-                            ),
-                            password_ext_salt
-                        ),
-                        exp_in
-                    ),
-                    password_ext_salt,
-                );
-                if hashed_pass.eq(&user_password) {
+            match verify_credentials(&user.email, user_password, &user.password, None) {
+                Ok(()) => {
+                    log::debug!("Validating internal user");
+                    let mut updated = false;
+                    if user.password_ext.is_none() {
+                        let password_ext = get_hash(user_password, password_ext_salt);
+                        user.password_ext = Some(password_ext);
+                        updated = true;
+                    }
+                    if needs_rehash(&user.password) {
+                        user.password = hash_password(user_password, &user.salt);
+                        updated = true;
+                    }
+                    if updated {
+                        let _ = db::user::set(&user).await;
+                    }
                     let resp = TokenValidationResponseBuilder::from_db_user(&user).build();
-                    return Ok(resp);
-                } else {
-                    Err(ErrorForbidden("Not allowed"))
+                    Ok(resp)
                 }
-            } else {
-                Err(ErrorForbidden("Not allowed"))
+                Err(e @ CredentialsError::Throttled(_)) => Err(ErrorTooManyRequests(e.to_string())),
+                Err(CredentialsError::Invalid) => Err(ErrorForbidden("Not allowed")),
             }
         }
         Err(_) => Err(ErrorForbidden("Not allowed")),
@@ -381,25 +440,48 @@ pub async fn validate_user(
 ) -> Result<TokenValidationResponse, Error> {
     let db_user = db::user::get_db_user(user_id).await;
     let config = get_config();
-    validate_user_from_db(db_user, user_password, None, 0, &config.auth.ext_auth_salt).await
+    validate_user_from_db(db_user, user_password, &config.auth.ext_auth_salt).await
 }
 
+/// Validates the `auth` blob of a presigned login url, see [`verify_presigned_url`].
+///
+/// A malformed url is a bad request, a tampered or expired one is unauthorized and one used from
+/// outside its `allowed_cidr` is forbidden.
 pub async fn validate_user_for_query_params(
     user_id: &str,
-    user_password: &str,
-    req_time: Option<&String>,
+    auth: &str,
+    req_time: i64,
     exp_in: i64,
+    scope: PresignedScope<'_>,
+    client_ip: Option<std::net::IpAddr>,
 ) -> Result<TokenValidationResponse, Error> {
-    let db_user = db::user::get_db_user(user_id).await;
+    let Ok(user) = db::user::get_db_user(user_id).await else {
+        return Err(ErrorForbidden("Not allowed"));
+    };
+    let Some(password_ext) = user.password_ext.as_ref() else {
+        return Err(ErrorForbidden("Not allowed"));
+    };
     let config = get_config();
-    validate_user_from_db(
-        db_user,
-        user_password,
+    if let Err(e) = verify_presigned_url(
+        user_id,
+        auth,
         req_time,
         exp_in,
         &config.auth.ext_auth_salt,
-    )
-    .await
+        password_ext,
+        scope,
+        client_ip,
+    ) {
+        log::debug!("Presigned url rejected for user {user_id}: {e}");
+        return Err(match e {
+            PresignedError::Malformed => ErrorBadRequest(e.to_string()),
+            PresignedError::BadSignature | PresignedError::Expired => {
+                ErrorUnauthorized(e.to_string())
+            }
+            PresignedError::OutsideNetwork => ErrorForbidden(e.to_string()),
+        });
+    }
+    Ok(TokenValidationResponseBuilder::from_db_user(&user).build())
 }
 
 pub async fn validator_aws(
@@ -551,6 +633,18 @@ async fn oo_validator_internal(
         validator(req, &username, &password, auth_info, path_prefix).await
     } else if auth_info.auth.starts_with("Bearer") {
         super::token::token_validator(req, auth_info).await
+    } else if auth_info.auth.starts_with("ApiKey") {
+        let api_key = auth_info
+            .auth
+            .strip_prefix("ApiKey")
+            .unwrap()
+            .trim()
+            .to_string();
+        let user_id = match api_keys::get(&api_key) {
+            Some(key) => key.user_email,
+            None => return Err((ErrorUnauthorized("Unauthorized Access"), req)),
+        };
+        validator(req, &user_id, &api_key, auth_info, path_prefix).await
     } else if auth_info.auth.starts_with("{\"auth_ext\":") {
         let auth_tokens: AuthTokensExt =
             config::utils::json::from_str(&auth_info.auth).unwrap_or_default();
@@ -588,6 +682,8 @@ pub async fn get_user_email_from_auth_str(auth_str: &str) -> Option<String> {
         }
     } else if auth_str.starts_with("Bearer") {
         super::token::get_user_name_from_token(auth_str).await
+    } else if auth_str.starts_with("ApiKey") {
+        api_keys::get(auth_str.strip_prefix("ApiKey").unwrap().trim()).map(|key| key.user_email)
     } else if auth_str.starts_with("{\"auth_ext\":") {
         let auth_tokens: AuthTokensExt =
             config::utils::json::from_str(auth_str).unwrap_or_default();
@@ -680,6 +776,11 @@ pub(crate) async fn check_permissions(
         return true;
     }
 
+    // root user should have access to everything, even before the users cache is loaded
+    if is_root_user_async(user_id).await {
+        return true;
+    }
+
     let object_str = auth_info.o2_type;
     let obj_str = if object_str.contains("##user_id##") {
         object_str.replace("##user_id##", user_id)
@@ -703,15 +804,52 @@ pub(crate) async fn check_permissions(
         &auth_info.org_id
     };
 
-    o2_enterprise::enterprise::openfga::authorizer::authz::is_allowed(
-        org_id,
-        user_id,
-        &auth_info.method,
-        &obj_str,
-        &auth_info.parent_id,
-        &role,
+    let obj_str = ingestion_stream_object(&obj_str, org_id).unwrap_or(obj_str);
+
+    let key = (
+        user_id.to_string(),
+        org_id.to_string(),
+        obj_str.clone(),
+        auth_info.method.clone(),
+        auth_info.parent_id.clone(),
+    );
+    let allowed = crate::common::utils::auth::cached_permission_check(
+        key,
+        o2_enterprise::enterprise::openfga::authorizer::authz::is_allowed(
+            org_id,
+            user_id,
+            &auth_info.method,
+            &obj_str,
+            &auth_info.parent_id,
+            &role,
+        ),
     )
-    .await
+    .await;
+    if !allowed {
+        crate::common::utils::auth_audit::record_denial(
+            org_id,
+            user_id,
+            &obj_str,
+            &auth_info.method,
+            "",
+            "permission denied",
+        );
+    }
+    allowed
+}
+
+/// Resolves the `stream:{org_id}/{stream}` object of a per stream ingestion check to the logs
+/// stream, other objects are `None`. Stream names never contain a `/`.
+#[cfg_attr(not(feature = "enterprise"), allow(dead_code))]
+fn ingestion_stream_object(obj_str: &str, org_id: &str) -> Option<String> {
+    let stream = obj_str
+        .strip_prefix("stream:")?
+        .strip_prefix(org_id)?
+        .strip_prefix('/')?;
+    Some(format!(
+        "{}:{stream}",
+        config::meta::stream::StreamType::Logs
+    ))
 }
 
 #[cfg(not(feature = "enterprise"))]
@@ -766,6 +904,25 @@ mod tests {
     use super::*;
     use crate::common::meta::user::UserRequest;
 
+    #[test]
+    fn test_ingestion_stream_object() {
+        let samples = [
+            ("stream:default/app", "default", Some("logs:app")),
+            ("stream:default/app", "other", None),
+            ("stream:app", "default", None),
+            ("stream:defaultx/app", "default", None),
+            ("logs:_all_default", "default", None),
+            ("logs:app", "default", None),
+        ];
+        for (obj_str, org_id, expected) in samples {
+            assert_eq!(
+                ingestion_stream_object(obj_str, org_id).as_deref(),
+                expected,
+                "{obj_str}"
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_validation_response_builder_from_db_user() {
         let user = DBUser {
@@ -887,5 +1044,77 @@ mod tests {
                 .is_valid
         );
         assert!(validate_user(init_user, pwd).await.unwrap().is_valid);
+
+        // the passcode is accepted in place of the password
+        let passcode = users::get_user(Some(org_id), user_id).await.unwrap().token;
+        assert!(
+            validate_credentials(user_id, &passcode, "default/streams")
+                .await
+                .unwrap()
+                .is_valid
+        );
+        assert!(
+            !validate_credentials(user_id, "not_the_passcode", "default/streams")
+                .await
+                .unwrap()
+                .is_valid
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_api_key() {
+        let org_id = "default";
+        let init_user = "root@example.com";
+        let pwd = "Complexpass#123";
+
+        infra_db::create_table().await.unwrap();
+        users::create_root_user(
+            org_id,
+            UserRequest {
+                email: init_user.to_string(),
+                password: pwd.to_string(),
+                role: crate::common::meta::user::UserRole::Root,
+                first_name: "root".to_owned(),
+                last_name: "".to_owned(),
+                is_external: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        let key = api_keys::create(org_id, init_user, "ci").await.unwrap();
+        let api_key = key.api_key.unwrap();
+        let resp = validate_api_key(init_user, &api_key, "default/_search")
+            .await
+            .unwrap();
+        assert!(resp.is_valid);
+        assert_eq!(resp.user_email, init_user);
+
+        // unknown key
+        assert!(
+            !validate_api_key(init_user, "o2k_unknown", "default/_search")
+                .await
+                .unwrap()
+                .is_valid
+        );
+        // scoped to the org it was created in
+        assert!(
+            !validate_api_key(init_user, &api_key, "other/_search")
+                .await
+                .unwrap()
+                .is_valid
+        );
+        // revoked key
+        assert!(
+            api_keys::revoke(org_id, init_user, &key.key_id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !validate_api_key(init_user, &api_key, "default/_search")
+                .await
+                .unwrap()
+                .is_valid
+        );
     }
 }
@@ -18,7 +18,16 @@ use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
 #[cfg(feature = "enterprise")]
 use o2_enterprise::enterprise::dex::meta::auth::RoleRequest;
 
-use crate::common::meta::user::{UserGroup, UserGroupRequest, UserRoleRequest};
+#[cfg(feature = "enterprise")]
+use crate::common::utils::auth::invalidate_permission_decisions;
+use crate::common::{
+    infra::config::USERS,
+    meta::{
+        dashboards::DEFAULT_FOLDER,
+        user::{UserGroup, UserGroupRequest, UserRole, UserRoleRequest},
+    },
+    utils::auth::is_root_user,
+};
 
 #[cfg(feature = "enterprise")]
 #[post("/{org_id}/roles")]
@@ -54,9 +63,11 @@ pub async fn create_role(
 pub async fn delete_role(path: web::Path<(String, String)>) -> Result<HttpResponse, Error> {
     let (org_id, role_name) = path.into_inner();
 
-    match o2_enterprise::enterprise::openfga::authorizer::roles::delete_role(&org_id, &role_name)
-        .await
-    {
+    let res =
+        o2_enterprise::enterprise::openfga::authorizer::roles::delete_role(&org_id, &role_name)
+            .await;
+    invalidate_permission_decisions(&org_id);
+    match res {
         Ok(_) => Ok(HttpResponse::Ok().finish()),
         Err(err) => Ok(HttpResponse::InternalServerError().body(err.to_string())),
     }
@@ -135,7 +146,7 @@ pub async fn update_role(
     let (org_id, role_id) = path.into_inner();
     let update_role = update_role.into_inner();
 
-    match o2_enterprise::enterprise::openfga::authorizer::roles::update_role(
+    let res = o2_enterprise::enterprise::openfga::authorizer::roles::update_role(
         &org_id,
         &role_id,
         update_role.add,
@@ -143,8 +154,9 @@ pub async fn update_role(
         update_role.add_users,
         update_role.remove_users,
     )
-    .await
-    {
+    .await;
+    invalidate_permission_decisions(&org_id);
+    match res {
         Ok(res) => Ok(HttpResponse::Ok().json(res)),
         Err(err) => Ok(HttpResponse::InternalServerError().body(err.to_string())),
     }
@@ -212,13 +224,14 @@ pub async fn create_group(
     let org_id = org_id.into_inner();
     let user_grp = user_group.into_inner();
 
-    match o2_enterprise::enterprise::openfga::authorizer::groups::create_group(
+    let res = o2_enterprise::enterprise::openfga::authorizer::groups::create_group(
         &org_id,
         &user_grp.name,
         user_grp.users.unwrap_or_default(),
     )
-    .await
-    {
+    .await;
+    invalidate_permission_decisions(&org_id);
+    match res {
         Ok(_) => Ok(HttpResponse::Ok().finish()),
         Err(err) => Ok(HttpResponse::InternalServerError().body(err.to_string())),
     }
@@ -242,7 +255,7 @@ pub async fn update_group(
     let (org_id, group_name) = path.into_inner();
     let user_grp = user_group.into_inner();
 
-    match o2_enterprise::enterprise::openfga::authorizer::groups::update_group(
+    let res = o2_enterprise::enterprise::openfga::authorizer::groups::update_group(
         &org_id,
         &group_name,
         user_grp.add_users,
@@ -250,8 +263,9 @@ pub async fn update_group(
         user_grp.add_roles,
         user_grp.remove_roles,
     )
-    .await
-    {
+    .await;
+    invalidate_permission_decisions(&org_id);
+    match res {
         Ok(_) => Ok(HttpResponse::Ok().finish()),
         Err(err) => Ok(HttpResponse::InternalServerError().body(err.to_string())),
     }
@@ -365,9 +379,11 @@ pub async fn get_resources(_org_id: web::Path<String>) -> Result<HttpResponse, E
 pub async fn delete_group(path: web::Path<(String, String)>) -> Result<HttpResponse, Error> {
     let (org_id, group_name) = path.into_inner();
 
-    match o2_enterprise::enterprise::openfga::authorizer::groups::delete_group(&org_id, &group_name)
-        .await
-    {
+    let res =
+        o2_enterprise::enterprise::openfga::authorizer::groups::delete_group(&org_id, &group_name)
+            .await;
+    invalidate_permission_decisions(&org_id);
+    match res {
         Ok(_) => Ok(HttpResponse::Ok().finish()),
         Err(err) => Ok(HttpResponse::InternalServerError().body(err.to_string())),
     }
@@ -378,3 +394,55 @@ pub async fn delete_group(path: web::Path<(String, String)>) -> Result<HttpRespo
 pub async fn delete_group(_path: web::Path<(String, String)>) -> Result<HttpResponse, Error> {
     Ok(HttpResponse::Forbidden().json("Not Supported"))
 }
+
+#[derive(serde::Deserialize)]
+pub struct ExplainPermissionQuery {
+    user_id: String,
+    object_type: String,
+    /// Empty for the list route of the object type
+    #[serde(default)]
+    object_id: String,
+    #[serde(default = "default_explain_method")]
+    method: String,
+    folder: Option<String>,
+}
+
+fn default_explain_method() -> String {
+    "GET".to_string()
+}
+
+/// Explains why a user is allowed or denied a request on an object, only org admins and root
+/// can ask
+#[get("/{org_id}/permissions/explain")]
+pub async fn explain_permission(
+    org_id: web::Path<String>,
+    query: web::Query<ExplainPermissionQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let org_id = org_id.into_inner();
+    let requester = req
+        .headers()
+        .get("user_id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let is_admin = is_root_user(requester)
+        || USERS
+            .get(&format!("{org_id}/{requester}"))
+            .is_some_and(|user| user.role == UserRole::Admin);
+    if !is_admin {
+        return Ok(crate::common::meta::http::HttpResponse::forbidden(
+            "Only org admins can explain permissions",
+        ));
+    }
+    let folder = query.folder.as_deref().unwrap_or(DEFAULT_FOLDER);
+    let explanation = crate::common::utils::auth::explain_permission(
+        &org_id,
+        &query.user_id,
+        &query.object_type,
+        &query.object_id,
+        &query.method,
+        folder,
+    )
+    .await;
+    Ok(HttpResponse::Ok().json(explanation))
+}
@@ -46,6 +46,7 @@ pub mod reports;
     ),
     responses(
         (status = StatusCode::CREATED, description = "Dashboard created", body = Dashboard),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to create dashboards in the folder of the body", body = HttpResponse),
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal Server Error", body = HttpResponse),
     ),
 )]
@@ -56,8 +57,14 @@ pub async fn create_dashboard(
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let org_id = path.into_inner();
-    let folder = get_folder(req);
-    dashboards::create_dashboard(&org_id, &folder, body).await
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let folder = query.get("folder").map(String::as_str);
+    let user_id = req
+        .headers()
+        .get("user_id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    dashboards::create_dashboard(&org_id, folder, user_id, body).await
 }
 
 /// UpdateDashboard
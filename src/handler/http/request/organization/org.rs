@@ -13,9 +13,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{collections::HashSet, io::Error};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Error,
+};
 
-use actix_web::{get, http, post, put, web, HttpResponse, Result};
+use actix_web::{get, http, post, put, web, HttpRequest, HttpResponse, Result};
 use infra::schema::STREAM_SCHEMAS_LATEST;
 
 use crate::{
@@ -30,7 +33,9 @@ use crate::{
         },
         utils::auth::{is_root_user, UserEmail},
     },
-    service::organization::{self, get_passcode, get_rum_token, update_passcode, update_rum_token},
+    service::organization::{
+        self, get_passcode, get_rum_token, rotate_passcode, update_passcode, update_rum_token,
+    },
 };
 
 /// GetOrganizations
@@ -195,6 +200,7 @@ async fn get_user_passcode(
     ),
     params(
         ("org_id" = String, Path, description = "Organization name"),
+        ("rotate" = Option<bool>, Query, description = "Keep the current passcode valid for a grace period"),
       ),
     responses(
         (status = 200, description = "Success", content_type = "application/json", body = PasscodeResponse),
@@ -205,6 +211,7 @@ async fn get_user_passcode(
 async fn update_user_passcode(
     user_email: UserEmail,
     org_id: web::Path<String>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let org = org_id.into_inner();
     let user_id = user_email.user_id.as_str();
@@ -212,7 +219,14 @@ async fn update_user_passcode(
     if is_root_user(user_id) {
         org_id = None;
     }
-    match update_passcode(org_id, user_id).await {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let rotate = query.get("rotate").is_some_and(|v| v == "true");
+    let res = if rotate {
+        rotate_passcode(org_id, user_id).await
+    } else {
+        update_passcode(org_id, user_id).await
+    };
+    match res {
         Ok(passcode) => Ok(HttpResponse::Ok().json(PasscodeResponse { data: passcode })),
         Err(e) => Ok(HttpResponse::NotFound().json(MetaHttpResponse::error(
             http::StatusCode::NOT_FOUND.into(),
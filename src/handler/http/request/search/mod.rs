@@ -224,22 +224,28 @@ pub async fn search(
             let user: meta::user::User =
                 USERS.get(&format!("{org_id}/{}", user_id)).unwrap().clone();
 
-            if user.is_external
-                && !crate::handler::http::auth::validator::check_permissions(
-                    &user_id,
-                    AuthExtractor {
-                        auth: "".to_string(),
-                        method: "GET".to_string(),
-                        o2_type: format!("{}:{}", stream_type, stream_name),
-                        org_id: org_id.clone(),
-                        bypass_check: false,
-                        parent_id: "".to_string(),
-                    },
-                    Some(user.role),
-                )
-                .await
-            {
-                return Ok(MetaHttpResponse::forbidden("Unauthorized Access"));
+            if user.is_external {
+                // every stream the query reads, including the ones of its subqueries
+                let streams =
+                    std::iter::once(stream_name).chain(parsed_sql.subquery_sources.iter());
+                for stream in streams {
+                    if !crate::handler::http::auth::validator::check_permissions(
+                        &user_id,
+                        AuthExtractor {
+                            auth: "".to_string(),
+                            method: "GET".to_string(),
+                            o2_type: format!("{}:{}", stream_type, stream),
+                            org_id: org_id.clone(),
+                            bypass_check: false,
+                            parent_id: "".to_string(),
+                        },
+                        Some(user.role.clone()),
+                    )
+                    .await
+                    {
+                        return Ok(MetaHttpResponse::forbidden("Unauthorized Access"));
+                    }
+                }
             }
         }
         // Check permissions on stream ends
@@ -251,7 +257,12 @@ pub async fn search(
         .unwrap_or_default();
 
     let mut h = config::utils::hash::gxhash::new();
-    let hashed_query = h.sum64(&origin_sql);
+    // DISTINCT is part of the key so deduplicated results never share an entry with raw rows
+    let hashed_query = if parsed_sql.distinct {
+        h.sum64(&format!("distinct {origin_sql}"))
+    } else {
+        h.sum64(&origin_sql)
+    };
     let mut file_path = format!(
         "{}/{}/{}/{}",
         org_id, stream_type, stream_name, hashed_query
@@ -176,7 +176,13 @@ pub async fn create_view(
 
     match saved_view::set_view(&org_id, &view).await {
         Ok(created_view) => {
-            set_ownership(&org_id, "savedviews", Authz::new(&created_view.view_id)).await;
+            set_ownership(
+                &org_id,
+                "savedviews",
+                Authz::new(&created_view.view_id),
+                false,
+            )
+            .await;
             Ok(MetaHttpResponse::json(CreateViewResponse {
                 org_id,
                 view_id: created_view.view_id,
@@ -54,7 +54,7 @@ use {
             infra::config::O2_CONFIG,
             settings::{get_logo, get_logo_text},
         },
-        dex::service::auth::{exchange_code, get_dex_login, get_jwks, refresh_token},
+        dex::service::auth::{exchange_code, get_dex_login, refresh_token},
     },
     std::io::ErrorKind,
 };
@@ -132,11 +132,18 @@ struct Rum {
     path = "/healthz",
     tag = "Meta",
     responses(
-        (status = 200, description="Staus OK", content_type = "application/json", body = HealthzResponse, example = json!({"status": "ok"}))
+        (status = 200, description="Staus OK", content_type = "application/json", body = HealthzResponse, example = json!({"status": "ok"})),
+        (status = 503, description="Cluster coordinator unreachable", content_type = "application/json", body = HealthzResponse, example = json!({"status": "not ok"})),
     )
 )]
 #[get("/healthz")]
 pub async fn healthz() -> Result<HttpResponse, Error> {
+    if let Err(e) = db::get_coordinator().await.health().await {
+        log::error!("[HEALTHZ] cluster coordinator is unreachable: {e}");
+        return Ok(HttpResponse::ServiceUnavailable().json(HealthzResponse {
+            status: "not ok".to_string(),
+        }));
+    }
     Ok(HttpResponse::Ok().json(HealthzResponse {
         status: "ok".to_string(),
     }))
@@ -434,13 +441,14 @@ pub async fn redirect(req: HttpRequest) -> Result<HttpResponse, Error> {
         Ok(login_data) => {
             let login_url;
             let access_token = login_data.access_token;
-            let keys = get_jwks().await;
             let token_ver =
-                verify_decode_token(&access_token, &keys, &O2_CONFIG.dex.client_id, true).await;
+                verify_decode_token(&access_token, &O2_CONFIG.dex.client_id, true).await;
             let id_token;
+            let user_email;
             match token_ver {
                 Ok(res) => {
                     audit_message.user_email = res.0.user_email.clone();
+                    user_email = res.0.user_email.clone();
                     id_token = json::to_string(&json::json!({
                         "email": res.0.user_email,
                         "name": res.0.user_name,
@@ -465,11 +473,25 @@ pub async fn redirect(req: HttpRequest) -> Result<HttpResponse, Error> {
                 }
             }
 
+            let evicted = match crate::common::utils::auth::admit_session(&user_email) {
+                Ok(evicted) => evicted,
+                Err(e) => {
+                    audit_message.response_code = 409;
+                    audit_message._timestamp = chrono::Utc::now().timestamp_micros();
+                    audit(audit_message).await;
+                    return Ok(HttpResponse::Conflict().json(e.to_string()));
+                }
+            };
+            for session_id in evicted {
+                crate::common::utils::auth::revoke_session(&session_id).await;
+            }
+
             // generate new UUID for access token & store token in DB
             let session_id = ider::uuid();
 
             // store session_id in cluster co-ordinator
-            let _ = crate::service::session::set_session(&session_id, &access_token).await;
+            let _ =
+                crate::service::session::set_session(&session_id, &user_email, &access_token).await;
 
             let access_token = format!("session {}", session_id);
 
@@ -525,15 +547,19 @@ pub async fn dex_login() -> Result<HttpResponse, Error> {
 #[cfg(feature = "enterprise")]
 #[get("/dex_refresh")]
 async fn refresh_token_with_dex(req: actix_web::HttpRequest) -> HttpResponse {
+    let mut user_id = String::new();
     let token = if let Some(cookie) = req.cookie("auth_tokens") {
         let auth_tokens: AuthTokens = json::from_str(cookie.value()).unwrap_or_default();
 
         // remove old session id from cluster co-ordinator
 
         let access_token = auth_tokens.access_token;
-        if access_token.starts_with("session") {
-            crate::service::session::remove_session(access_token.strip_prefix("session ").unwrap())
-                .await;
+        if let Some(session_id) = access_token.strip_prefix("session ") {
+            // the new session replaces it, so it doesn't count against the session limit
+            if let Some(session) = USER_SESSIONS.get(session_id) {
+                user_id = session.user_id.clone();
+            }
+            crate::service::session::remove_session(session_id).await;
         }
 
         auth_tokens.refresh_token
@@ -548,7 +574,8 @@ async fn refresh_token_with_dex(req: actix_web::HttpRequest) -> HttpResponse {
             let session_id = ider::uuid();
 
             // store session_id in cluster co-ordinator
-            let _ = crate::service::session::set_session(&session_id, &access_token).await;
+            let _ =
+                crate::service::session::set_session(&session_id, &user_id, &access_token).await;
 
             let access_token = format!("session {}", session_id);
 
@@ -36,13 +36,13 @@ use crate::{
         meta::{
             self,
             user::{
-                AuthTokens, RolesResponse, SignInResponse, SignInUser, UpdateUser, UserOrgRole,
-                UserRequest, UserRole,
+                ApiKeyList, ApiKeyRequest, ApiKeyResponse, AuthTokens, RolesResponse,
+                SignInResponse, SignInUser, UpdateUser, UserOrgRole, UserRequest, UserRole,
             },
         },
-        utils::auth::{generate_presigned_url, UserEmail},
+        utils::auth::{generate_presigned_url, PresignedScope, UserEmail},
     },
-    service::users,
+    service::{api_keys, users},
 };
 
 /// ListUsers
@@ -230,6 +230,115 @@ pub async fn delete(
     users::remove_user_from_org(&org_id, &email_id, &initiator_id).await
 }
 
+/// CreateApiKey
+#[utoipa::path(
+    context_path = "/api",
+    tag = "Users",
+    operation_id = "CreateApiKey",
+    security(
+        ("Authorization"= [])
+    ),
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+    ),
+    request_body(content = ApiKeyRequest, description = "Api key data", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Success", content_type = "application/json", body = ApiKeyResponse),
+    )
+)]
+#[post("/{org_id}/api_keys")]
+pub async fn create_api_key(
+    org_id: web::Path<String>,
+    req: web::Json<ApiKeyRequest>,
+    user_email: UserEmail,
+) -> Result<HttpResponse, Error> {
+    let org_id = org_id.into_inner();
+    match api_keys::create(&org_id, &user_email.user_id, &req.name).await {
+        Ok(key) => Ok(HttpResponse::Ok().json(key)),
+        Err(e) => Ok(
+            HttpResponse::InternalServerError().json(meta::http::HttpResponse::error(
+                http::StatusCode::INTERNAL_SERVER_ERROR.into(),
+                e.to_string(),
+            )),
+        ),
+    }
+}
+
+/// ListApiKeys
+#[utoipa::path(
+    context_path = "/api",
+    tag = "Users",
+    operation_id = "ListApiKeys",
+    security(
+        ("Authorization"= [])
+    ),
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+    ),
+    responses(
+        (status = 200, description = "Success", content_type = "application/json", body = ApiKeyList),
+    )
+)]
+#[get("/{org_id}/api_keys")]
+pub async fn list_api_keys(
+    org_id: web::Path<String>,
+    user_email: UserEmail,
+) -> Result<HttpResponse, Error> {
+    let org_id = org_id.into_inner();
+    match api_keys::list(&org_id, &user_email.user_id).await {
+        Ok(data) => Ok(HttpResponse::Ok().json(ApiKeyList { data })),
+        Err(e) => Ok(
+            HttpResponse::InternalServerError().json(meta::http::HttpResponse::error(
+                http::StatusCode::INTERNAL_SERVER_ERROR.into(),
+                e.to_string(),
+            )),
+        ),
+    }
+}
+
+/// RevokeApiKey
+#[utoipa::path(
+    context_path = "/api",
+    tag = "Users",
+    operation_id = "RevokeApiKey",
+    security(
+        ("Authorization"= [])
+    ),
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("key_id" = String, Path, description = "Api key id"),
+    ),
+    responses(
+        (status = 200, description = "Success", content_type = "application/json", body = HttpResponse),
+        (status = 404, description = "NotFound", content_type = "application/json", body = HttpResponse),
+    )
+)]
+#[delete("/{org_id}/api_keys/{key_id}")]
+pub async fn revoke_api_key(
+    path: web::Path<(String, String)>,
+    user_email: UserEmail,
+) -> Result<HttpResponse, Error> {
+    let (org_id, key_id) = path.into_inner();
+    match api_keys::revoke(&org_id, &user_email.user_id, &key_id).await {
+        Ok(true) => Ok(HttpResponse::Ok().json(meta::http::HttpResponse::message(
+            http::StatusCode::OK.into(),
+            "Api key revoked".to_string(),
+        ))),
+        Ok(false) => Ok(
+            HttpResponse::NotFound().json(meta::http::HttpResponse::error(
+                http::StatusCode::NOT_FOUND.into(),
+                "Api key not found".to_string(),
+            )),
+        ),
+        Err(e) => Ok(
+            HttpResponse::InternalServerError().json(meta::http::HttpResponse::error(
+                http::StatusCode::INTERNAL_SERVER_ERROR.into(),
+                e.to_string(),
+            )),
+        ),
+    }
+}
+
 /// AuthenticateUser
 #[utoipa::path(
     context_path = "/auth",
@@ -316,10 +425,10 @@ pub async fn authentication(
                 return unauthorized_error(resp);
             }
         }
-        Err(_e) => {
+        Err(e) => {
             #[cfg(feature = "enterprise")]
             audit_unauthorized_error(audit_message).await;
-            return unauthorized_error(resp);
+            return validation_error(e, resp);
         }
     };
     if resp.status {
@@ -363,6 +472,12 @@ pub async fn authentication(
 struct PresignedURLGenerator {
     #[serde(default = "default_exp_in")]
     exp_in: u32,
+    /// Limits the url to one org
+    org_id: Option<String>,
+    /// Limits the url to one resource, e.g. a dashboard path
+    resource: Option<String>,
+    /// Limits the url to clients from an IP or CIDR
+    allowed_cidr: Option<String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -381,6 +496,28 @@ pub async fn get_presigned_url(
     query: web::Query<PresignedURLGenerator>,
 ) -> Result<HttpResponse, Error> {
     let cfg = get_config();
+    if query.exp_in as i64 > cfg.auth.presigned_url_max_exp_in {
+        return Ok(
+            HttpResponse::BadRequest().json(meta::http::HttpResponse::error(
+                http::StatusCode::BAD_REQUEST.into(),
+                format!(
+                    "exp_in can't be more than {} seconds",
+                    cfg.auth.presigned_url_max_exp_in
+                ),
+            )),
+        );
+    }
+    let allowed_cidr = query.allowed_cidr.as_deref().filter(|v| !v.is_empty());
+    if let Some(cidr) = allowed_cidr {
+        if cidr.trim().parse::<ipnetwork::IpNetwork>().is_err() {
+            return Ok(
+                HttpResponse::BadRequest().json(meta::http::HttpResponse::error(
+                    http::StatusCode::BAD_REQUEST.into(),
+                    format!("Invalid allowed_cidr: {cidr}"),
+                )),
+            );
+        }
+    }
     let time = chrono::Utc::now().timestamp();
     let password_ext_salt = cfg.auth.ext_auth_salt.as_str();
 
@@ -392,6 +529,11 @@ pub async fn get_presigned_url(
         &base_url,
         query.exp_in as i64,
         time,
+        PresignedScope {
+            org_id: query.org_id.as_deref().filter(|v| !v.is_empty()),
+            resource: query.resource.as_deref().filter(|v| !v.is_empty()),
+            allowed_cidr,
+        },
     );
 
     let payload = PresignedURLGeneratorResponse { url };
@@ -430,9 +572,23 @@ pub async fn get_auth(_req: HttpRequest) -> Result<HttpResponse, Error> {
         )
         .unwrap();
 
-        let mut request_time = None;
+        let mut presigned_auth = None;
         let mut expires_in = 300;
         let mut req_ts = 0;
+        let scope = PresignedScope {
+            org_id: query
+                .get("org_id")
+                .map(|v| v.as_str())
+                .filter(|v| !v.is_empty()),
+            resource: query
+                .get("resource")
+                .map(|v| v.as_str())
+                .filter(|v| !v.is_empty()),
+            allowed_cidr: query
+                .get("allowed_cidr")
+                .map(|v| v.as_str())
+                .filter(|v| !v.is_empty()),
+        };
 
         let mut audit_message = AuditMessage {
             user_email: "".to_string(),
@@ -456,7 +612,7 @@ pub async fn get_auth(_req: HttpRequest) -> Result<HttpResponse, Error> {
                             audit_unauthorized_error(audit_message).await;
                             return unauthorized_error(resp);
                         }
-                        request_time = Some(req_time_str);
+                        presigned_auth = Some(s);
                     }
                     None => {
                         audit_unauthorized_error(audit_message).await;
@@ -465,18 +621,19 @@ pub async fn get_auth(_req: HttpRequest) -> Result<HttpResponse, Error> {
                 };
 
                 match query.get("exp_in") {
-                    Some(exp_in_str) => {
-                        expires_in = exp_in_str.parse::<i64>().unwrap();
-                    }
+                    Some(exp_in_str) => match exp_in_str.parse::<i64>() {
+                        Ok(exp_in) => expires_in = exp_in,
+                        Err(_) => {
+                            audit_unauthorized_error(audit_message).await;
+                            return unauthorized_error(resp);
+                        }
+                    },
                     None => {
                         audit_unauthorized_error(audit_message).await;
                         return unauthorized_error(resp);
                     }
                 };
-                if Utc::now().timestamp() - req_ts > expires_in {
-                    audit_unauthorized_error(audit_message).await;
-                    return unauthorized_error(resp);
-                }
+                // expiry and signature are checked in validate_user_for_query_params
                 format!("q_auth {}", s)
             } else if let Some(auth_header) = _req.headers().get("Authorization") {
                 log::debug!("get_auth: auth header found: {:?}", auth_header);
@@ -500,11 +657,14 @@ pub async fn get_auth(_req: HttpRequest) -> Result<HttpResponse, Error> {
 
             let (name, password) = if let Some((name, password)) = get_user_from_token(&auth_header)
             {
-                let token_validation_response = match request_time {
-                    Some(req_ts) => {
+                let token_validation_response = match presigned_auth {
+                    Some(auth) => {
                         log::debug!("Validating user for query params");
-                        validate_user_for_query_params(&name, &password, Some(req_ts), expires_in)
-                            .await
+                        let client_ip = crate::common::utils::auth::client_ip(&_req);
+                        validate_user_for_query_params(
+                            &name, auth, req_ts, expires_in, scope, client_ip,
+                        )
+                        .await
                     }
                     None => {
                         log::debug!("Validating user for basic auth header");
@@ -523,9 +683,9 @@ pub async fn get_auth(_req: HttpRequest) -> Result<HttpResponse, Error> {
                             return unauthorized_error(resp);
                         }
                     }
-                    Err(_) => {
+                    Err(e) => {
                         audit_unauthorized_error(audit_message).await;
-                        return unauthorized_error(resp);
+                        return validation_error(e, resp);
                     }
                 }
             } else {
@@ -569,6 +729,9 @@ pub async fn get_auth(_req: HttpRequest) -> Result<HttpResponse, Error> {
                     refresh_token: "".to_string(),
                     request_time: req_ts,
                     expires_in,
+                    org_id: scope.org_id.map(String::from),
+                    resource: scope.resource.map(String::from),
+                    allowed_cidr: scope.allowed_cidr.map(String::from),
                 };
                 let expiry = cookie::time::OffsetDateTime::now_utc()
                     + cookie::time::Duration::seconds(req_ts);
@@ -641,6 +804,27 @@ fn unauthorized_error(mut resp: SignInResponse) -> Result<HttpResponse, Error> {
     Ok(HttpResponse::Unauthorized().json(resp))
 }
 
+/// Maps a failed credential validation to a response, a locked out user gets a 429 and a
+/// malformed presigned url a 400
+fn validation_error(
+    err: actix_web::Error,
+    mut resp: SignInResponse,
+) -> Result<HttpResponse, Error> {
+    match err.as_response_error().status_code() {
+        http::StatusCode::TOO_MANY_REQUESTS => {
+            resp.status = false;
+            resp.message = err.to_string();
+            Ok(HttpResponse::TooManyRequests().json(resp))
+        }
+        http::StatusCode::BAD_REQUEST => {
+            resp.status = false;
+            resp.message = "Malformed login url".to_string();
+            Ok(HttpResponse::BadRequest().json(resp))
+        }
+        _ => unauthorized_error(resp),
+    }
+}
+
 #[cfg(feature = "enterprise")]
 async fn audit_unauthorized_error(mut audit_message: AuditMessage) {
     use chrono::Utc;
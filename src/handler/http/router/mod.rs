@@ -335,6 +335,9 @@ pub fn get_service_routes(cfg: &mut web::ServiceConfig) {
             .service(users::delete)
             .service(users::update)
             .service(users::add_user_to_org)
+            .service(users::create_api_key)
+            .service(users::list_api_keys)
+            .service(users::revoke_api_key)
             .service(organization::org::organizations)
             .service(organization::settings::get)
             .service(organization::settings::create)
@@ -475,6 +478,7 @@ pub fn get_service_routes(cfg: &mut web::ServiceConfig) {
             .service(authz::fga::get_users_with_role)
             .service(authz::fga::delete_role)
             .service(authz::fga::delete_group)
+            .service(authz::fga::explain_permission)
             .service(users::list_roles)
             .service(clusters::list_clusters)
             .service(pipelines::save_pipeline)
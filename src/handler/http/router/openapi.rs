@@ -27,6 +27,9 @@ use crate::{common::meta, handler::http::request};
         request::users::update,
         request::users::delete,
         request::users::add_user_to_org,
+        request::users::create_api_key,
+        request::users::list_api_keys,
+        request::users::revoke_api_key,
         request::organization::org::organizations,
         request::organization::org::org_summary,
         request::organization::org::get_user_passcode,
@@ -194,6 +197,9 @@ use crate::{common::meta, handler::http::request};
             meta::user::UserResponse,
             meta::user::UpdateUser,
             meta::user::SignInResponse,
+            meta::user::ApiKeyRequest,
+            meta::user::ApiKeyResponse,
+            meta::user::ApiKeyList,
             meta::organization::OrgSummary,
             meta::organization::StreamSummary,
             meta::organization::OrganizationResponse,
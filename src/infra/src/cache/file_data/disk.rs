@@ -317,6 +317,17 @@ pub async fn exist(file: &str) -> bool {
     files.exist(file).await
 }
 
+/// Returns the size of the cached file
+#[inline]
+pub async fn get_size(file: &str) -> Option<usize> {
+    if !get_config().disk_cache.enabled {
+        return None;
+    }
+    let idx = get_bucket_idx(file);
+    let files = FILES[idx].read().await;
+    files.data.get_size(file)
+}
+
 #[inline]
 pub async fn set(trace_id: &str, file: &str, data: Bytes) -> Result<(), anyhow::Error> {
     if !get_config().disk_cache.enabled || is_local_disk_storage() {
@@ -258,6 +258,16 @@ pub async fn exist(file: &str) -> bool {
     files.exist(file).await
 }
 
+/// Returns the size of the cached file data
+#[inline]
+pub async fn get_size(file: &str) -> Option<usize> {
+    if !get_config().memory_cache.enabled {
+        return None;
+    }
+    let idx = get_bucket_idx(file);
+    DATA[idx].get(file).map(|data| data.value().len())
+}
+
 #[inline]
 pub async fn set(trace_id: &str, file: &str, data: Bytes) -> Result<(), anyhow::Error> {
     if !get_config().memory_cache.enabled {
@@ -18,7 +18,7 @@ pub mod memory;
 
 use std::collections::VecDeque;
 
-use hashbrown::HashSet;
+use hashbrown::HashMap;
 use hashlink::lru_cache::LruCache;
 
 const INITIAL_CACHE_SIZE: usize = 128;
@@ -32,7 +32,7 @@ pub enum CacheType {
 
 enum CacheStrategy {
     Lru(LruCache<String, usize>),
-    Fifo((VecDeque<(String, usize)>, HashSet<String>)),
+    Fifo((VecDeque<(String, usize)>, HashMap<String, usize>)),
 }
 
 impl CacheStrategy {
@@ -41,7 +41,7 @@ impl CacheStrategy {
             "lru" => CacheStrategy::Lru(LruCache::new_unbounded()),
             "fifo" => CacheStrategy::Fifo((
                 VecDeque::with_capacity(INITIAL_CACHE_SIZE),
-                HashSet::with_capacity(INITIAL_CACHE_SIZE),
+                HashMap::with_capacity(INITIAL_CACHE_SIZE),
             )),
             _ => CacheStrategy::Lru(LruCache::new_unbounded()),
         }
@@ -52,8 +52,8 @@ impl CacheStrategy {
             CacheStrategy::Lru(cache) => {
                 cache.insert(key, value);
             }
-            CacheStrategy::Fifo((queue, set)) => {
-                set.insert(key.clone());
+            CacheStrategy::Fifo((queue, sizes)) => {
+                sizes.insert(key.clone(), value);
                 queue.push_back((key, value));
             }
         }
@@ -62,21 +62,29 @@ impl CacheStrategy {
     fn remove(&mut self) -> Option<(String, usize)> {
         match self {
             CacheStrategy::Lru(cache) => cache.remove_lru(),
-            CacheStrategy::Fifo((queue, set)) => {
+            CacheStrategy::Fifo((queue, sizes)) => {
                 if queue.is_empty() {
                     return None;
                 }
                 let (key, size) = queue.pop_front().unwrap();
-                set.remove(&key);
+                sizes.remove(&key);
                 Some((key, size))
             }
         }
     }
 
+    /// Returns the size cached for the key
+    fn get_size(&self, key: &str) -> Option<usize> {
+        match self {
+            CacheStrategy::Lru(cache) => cache.peek(key).copied(),
+            CacheStrategy::Fifo((_, sizes)) => sizes.get(key).copied(),
+        }
+    }
+
     fn contains_key(&self, key: &str) -> bool {
         match self {
             CacheStrategy::Lru(cache) => cache.contains_key(key),
-            CacheStrategy::Fifo((_, set)) => set.contains(key),
+            CacheStrategy::Fifo((_, sizes)) => sizes.contains_key(key),
         }
     }
 
@@ -97,7 +105,7 @@ impl CacheStrategy {
     fn remove_key(&mut self, key: &str) -> Option<(String, usize)> {
         match self {
             CacheStrategy::Lru(cache) => cache.remove_entry(key),
-            CacheStrategy::Fifo((queue, set)) => {
+            CacheStrategy::Fifo((queue, sizes)) => {
                 if queue.is_empty() {
                     return None;
                 }
@@ -105,7 +113,7 @@ impl CacheStrategy {
                 while index < queue.len() {
                     if queue[index].0 == key {
                         let (k, v) = queue.remove(index).unwrap();
-                        set.remove(&k);
+                        sizes.remove(&k);
                         return Some((k, v));
                     }
                     index += 1;
@@ -133,6 +141,35 @@ pub async fn download(trace_id: &str, file: &str) -> Result<(), anyhow::Error> {
     }
 }
 
+/// Checks the file is already cached with the expected size, so it doesn't need a download. A
+/// cached file of another size is stale and is removed, so the next download replaces it. A size
+/// of 0 is unknown and only checks the file is cached.
+pub async fn is_cached(trace_id: &str, file: &str, size: usize) -> bool {
+    let cfg = config::get_config();
+    let (cached_size, memory_cache) = if cfg.memory_cache.enabled {
+        (memory::get_size(file).await, true)
+    } else if cfg.disk_cache.enabled {
+        (disk::get_size(file).await, false)
+    } else {
+        return false;
+    };
+    match cached_size {
+        Some(cached_size) if size == 0 || cached_size == size => true,
+        Some(_) => {
+            let ret = if memory_cache {
+                memory::remove(trace_id, file).await
+            } else {
+                disk::remove(trace_id, file).await
+            };
+            if let Err(e) = ret {
+                log::error!("[trace_id {trace_id}] remove stale cached file {file} error: {e}");
+            }
+            false
+        }
+        None => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -150,6 +187,20 @@ mod tests {
         assert!(!cache.contains_key(key2));
     }
 
+    #[test]
+    fn test_cache_get_size() {
+        for strategy in ["lru", "fifo"] {
+            let mut cache = CacheStrategy::new(strategy);
+            cache.insert("a".to_string(), 1);
+            cache.insert("b".to_string(), 2);
+            assert_eq!(cache.get_size("a"), Some(1), "{strategy}");
+            assert_eq!(cache.get_size("b"), Some(2), "{strategy}");
+            assert_eq!(cache.get_size("c"), None, "{strategy}");
+            cache.remove_key("a");
+            assert_eq!(cache.get_size("a"), None, "{strategy}");
+        }
+    }
+
     #[test]
     fn test_fifo_cache_miss() {
         let mut cache = CacheStrategy::new("fifo");
@@ -15,18 +15,21 @@
 
 use std::{
     cmp::min,
+    future::Future,
     sync::{
-        atomic::{AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicU8, Ordering},
         Arc,
     },
 };
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{cluster, get_config};
+use config::{cluster, get_config, metrics};
 use etcd_client::{
-    Certificate, DeleteOptions, EventType, GetOptions, Identity, SortOrder, SortTarget, TlsOptions,
+    Certificate, Compare, CompareOp, DeleteOptions, EventType, GetOptions, Identity, LockOptions,
+    PutOptions, SortOrder, SortTarget, TlsOptions, Txn, TxnOp, TxnOpResponse,
 };
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use hashbrown::HashMap;
 use tokio::{
     sync::{mpsc, OnceCell},
@@ -35,7 +38,7 @@ use tokio::{
 };
 
 use crate::{
-    db::{Event, EventData},
+    db::{Event, EventData, TtlGuard},
     dist_lock,
     errors::*,
 };
@@ -67,6 +70,64 @@ impl Etcd {
         }
     }
 
+    /// Yields `map(key, value)` for every key under `prefix`, reading `load_page_size` keys at a
+    /// time. Each page reads the prefix range from right after the last key of the previous
+    /// page, so keys written or deleted between pages can't repeat or skip any.
+    async fn list_pages<T, F>(
+        &self,
+        prefix: &str,
+        mut map: F,
+    ) -> Result<BoxStream<'static, Result<T>>>
+    where
+        T: Send + 'static,
+        F: FnMut(String, Bytes) -> T + Send + 'static,
+    {
+        let page_size = get_config().etcd.load_page_size;
+        let key = format!("{}{}", self.prefix, prefix);
+        let range_end = prefix_range_end(key.as_bytes());
+        let store_prefix = self.prefix.clone();
+        let client = get_etcd_client().await.clone();
+        // (client, key the page starts at)
+        let first_page = Some((client, key.into_bytes()));
+        let pages = futures::stream::try_unfold(first_page, move |page| {
+            let range_end = range_end.clone();
+            let store_prefix = store_prefix.clone();
+            async move {
+                let Some((mut client, from_key)) = page else {
+                    return Ok(None);
+                };
+                let opt = GetOptions::new()
+                    .with_range(range_end)
+                    .with_sort(SortTarget::Key, SortOrder::Ascend)
+                    .with_limit(page_size);
+                let resp = observe("list", client.get(from_key, Some(opt))).await?;
+                metrics::DB_LIST_PAGES.with_label_values(&["etcd"]).inc();
+                let mut items = Vec::with_capacity(resp.kvs().len());
+                for kv in resp.kvs() {
+                    let item_key = strip_key_prefix(&store_prefix, kv.key_str()?);
+                    items.push((item_key.to_string(), Bytes::from(kv.value().to_vec())));
+                }
+                // the smallest key after the last one of this page is that key followed by \0
+                let next_page = match resp.kvs().last() {
+                    Some(kv) if resp.more() => {
+                        let mut from_key = kv.key().to_vec();
+                        from_key.push(0);
+                        Some((client, from_key))
+                    }
+                    _ => None,
+                };
+                Ok::<_, Error>(Some((items, next_page)))
+            }
+        });
+        Ok(pages
+            .map_ok(move |items| {
+                let items = items.into_iter().map(|(key, value)| Ok(map(key, value)));
+                futures::stream::iter(items.collect::<Vec<_>>())
+            })
+            .try_flatten()
+            .boxed())
+    }
+
     async fn get_key_value(&self, key: &str) -> Result<(String, Bytes)> {
         let key = format!("{}{}", self.prefix, key);
         let mut client = get_etcd_client().await.clone();
@@ -74,15 +135,11 @@ impl Etcd {
             .with_prefix()
             .with_sort(SortTarget::Key, SortOrder::Descend)
             .with_limit(1);
-        let ret = client.get(key.as_str(), Some(opt)).await?;
+        let ret = observe("get", client.get(key.as_str(), Some(opt))).await?;
         if ret.kvs().is_empty() {
             return Err(Error::from(DbError::KeyNotExists(key)));
         }
-        let item_key = ret.kvs()[0]
-            .key_str()
-            .unwrap()
-            .strip_prefix(&self.prefix)
-            .unwrap();
+        let item_key = strip_key_prefix(&self.prefix, ret.kvs()[0].key_str().unwrap());
         Ok((
             item_key.to_string(),
             Bytes::from(ret.kvs()[0].value().to_vec()),
@@ -90,6 +147,43 @@ impl Etcd {
     }
 }
 
+/// Times the etcd operation `op`, counting it as an error when it fails
+async fn observe<T, E>(
+    op: &str,
+    fut: impl Future<Output = std::result::Result<T, E>>,
+) -> std::result::Result<T, E> {
+    let start = std::time::Instant::now();
+    let ret = fut.await;
+    metrics::DB_OPERATION_TIME
+        .with_label_values(&["etcd", op])
+        .observe(start.elapsed().as_secs_f64());
+    if ret.is_err() {
+        metrics::DB_OPERATION_ERRORS
+            .with_label_values(&["etcd", op])
+            .inc();
+    }
+    ret
+}
+
+/// The end of the range etcd reads for a prefix request: `prefix` with its last byte below
+/// 0xff incremented and the rest dropped, or `\0`, meaning no end, when there's no such byte.
+fn prefix_range_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+/// The key without the store prefix. A key outside of the prefix, which an empty prefix lists
+/// as well, is returned as is.
+fn strip_key_prefix<'a>(prefix: &str, key: &'a str) -> &'a str {
+    key.strip_prefix(prefix).unwrap_or(key)
+}
+
 impl Default for Etcd {
     fn default() -> Self {
         Self::new(&get_config().etcd.prefix)
@@ -119,6 +213,15 @@ impl super::Db for Etcd {
         })
     }
 
+    async fn health(&self) -> Result<()> {
+        super::with_health_timeout(async {
+            let mut client = get_etcd_client().await.clone();
+            client.status().await?;
+            Ok(())
+        })
+        .await
+    }
+
     async fn get(&self, key: &str) -> Result<Bytes> {
         let key = format!("{}{}", self.prefix, key);
         let mut client = get_etcd_client().await.clone();
@@ -126,7 +229,7 @@ impl super::Db for Etcd {
             .with_prefix()
             .with_sort(SortTarget::Key, SortOrder::Descend)
             .with_limit(1);
-        let ret = client.get(key.as_str(), Some(opt)).await?;
+        let ret = observe("get", client.get(key.as_str(), Some(opt))).await?;
         if ret.kvs().is_empty() {
             return Err(Error::from(DbError::KeyNotExists(key)));
         }
@@ -146,7 +249,7 @@ impl super::Db for Etcd {
             format!("{}{}", self.prefix, key)
         };
         let mut client = get_etcd_client().await.clone();
-        let _ = client.put(key, value, None).await?;
+        let _ = observe("put", client.put(key, value, None)).await?;
         Ok(())
     }
 
@@ -170,7 +273,7 @@ impl super::Db for Etcd {
         };
         log::info!("Acquired lock for cluster key: {}", lock_key);
 
-        // get value and update
+        // get value and update, both keys are written in one transaction
         let value = self.get_key_value(key).await.ok();
         let old_key = value.as_ref().map(|v| v.0.clone());
         let old_value = value.map(|v| v.1);
@@ -178,28 +281,27 @@ impl super::Db for Etcd {
             Err(e) => Err(e),
             Ok(None) => Ok(()),
             Ok(Some((value, new_value))) => {
+                let mut ops = Vec::with_capacity(2);
                 if let Some(value) = value {
-                    if let Err(e) = self.put(&old_key.unwrap(), value, need_watch, None).await {
-                        if let Err(e) = dist_lock::unlock(&locker).await {
-                            log::error!("dist_lock unlock err: {}", e);
-                        }
-                        log::info!("Released lock for cluster key: {}", lock_key);
-                        return Err(e);
-                    }
+                    let key = format!("{}{}", self.prefix, old_key.unwrap());
+                    ops.push(TxnOp::put(key, value.to_vec(), None));
                 }
                 if let Some((new_key, new_value, new_start_dt)) = new_value {
-                    if let Err(e) = self
-                        .put(&new_key, new_value, need_watch, new_start_dt)
+                    let new_key = match new_start_dt {
+                        Some(start_dt) => format!("{}{new_key}/{start_dt}", self.prefix),
+                        None => format!("{}{new_key}", self.prefix),
+                    };
+                    ops.push(TxnOp::put(new_key, new_value.to_vec(), None));
+                }
+                if ops.is_empty() {
+                    Ok(())
+                } else {
+                    let mut client = get_etcd_client().await.clone();
+                    observe("put", client.txn(Txn::new().and_then(ops)))
                         .await
-                    {
-                        if let Err(e) = dist_lock::unlock(&locker).await {
-                            log::error!("dist_lock unlock err: {}", e);
-                        }
-                        log::info!("Released lock for cluster key: {}", lock_key);
-                        return Err(e);
-                    }
+                        .map(|_| ())
+                        .map_err(Error::from)
                 }
-                Ok(())
             }
         };
 
@@ -211,6 +313,106 @@ impl super::Db for Etcd {
         ret
     }
 
+    async fn put_many(&self, items: Vec<(String, Bytes)>, _need_watch: bool) -> Result<()> {
+        let client = get_etcd_client().await.clone();
+        let chunk_size = get_config().etcd.max_txn_ops;
+        super::write_in_chunks(items, chunk_size, |chunk| {
+            let ops = chunk
+                .into_iter()
+                .map(|(key, value)| {
+                    TxnOp::put(format!("{}{}", self.prefix, key), value.to_vec(), None)
+                })
+                .collect::<Vec<_>>();
+            let mut client = client.clone();
+            async move {
+                observe("put", client.txn(Txn::new().and_then(ops))).await?;
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    async fn put_with_ttl(&self, key: &str, value: Bytes, ttl_secs: i64) -> Result<TtlGuard> {
+        let key = format!("{}{}", self.prefix, key);
+        let mut client = get_etcd_client().await.clone();
+        let lease_id = client.lease_grant(ttl_secs, None).await?.id();
+        let opt = PutOptions::new().with_lease(lease_id);
+        if let Err(e) = observe("put", client.put(key.as_str(), value.to_vec(), Some(opt))).await {
+            _ = client.lease_revoke(lease_id).await;
+            return Err(e.into());
+        }
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let keepalive_stopped = stopped.clone();
+        tokio::task::spawn(async move {
+            let stopper = || keepalive_stopped.load(Ordering::Relaxed);
+            if let Err(e) = keepalive_lease_id(lease_id, ttl_secs, stopper, || {}).await {
+                log::error!("[ETCD] lease of {key} lost: {e}");
+            }
+        });
+        Ok(TtlGuard::new(move || {
+            stopped.store(true, Ordering::Relaxed);
+            // without a runtime to revoke it the lease runs out on its own
+            if let Ok(rt) = tokio::runtime::Handle::try_current() {
+                rt.spawn(async move {
+                    let mut client = get_etcd_client().await.clone();
+                    if let Err(e) = client.lease_revoke(lease_id).await {
+                        log::error!("[ETCD] revoke lease {lease_id} error: {e}");
+                    }
+                });
+            }
+        }))
+    }
+
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<Bytes>,
+        new: Bytes,
+        _need_watch: bool,
+    ) -> Result<bool> {
+        let key = format!("{}{}", self.prefix, key);
+        let compare = match expected {
+            // a key that was never written or was deleted has version 0
+            None => Compare::version(key.as_str(), CompareOp::Equal, 0),
+            Some(expected) => Compare::value(key.as_str(), CompareOp::Equal, expected.to_vec()),
+        };
+        let txn = Txn::new()
+            .when([compare])
+            .and_then([TxnOp::put(key, new.to_vec(), None)]);
+        let mut client = get_etcd_client().await.clone();
+        Ok(observe("compare_and_swap", client.txn(txn))
+            .await?
+            .succeeded())
+    }
+
+    async fn move_key(&self, from: &str, to: &str) -> Result<()> {
+        let from = format!("{}{}", self.prefix, from);
+        let to = format!("{}{}", self.prefix, to);
+        let mut client = get_etcd_client().await.clone();
+        loop {
+            let resp = observe("get", client.get(from.as_str(), None)).await?;
+            let Some(kv) = resp.kvs().first() else {
+                return Err(Error::from(DbError::KeyNotExists(from)));
+            };
+            // values can't be copied on the server, so move the one just read only if it's still
+            // current, and read it again otherwise
+            let txn = Txn::new()
+                .when([Compare::mod_revision(
+                    from.as_str(),
+                    CompareOp::Equal,
+                    kv.mod_revision(),
+                )])
+                .and_then([
+                    TxnOp::put(to.as_str(), kv.value().to_vec(), None),
+                    TxnOp::delete(from.as_str(), None),
+                ]);
+            if observe("move", client.txn(txn)).await?.succeeded() {
+                return Ok(());
+            }
+        }
+    }
+
     async fn delete(
         &self,
         key: &str,
@@ -224,142 +426,54 @@ impl super::Db for Etcd {
         }
         let mut client = get_etcd_client().await.clone();
         let opt = with_prefix.then(|| DeleteOptions::new().with_prefix());
-        let _ = client.delete(key.as_str(), opt).await?.deleted();
+        let _ = observe("delete", client.delete(key.as_str(), opt))
+            .await?
+            .deleted();
         Ok(())
     }
 
-    async fn list(&self, prefix: &str) -> Result<HashMap<String, Bytes>> {
-        let cfg = get_config();
-        let mut result = HashMap::default();
-        let key = format!("{}{}", self.prefix, prefix);
+    async fn delete_batch(&self, keys: Vec<String>) -> Result<u64> {
         let mut client = get_etcd_client().await.clone();
-        let mut opt = GetOptions::new()
-            .with_prefix()
-            .with_sort(SortTarget::Key, SortOrder::Ascend)
-            .with_limit(cfg.etcd.load_page_size);
-        let mut resp = client.get(key.clone(), Some(opt.clone())).await?;
-        let mut first_call = true;
-        let mut have_next = true;
-        let mut last_key = String::new();
-        loop {
-            let kvs_num = resp.kvs().len() as i64;
-            if kvs_num < cfg.etcd.load_page_size {
-                have_next = false;
-            }
-            for kv in resp.kvs() {
-                let item_key = kv.key_str().unwrap();
-                if !item_key.starts_with(&key) {
-                    have_next = false;
-                    break;
-                }
-                if item_key.eq(last_key.as_str()) {
-                    continue;
+        let mut deleted = 0;
+        for keys in keys.chunks(get_config().etcd.max_txn_ops) {
+            let ops = keys
+                .iter()
+                .map(|key| TxnOp::delete(format!("{}{}", self.prefix, key), None))
+                .collect::<Vec<_>>();
+            let resp = observe("delete", client.txn(Txn::new().and_then(ops))).await?;
+            for op in resp.op_responses() {
+                if let TxnOpResponse::Delete(resp) = op {
+                    deleted += resp.deleted() as u64;
                 }
-                let item_key = item_key.strip_prefix(&self.prefix).unwrap();
-                result.insert(item_key.to_string(), Bytes::from(kv.value().to_vec()));
             }
-            tokio::task::yield_now().await; // yield to other tasks
+        }
+        Ok(deleted)
+    }
 
-            if !have_next {
-                break;
-            }
-            if first_call {
-                first_call = false;
-                opt = opt.with_from_key();
-            }
-            last_key = resp.kvs().last().unwrap().key_str().unwrap().to_string();
-            resp = client.get(last_key.clone(), Some(opt.clone())).await?;
+    async fn list(&self, prefix: &str) -> Result<HashMap<String, Bytes>> {
+        let mut result = HashMap::default();
+        let mut items = self.list_stream(prefix).await?;
+        while let Some((key, value)) = items.try_next().await? {
+            result.insert(key, value);
         }
         Ok(result)
     }
 
-    async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
-        let cfg = get_config();
-        let mut result = Vec::new();
-        let key = format!("{}{}", self.prefix, prefix);
-        let mut client = get_etcd_client().await.clone();
-        let mut opt = GetOptions::new()
-            .with_prefix()
-            .with_sort(SortTarget::Key, SortOrder::Ascend)
-            .with_limit(cfg.etcd.load_page_size);
-        let mut resp = client.get(key.clone(), Some(opt.clone())).await?;
-        let mut first_call = true;
-        let mut have_next = true;
-        let mut last_key = String::new();
-        loop {
-            let kvs_num = resp.kvs().len() as i64;
-            if kvs_num < cfg.etcd.load_page_size {
-                have_next = false;
-            }
-            for kv in resp.kvs() {
-                let item_key = kv.key_str().unwrap();
-                if !item_key.starts_with(&key) {
-                    have_next = false;
-                    break;
-                }
-                if item_key.eq(last_key.as_str()) {
-                    continue;
-                }
-                let item_key = item_key.strip_prefix(&self.prefix).unwrap();
-                result.push(item_key.to_string());
-            }
-            tokio::task::yield_now().await; // yield to other tasks
+    async fn list_stream(
+        &self,
+        prefix: &str,
+    ) -> Result<BoxStream<'static, Result<(String, Bytes)>>> {
+        self.list_pages(prefix, |key, value| (key, value)).await
+    }
 
-            if !have_next {
-                break;
-            }
-            if first_call {
-                first_call = false;
-                opt = opt.with_from_key();
-            }
-            last_key = resp.kvs().last().unwrap().key_str().unwrap().to_string();
-            resp = client.get(last_key.clone(), Some(opt.clone())).await?;
-        }
-        Ok(result)
+    async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let keys = self.list_pages(prefix, |key, _| key).await?;
+        keys.try_collect().await
     }
 
     async fn list_values(&self, prefix: &str) -> Result<Vec<Bytes>> {
-        let cfg = get_config();
-        let mut result = Vec::new();
-        let key = format!("{}{}", self.prefix, prefix);
-        let mut client = get_etcd_client().await.clone();
-        let mut opt = GetOptions::new()
-            .with_prefix()
-            .with_sort(SortTarget::Key, SortOrder::Ascend)
-            .with_limit(cfg.etcd.load_page_size);
-        let mut resp = client.get(key.clone(), Some(opt.clone())).await?;
-        let mut first_call = true;
-        let mut have_next = true;
-        let mut last_key = String::new();
-        loop {
-            let kvs_num = resp.kvs().len() as i64;
-            if kvs_num < cfg.etcd.load_page_size {
-                have_next = false;
-            }
-            for kv in resp.kvs() {
-                let item_key = kv.key_str().unwrap();
-                if !item_key.starts_with(&key) {
-                    have_next = false;
-                    break;
-                }
-                if item_key.eq(last_key.as_str()) {
-                    continue;
-                }
-                result.push(Bytes::from(kv.value().to_vec()));
-            }
-            tokio::task::yield_now().await; // yield to other tasks
-
-            if !have_next {
-                break;
-            }
-            if first_call {
-                first_call = false;
-                opt = opt.with_from_key();
-            }
-            last_key = resp.kvs().last().unwrap().key_str().unwrap().to_string();
-            resp = client.get(last_key.clone(), Some(opt.clone())).await?;
-        }
-        Ok(result)
+        let values = self.list_pages(prefix, |_, value| value).await?;
+        values.try_collect().await
     }
 
     async fn list_values_by_start_dt(
@@ -367,136 +481,262 @@ impl super::Db for Etcd {
         prefix: &str,
         start_dt: Option<(i64, i64)>,
     ) -> Result<Vec<(i64, Bytes)>> {
-        let cfg = get_config();
         if start_dt.is_none() || start_dt == Some((0, 0)) {
             let vals = self.list_values(prefix).await?;
             return Ok(vals.into_iter().map(|v| (0, v)).collect());
         }
 
         let (min_dt, max_dt) = start_dt.unwrap();
-        let mut result = Vec::new();
-        let key = format!("{}{}", self.prefix, prefix);
-        let mut client = get_etcd_client().await.clone();
-        let mut opt = GetOptions::new()
-            .with_prefix()
-            .with_sort(SortTarget::Key, SortOrder::Ascend)
-            .with_limit(cfg.etcd.load_page_size);
-        let mut resp = client.get(key.clone(), Some(opt.clone())).await?;
-        let mut first_call = true;
-        let mut have_next = true;
-        let mut last_key = String::new();
-        loop {
-            let kvs_num = resp.kvs().len() as i64;
-            if kvs_num < cfg.etcd.load_page_size {
-                have_next = false;
-            }
-            for kv in resp.kvs() {
-                let item_key = kv.key_str().unwrap();
-                if !item_key.starts_with(&key) {
-                    have_next = false;
-                    break;
-                }
-                if item_key.eq(last_key.as_str()) {
-                    continue;
-                }
-                let start_dt = item_key
+        let items = self
+            .list_pages(prefix, |key, value| {
+                let start_dt = key
                     .split('/')
                     .last()
                     .unwrap()
                     .parse::<i64>()
                     .unwrap_or_default();
-                if start_dt >= min_dt && start_dt <= max_dt {
-                    result.push((start_dt, Bytes::from(kv.value().to_vec())));
-                }
-            }
-            tokio::task::yield_now().await; // yield to other tasks
-
-            if !have_next {
-                break;
-            }
-            if first_call {
-                first_call = false;
-                opt = opt.with_from_key();
-            }
-            last_key = resp.kvs().last().unwrap().key_str().unwrap().to_string();
-            resp = client.get(last_key.clone(), Some(opt.clone())).await?;
-        }
-        Ok(result)
+                (start_dt, value)
+            })
+            .await?;
+        items
+            .try_filter(|(start_dt, _)| {
+                futures::future::ready(*start_dt >= min_dt && *start_dt <= max_dt)
+            })
+            .try_collect()
+            .await
     }
 
     async fn count(&self, prefix: &str) -> Result<i64> {
         let key = format!("{}{}", self.prefix, prefix);
         let mut client = get_etcd_client().await.clone();
         let opt = GetOptions::new().with_prefix().with_count_only();
-        let resp = client.get(key.clone(), Some(opt)).await?;
+        let resp = observe("count", client.get(key.clone(), Some(opt))).await?;
         Ok(resp.count())
     }
 
     async fn watch(&self, prefix: &str) -> Result<Arc<mpsc::Receiver<Event>>> {
         let (tx, rx) = mpsc::channel(1024);
-        let key = format!("{}{}", &self.prefix, prefix);
-        let self_prefix = self.prefix.to_string();
-        let _task: JoinHandle<Result<()>> = tokio::task::spawn(async move {
-            loop {
-                if cluster::is_offline() {
+        let source = EtcdWatchSource::new(&self.prefix, true);
+        let prefix = prefix.to_string();
+        let _task: JoinHandle<Result<()>> =
+            tokio::task::spawn(async move { watch_loop(source, prefix, tx).await });
+        Ok(Arc::new(rx))
+    }
+
+    async fn watch_key(&self, key: &str) -> Result<Arc<mpsc::Receiver<Event>>> {
+        let (tx, rx) = mpsc::channel(1024);
+        let source = EtcdWatchSource::new(&self.prefix, false);
+        let key = key.to_string();
+        let _task: JoinHandle<Result<()>> =
+            tokio::task::spawn(async move { watch_loop(source, key, tx).await });
+        Ok(Arc::new(rx))
+    }
+
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn add_start_dt_column(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A watch response reduced to what `watch_loop` acts on.
+#[derive(Debug)]
+enum WatchMessage {
+    /// The watch was created at this store revision
+    Created(i64),
+    /// A put or delete, with the revision that made it
+    Event(Event, i64),
+    /// The requested start revision has been compacted away
+    Compacted,
+}
+
+#[async_trait]
+trait WatchSource: Send {
+    /// Opens a watch on `prefix`, from `start_revision` when it is above zero.
+    async fn open(&mut self, prefix: &str, start_revision: i64) -> Result<()>;
+    /// The next messages of the open watch, `None` once the stream ends.
+    async fn next(&mut self) -> Result<Option<Vec<WatchMessage>>>;
+    /// Every key under `prefix`, and a revision the listing is at least as new as.
+    async fn list(&mut self, prefix: &str) -> Result<(HashMap<String, Bytes>, i64)>;
+}
+
+struct EtcdWatchSource {
+    store: Etcd,
+    /// false watches and lists only the exact key given as prefix
+    with_prefix: bool,
+    stream: Option<(etcd_client::Watcher, etcd_client::WatchStream)>,
+}
+
+impl EtcdWatchSource {
+    fn new(prefix: &str, with_prefix: bool) -> Self {
+        Self {
+            store: Etcd::new(prefix),
+            with_prefix,
+            stream: None,
+        }
+    }
+}
+
+#[async_trait]
+impl WatchSource for EtcdWatchSource {
+    async fn open(&mut self, prefix: &str, start_revision: i64) -> Result<()> {
+        self.stream = None;
+        let key = format!("{}{}", self.store.prefix, prefix);
+        let mut client = get_etcd_client().await.clone();
+        let mut opt = etcd_client::WatchOptions::new();
+        if self.with_prefix {
+            opt = opt.with_prefix();
+        }
+        if start_revision > 0 {
+            opt = opt.with_start_revision(start_revision);
+        }
+        self.stream = Some(client.watch(key, Some(opt)).await?);
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<Option<Vec<WatchMessage>>> {
+        let Some((_, stream)) = self.stream.as_mut() else {
+            return Ok(None);
+        };
+        let Some(resp) = stream.message().await? else {
+            return Ok(None);
+        };
+        if resp.compact_revision() > 0 {
+            return Ok(Some(vec![WatchMessage::Compacted]));
+        }
+        if resp.canceled() {
+            return Ok(None);
+        }
+        let mut messages = Vec::with_capacity(resp.events().len() + 1);
+        if resp.created() {
+            let revision = resp.header().map(|h| h.revision()).unwrap_or_default();
+            messages.push(WatchMessage::Created(revision));
+        }
+        for ev in resp.events() {
+            let Some(kv) = ev.kv() else {
+                continue;
+            };
+            let key = strip_key_prefix(&self.store.prefix, kv.key_str()?).to_string();
+            let event = match ev.event_type() {
+                EventType::Put => Event::Put(EventData {
+                    key,
+                    value: Some(Bytes::from(kv.value().to_vec())),
+                    start_dt: None,
+                }),
+                EventType::Delete => Event::Delete(EventData {
+                    key,
+                    value: None,
+                    start_dt: None,
+                }),
+            };
+            messages.push(WatchMessage::Event(event, kv.mod_revision()));
+        }
+        Ok(Some(messages))
+    }
+
+    async fn list(&mut self, prefix: &str) -> Result<(HashMap<String, Bytes>, i64)> {
+        let mut client = get_etcd_client().await.clone();
+        let key = format!("{}{}", self.store.prefix, prefix);
+        if !self.with_prefix {
+            let resp = client.get(key, None).await?;
+            let revision = resp.header().map(|h| h.revision()).unwrap_or_default();
+            let mut items = HashMap::default();
+            for kv in resp.kvs() {
+                items.insert(prefix.to_string(), Bytes::from(kv.value().to_vec()));
+            }
+            return Ok((items, revision));
+        }
+        // pages are read at the latest revision, so take the revision first and
+        // let the resumed watch replay whatever changed while listing
+        let opt = GetOptions::new().with_prefix().with_count_only();
+        let resp = client.get(key, Some(opt)).await?;
+        let revision = resp.header().map(|h| h.revision()).unwrap_or_default();
+        Ok((super::Db::list(&self.store, prefix).await?, revision))
+    }
+}
+
+/// Forwards the events of a watch on `prefix` to `tx` until the node goes
+/// offline or the receiver is dropped.
+///
+/// A dropped stream is reopened after the last delivered revision so nothing
+/// that happened in between is missed. If that revision has been compacted the
+/// prefix is listed again and every key is sent as a Put before the watch
+/// resumes; deletes that fell into the compacted range cannot be recovered.
+async fn watch_loop(
+    mut source: impl WatchSource,
+    prefix: String,
+    tx: mpsc::Sender<Event>,
+) -> Result<()> {
+    let mut last_revision = 0;
+    loop {
+        if cluster::is_offline() || tx.is_closed() {
+            return Ok(());
+        }
+        let start_revision = if last_revision > 0 {
+            last_revision + 1
+        } else {
+            0
+        };
+        if let Err(e) = source.open(&prefix, start_revision).await {
+            log::error!("watching prefix: {}, error: {}", prefix, e);
+            time::sleep(time::Duration::from_secs(1)).await;
+            continue;
+        }
+        'stream: loop {
+            let messages = match source.next().await {
+                Ok(Some(messages)) => messages,
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("watching prefix: {}, get message error: {}", prefix, e);
                     break;
                 }
-                let mut client = get_etcd_client().await.clone();
-                let opt = etcd_client::WatchOptions::new().with_prefix();
-                let (mut _watcher, mut stream) =
-                    match client.watch(key.clone(), Some(opt.clone())).await {
-                        Ok((watcher, stream)) => (watcher, stream),
-                        Err(e) => {
-                            log::error!("watching prefix: {}, error: {}", key, e);
-                            time::sleep(time::Duration::from_secs(1)).await;
-                            continue;
+            };
+            for message in messages {
+                match message {
+                    WatchMessage::Created(revision) => {
+                        if last_revision == 0 {
+                            last_revision = revision;
                         }
-                    };
-                loop {
-                    let resp = match stream.message().await {
-                        Ok(resp) => resp,
-                        Err(e) => {
-                            log::error!("watching prefix: {}, get message error: {}", key, e);
-                            break;
+                    }
+                    WatchMessage::Event(event, revision) => {
+                        if observe("watch", tx.send(event)).await.is_err() {
+                            return Ok(());
                         }
-                    };
-                    if let Some(ev) = resp {
-                        for ev in ev.events() {
-                            let kv = ev.kv().unwrap();
-                            let item_key = kv.key_str().unwrap();
-                            let item_key = item_key.strip_prefix(&self_prefix).unwrap();
-                            match ev.event_type() {
-                                EventType::Put => tx
-                                    .send(Event::Put(EventData {
-                                        key: item_key.to_string(),
-                                        value: Some(Bytes::from(kv.value().to_vec())),
-                                        start_dt: None,
-                                    }))
-                                    .await
-                                    .unwrap(),
-                                EventType::Delete => tx
-                                    .send(Event::Delete(EventData {
-                                        key: item_key.to_string(),
-                                        value: None,
-                                        start_dt: None,
-                                    }))
-                                    .await
-                                    .unwrap(),
+                        last_revision = last_revision.max(revision);
+                    }
+                    WatchMessage::Compacted => {
+                        log::warn!(
+                            "watching prefix: {}, revision {} was compacted, reloading",
+                            prefix,
+                            start_revision
+                        );
+                        let (items, revision) = match source.list(&prefix).await {
+                            Ok(ret) => ret,
+                            Err(e) => {
+                                log::error!("watching prefix: {}, list error: {}", prefix, e);
+                                time::sleep(time::Duration::from_secs(1)).await;
+                                break 'stream;
+                            }
+                        };
+                        let mut items = items.into_iter().collect::<Vec<_>>();
+                        items.sort_by(|a, b| a.0.cmp(&b.0));
+                        for (key, value) in items {
+                            let event = Event::Put(EventData {
+                                key,
+                                value: Some(value),
+                                start_dt: None,
+                            });
+                            if observe("watch", tx.send(event)).await.is_err() {
+                                return Ok(());
                             }
                         }
+                        last_revision = revision;
+                        break 'stream;
                     }
                 }
             }
-            Ok(())
-        });
-        Ok(Arc::new(rx))
-    }
-
-    async fn close(&self) -> Result<()> {
-        Ok(())
-    }
-    async fn add_start_dt_column(&self) -> Result<()> {
-        Ok(())
+        }
     }
 }
 
@@ -522,18 +762,86 @@ pub async fn connect() -> etcd_client::Client {
         let client_cert = tokio::fs::read(&cfg.etcd.cert_file).await.unwrap();
         let client_key = tokio::fs::read(&cfg.etcd.key_file).await.unwrap();
         let client_identity = Identity::from_pem(client_cert, client_key);
-        let tls = TlsOptions::new()
-            .domain_name(&cfg.etcd.domain_name)
+        let mut tls = TlsOptions::new()
             .ca_certificate(server_root_ca_cert)
             .identity(client_identity);
+        // without an override every endpoint is verified against its own host
+        if !cfg.etcd.domain_name.is_empty() {
+            tls = tls.domain_name(&cfg.etcd.domain_name);
+        }
         opts = opts.with_tls(tls);
     }
-    let addrs = cfg.etcd.addr.split(',').collect::<Vec<&str>>();
+    let addrs = parse_endpoints(&cfg.etcd.addr).expect("Etcd endpoints parse failed");
     etcd_client::Client::connect(addrs, Some(opts))
         .await
         .expect("Etcd connect failed")
 }
 
+/// Splits the comma separated `ZO_ETCD_ADDR` into endpoints, each one `[scheme://]host[:port]`
+/// where an IPv6 host must be bracketed, e.g. `https://[::1]:2379`. Without a scheme the etcd
+/// client picks one from whether TLS is configured.
+fn parse_endpoints(addr: &str) -> Result<Vec<String>> {
+    let mut endpoints = Vec::new();
+    for endpoint in addr.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let invalid =
+            |reason: &str| Error::Message(format!("invalid etcd endpoint {endpoint}: {reason}"));
+        let (scheme, rest) = match endpoint.split_once("://") {
+            Some((scheme, rest)) => {
+                let scheme = scheme.to_lowercase();
+                if scheme != "http" && scheme != "https" {
+                    return Err(invalid("the scheme must be http or https"));
+                }
+                (Some(scheme), rest)
+            }
+            None => (None, endpoint),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = if let Some(rest) = rest.strip_prefix('[') {
+            let (host, port) = rest
+                .split_once(']')
+                .ok_or_else(|| invalid("missing ] after the IPv6 address"))?;
+            if host.parse::<std::net::Ipv6Addr>().is_err() {
+                return Err(invalid("not an IPv6 address in brackets"));
+            }
+            let port = match port {
+                "" => None,
+                port => Some(
+                    port.strip_prefix(':')
+                        .ok_or_else(|| invalid("expected :port after the IPv6 address"))?,
+                ),
+            };
+            (format!("[{host}]"), port)
+        } else {
+            match rest.split(':').count() {
+                1 => (rest.to_string(), None),
+                2 => {
+                    let (host, port) = rest.split_once(':').unwrap();
+                    (host.to_string(), Some(port))
+                }
+                _ => return Err(invalid("IPv6 addresses must be in brackets")),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        let mut url = match scheme {
+            Some(scheme) => format!("{scheme}://{host}"),
+            None => host,
+        };
+        if let Some(port) = port {
+            if port.parse::<u16>().is_err() {
+                return Err(invalid("invalid port"));
+            }
+            url = format!("{url}:{port}");
+        }
+        endpoints.push(url);
+    }
+    if endpoints.is_empty() {
+        return Err(Error::Message("no etcd endpoint configured".to_string()));
+    }
+    Ok(endpoints)
+}
+
 pub async fn keepalive_connection() -> Result<()> {
     loop {
         if cluster::is_offline() {
@@ -560,9 +868,14 @@ pub async fn keepalive_connection() -> Result<()> {
     Ok(())
 }
 
-pub async fn keepalive_lease_id<F>(id: i64, ttl: i64, stopper: F) -> Result<()>
+/// Keeps the lease `id` alive until `stopper` returns true.
+///
+/// `on_lost` is called once the lease has expired or been revoked, so the caller can
+/// grant a new one and put back the keys attached to it, before the error is returned.
+pub async fn keepalive_lease_id<F, L>(id: i64, ttl: i64, stopper: F, on_lost: L) -> Result<()>
 where
     F: Fn() -> bool,
+    L: Fn(),
 {
     let mut ttl_keep_alive = min(10, (ttl / 2) as u64);
     loop {
@@ -595,6 +908,7 @@ where
                 Ok(v) => {
                     if v.unwrap().ttl() == 0 {
                         log::error!("lease {:?} keep alive ttl is 0", id);
+                        on_lost();
                         return Err(Error::from(etcd_client::Error::LeaseKeepAliveError(
                             "lease expired or revoked".to_string(),
                         )));
@@ -613,10 +927,16 @@ where
     Ok(())
 }
 
+/// How long `Locker::try_lock` waits on its lock request when the lock was taken between
+/// checking for holders and asking for it.
+const TRY_LOCK_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
 pub(crate) struct Locker {
     key: String,
     lock_id: String,
     state: Arc<AtomicU8>, // 0: init, 1: locking, 2: release
+    lease_id: i64,
+    keepalive: Option<JoinHandle<Result<()>>>,
 }
 
 impl Locker {
@@ -625,11 +945,18 @@ impl Locker {
             key: format!("{}locker{}", get_config().etcd.prefix, key),
             lock_id: "".to_string(),
             state: Arc::new(AtomicU8::new(0)),
+            lease_id: 0,
+            keepalive: None,
         }
     }
 
     /// lock with timeout, 0 means use default timeout, unit: second
     pub(crate) async fn lock(&mut self, timeout: u64) -> Result<()> {
+        self.lock_with_ttl(timeout, get_config().etcd.lock_ttl)
+            .await
+    }
+
+    async fn lock_with_ttl(&mut self, timeout: u64, ttl: i64) -> Result<()> {
         let cfg = get_config();
         let mut client = get_etcd_client().await.clone();
         let mut last_err = None;
@@ -642,8 +969,10 @@ impl Locker {
         if n < 1 {
             n = 1;
         }
+        self.grant_lease(&mut client, ttl).await?;
+        let opts = LockOptions::new().with_lease(self.lease_id);
         for _ in 0..n {
-            match client.lock(self.key.as_str(), None).await {
+            match observe("lock", client.lock(self.key.as_str(), Some(opts.clone()))).await {
                 Ok(resp) => {
                     self.lock_id = String::from_utf8_lossy(resp.key()).to_string();
                     self.state.store(1, Ordering::SeqCst);
@@ -659,6 +988,7 @@ impl Locker {
             };
         }
         if let Some(err) = last_err {
+            self.revoke_lease(&mut client).await;
             return Err(Error::Message(format!(
                 "etcd lock for key: {}, error: {}",
                 self.key, err
@@ -667,30 +997,435 @@ impl Locker {
         Ok(())
     }
 
+    /// Takes the lock only if nobody holds or waits for it, returns whether it was taken.
+    pub(crate) async fn try_lock(&mut self) -> Result<bool> {
+        self.try_lock_with_ttl(get_config().etcd.lock_ttl).await
+    }
+
+    async fn try_lock_with_ttl(&mut self, ttl: i64) -> Result<bool> {
+        let mut client = get_etcd_client().await.clone();
+        // etcd keeps one key per holder or waiter under `{name}/`, the oldest one holds it
+        let opts = GetOptions::new().with_prefix().with_count_only();
+        let resp = observe("get", client.get(format!("{}/", self.key), Some(opts))).await?;
+        if resp.count() > 0 {
+            return Ok(false);
+        }
+        self.grant_lease(&mut client, ttl).await?;
+        let opts = LockOptions::new().with_lease(self.lease_id);
+        let req = observe("lock", client.lock(self.key.as_str(), Some(opts)));
+        match time::timeout(TRY_LOCK_TIMEOUT, req).await {
+            Ok(Ok(resp)) => {
+                self.lock_id = String::from_utf8_lossy(resp.key()).to_string();
+                self.state.store(1, Ordering::SeqCst);
+                Ok(true)
+            }
+            Ok(Err(err)) => {
+                self.revoke_lease(&mut client).await;
+                Err(Error::Message(format!(
+                    "etcd lock for key: {}, error: {}",
+                    self.key, err
+                )))
+            }
+            // somebody else got there first, revoking the lease withdraws our waiting key
+            Err(_) => {
+                self.revoke_lease(&mut client).await;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Grants the lease the lock key is attached to and keeps it alive until the lock is
+    /// released, so the lock of a holder that is gone expires `ttl` seconds later.
+    async fn grant_lease(&mut self, client: &mut etcd_client::Client, ttl: i64) -> Result<()> {
+        self.lease_id = observe("lock", client.lease_grant(ttl, None)).await?.id();
+        let (id, state) = (self.lease_id, self.state.clone());
+        self.keepalive = Some(tokio::task::spawn(async move {
+            keepalive_lease_id(id, ttl, move || state.load(Ordering::SeqCst) == 2, || {}).await
+        }));
+        Ok(())
+    }
+
+    async fn revoke_lease(&mut self, client: &mut etcd_client::Client) {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+        if let Err(err) = observe("unlock", client.lease_revoke(self.lease_id)).await {
+            log::error!(
+                "etcd revoke lock lease for key: {}, error: {}",
+                self.key,
+                err
+            );
+        }
+    }
+
     pub(crate) async fn unlock(&self) -> Result<()> {
         if self.state.load(Ordering::SeqCst) != 1 {
             return Ok(());
         }
         let mut client = get_etcd_client().await.clone();
-        if let Err(err) = client.unlock(self.lock_id.as_str()).await {
+        if let Err(err) = observe("unlock", client.unlock(self.lock_id.as_str())).await {
             log::error!("etcd unlock for key: {}, error: {}", self.key, err);
             return Err(Error::Message("etcd unlock error".to_string()));
         };
         self.state.store(2, Ordering::SeqCst);
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.abort();
+        }
+        // the lock key is gone already, the lease would only linger until its ttl
+        if let Err(err) = observe("unlock", client.lease_revoke(self.lease_id)).await {
+            log::warn!(
+                "etcd revoke lock lease for key: {}, error: {}",
+                self.key,
+                err
+            );
+        }
         Ok(())
     }
 }
 
+impl Drop for Locker {
+    /// A holder dropped without unlocking stops renewing the lease, so the lock is released
+    /// once its ttl runs out instead of staying held for the lifetime of the etcd session.
+    fn drop(&mut self) {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{super::Db, *};
 
+    #[test]
+    fn test_parse_endpoints() {
+        let samples = [
+            (
+                "https://[::1]:2379,https://etcd2:2379",
+                vec!["https://[::1]:2379", "https://etcd2:2379"],
+            ),
+            ("localhost:2379", vec!["localhost:2379"]),
+            (
+                " etcd1:2379 , HTTP://etcd2:2379/,",
+                vec!["etcd1:2379", "http://etcd2:2379"],
+            ),
+            ("[fe80::1]", vec!["[fe80::1]"]),
+            ("http://127.0.0.1", vec!["http://127.0.0.1"]),
+        ];
+        for (addr, endpoints) in samples {
+            assert_eq!(parse_endpoints(addr).unwrap(), endpoints, "{addr}");
+        }
+
+        for addr in [
+            "",
+            "::1:2379",
+            "[::1:2379",
+            "[etcd]:2379",
+            "[::1]2379",
+            "grpc://etcd:2379",
+            "etcd:port",
+            ":2379",
+        ] {
+            assert!(parse_endpoints(addr).is_err(), "{addr}");
+        }
+    }
+
+    /// Replays a fixed history, dropping the stream after every `batch` events.
+    struct ReplaySource {
+        /// the revision a watch without a start revision begins after
+        head: i64,
+        /// (revision, event) in revision order
+        history: Vec<(i64, Event)>,
+        /// start revisions below this are reported as compacted
+        compacted: i64,
+        batch: usize,
+        queue: std::collections::VecDeque<Result<Vec<WatchMessage>>>,
+        opens: Arc<parking_lot::Mutex<Vec<i64>>>,
+    }
+
+    #[async_trait]
+    impl WatchSource for ReplaySource {
+        async fn open(&mut self, _prefix: &str, start_revision: i64) -> Result<()> {
+            self.opens.lock().push(start_revision);
+            self.queue.clear();
+            if start_revision > 0 && start_revision < self.compacted {
+                self.queue.push_back(Ok(vec![WatchMessage::Compacted]));
+                return Ok(());
+            }
+            let from = if start_revision > 0 {
+                start_revision
+            } else {
+                self.queue
+                    .push_back(Ok(vec![WatchMessage::Created(self.head)]));
+                self.head + 1
+            };
+            let events = self.history.iter().filter(|(rev, _)| *rev >= from);
+            for (i, (rev, event)) in events.enumerate() {
+                if i == self.batch {
+                    self.queue
+                        .push_back(Err(Error::Message("stream reset".to_string())));
+                    break;
+                }
+                self.queue
+                    .push_back(Ok(vec![WatchMessage::Event(event.clone(), *rev)]));
+            }
+            Ok(())
+        }
+
+        async fn next(&mut self) -> Result<Option<Vec<WatchMessage>>> {
+            match self.queue.pop_front() {
+                Some(ret) => ret.map(Some),
+                None => futures::future::pending().await,
+            }
+        }
+
+        async fn list(&mut self, _prefix: &str) -> Result<(HashMap<String, Bytes>, i64)> {
+            let mut items = HashMap::default();
+            for (_, event) in self.history.iter() {
+                match event {
+                    Event::Put(data) => {
+                        items.insert(data.key.clone(), data.value.clone().unwrap());
+                    }
+                    Event::Delete(data) => {
+                        items.remove(&data.key);
+                    }
+                    Event::Empty => {}
+                }
+            }
+            let revision = self
+                .history
+                .last()
+                .map(|(rev, _)| *rev)
+                .unwrap_or(self.head);
+            Ok((items, revision))
+        }
+    }
+
+    fn put(key: &str, value: &str) -> Event {
+        Event::Put(EventData {
+            key: key.to_string(),
+            value: Some(Bytes::from(value.to_string())),
+            start_dt: None,
+        })
+    }
+
+    fn delete(key: &str) -> Event {
+        Event::Delete(EventData {
+            key: key.to_string(),
+            value: None,
+            start_dt: None,
+        })
+    }
+
+    async fn replay(source: ReplaySource, expected: usize) -> Vec<Event> {
+        let (tx, mut rx) = mpsc::channel(16);
+        let task = tokio::spawn(watch_loop(source, "/test/".to_string(), tx));
+        let mut events = Vec::new();
+        while events.len() < expected {
+            let ev = time::timeout(time::Duration::from_secs(5), rx.recv()).await;
+            events.push(ev.unwrap().unwrap());
+        }
+        // nothing beyond the expected events is delivered
+        let extra = time::timeout(time::Duration::from_millis(50), rx.recv()).await;
+        assert!(extra.is_err(), "{extra:?}");
+        task.abort();
+        events
+    }
+
+    #[tokio::test]
+    async fn test_etcd_watch_resume() {
+        let history = vec![
+            (3, put("/test/a", "1")),
+            (4, put("/test/b", "1")),
+            (5, delete("/test/a")),
+            (6, put("/test/b", "2")),
+            (7, put("/test/c", "1")),
+            (8, delete("/test/b")),
+            (9, put("/test/a", "2")),
+        ];
+        let opens = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let source = ReplaySource {
+            head: 2,
+            history: history.clone(),
+            compacted: 0,
+            batch: 3,
+            queue: Default::default(),
+            opens: opens.clone(),
+        };
+        let events = replay(source, history.len()).await;
+        let expected = history.into_iter().map(|(_, ev)| ev).collect::<Vec<_>>();
+        assert_eq!(events, expected);
+        assert_eq!(*opens.lock(), vec![0, 6, 9]);
+    }
+
+    #[tokio::test]
+    async fn test_etcd_watch_compacted() {
+        let history = vec![
+            (1, put("/test/a", "1")),
+            (2, put("/test/b", "1")),
+            (3, delete("/test/a")),
+            (4, put("/test/c", "1")),
+            (5, put("/test/b", "2")),
+            (6, put("/test/d", "1")),
+        ];
+        let opens = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let source = ReplaySource {
+            head: 0,
+            history,
+            compacted: 5,
+            batch: 2,
+            queue: Default::default(),
+            opens: opens.clone(),
+        };
+        let events = replay(source, 5).await;
+        assert_eq!(
+            events,
+            vec![
+                put("/test/a", "1"),
+                put("/test/b", "1"),
+                // revision 3 was compacted, so the current keys are sent again
+                put("/test/b", "2"),
+                put("/test/c", "1"),
+                put("/test/d", "1"),
+            ]
+        );
+        assert_eq!(*opens.lock(), vec![0, 3, 7]);
+    }
+
     #[tokio::test]
     async fn test_etcd_prefix() {
         let client = Etcd::default();
         assert_eq!(client.prefix, "/zinc/observe".to_string());
     }
 
+    #[test]
+    fn test_etcd_empty_prefix() {
+        for prefix in ["", "/", "//"] {
+            assert_eq!(Etcd::new(prefix).prefix, "", "{prefix}");
+        }
+        let samples = [
+            ("", "/zinc/observe/user/root", "/zinc/observe/user/root"),
+            ("", "foreign", "foreign"),
+            ("/zinc/observe", "/zinc/observe/user/root", "/user/root"),
+            ("/zinc/observe", "/other/user/root", "/other/user/root"),
+        ];
+        for (prefix, key, expected) in samples {
+            assert_eq!(strip_key_prefix(prefix, key), expected, "{prefix} {key}");
+        }
+    }
+
+    #[test]
+    fn test_prefix_range_end() {
+        let samples: [(&[u8], &[u8]); 5] = [
+            (b"/user/", b"/user0"),
+            (b"a", b"b"),
+            (b"a\xff", b"b"),
+            (b"\xff\xff", b"\0"),
+            (b"", b"\0"),
+        ];
+        for (prefix, end) in samples {
+            assert_eq!(prefix_range_end(prefix), end, "{prefix:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_etcd_list_pages() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let client = Etcd::default();
+        let page_size = get_config().etcd.load_page_size as usize;
+        client
+            .delete_if_exists("/test/page", true, false)
+            .await
+            .unwrap();
+        // every key is followed by the keys sorting right after it, so some of them land on
+        // either side of a page boundary
+        let mut keys = Vec::new();
+        for i in 0..page_size {
+            let key = format!("/test/page/{i:05}");
+            keys.push(format!("{key}\0"));
+            keys.push(format!("{key}/0"));
+            keys.push(key);
+        }
+        keys.sort();
+        for key in keys.iter() {
+            client
+                .put(key, Bytes::from(key.clone()), false, None)
+                .await
+                .unwrap();
+        }
+        // keys next to the prefix but outside it
+        for key in ["/test/page", "/test/page.", "/test/page0"] {
+            client
+                .put(key, Bytes::from(key), false, None)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(client.list_keys("/test/page/").await.unwrap(), keys);
+        let values = client.list_values("/test/page/").await.unwrap();
+        assert_eq!(
+            values,
+            keys.iter()
+                .map(|k| Bytes::from(k.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        // deleting the last key read doesn't make the next page skip or repeat keys
+        let mut items = client.list_stream("/test/page/").await.unwrap();
+        let mut listed = Vec::new();
+        for _ in 0..page_size {
+            listed.push(items.try_next().await.unwrap().unwrap().0);
+        }
+        client
+            .delete(listed.last().unwrap(), false, false, None)
+            .await
+            .unwrap();
+        while let Some((key, _)) = items.try_next().await.unwrap() {
+            listed.push(key);
+        }
+        assert_eq!(listed, keys);
+
+        for key in ["/test/page", "/test/page.", "/test/page0"] {
+            client.delete(key, false, false, None).await.unwrap();
+        }
+        client
+            .delete_if_exists("/test/page/", true, false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_etcd_metrics() {
+        observe("test_ok", async { Ok::<_, ()>(()) }).await.unwrap();
+        observe("test_err", async { Err::<(), _>(()) })
+            .await
+            .unwrap_err();
+        for (op, errors) in [("test_ok", 0), ("test_err", 1)] {
+            let time = metrics::DB_OPERATION_TIME.with_label_values(&["etcd", op]);
+            assert_eq!(time.get_sample_count(), 1, "{op}");
+            let count = metrics::DB_OPERATION_ERRORS.with_label_values(&["etcd", op]);
+            assert_eq!(count.get(), errors, "{op}");
+        }
+
+        if get_config().common.local_mode {
+            return;
+        }
+        let client = Etcd::default();
+        client
+            .put("/test/metrics/a", Bytes::from("a"), false, None)
+            .await
+            .unwrap();
+        client.get("/test/metrics/a").await.unwrap();
+        client.list("/test/metrics/").await.unwrap();
+        for op in ["put", "get", "list"] {
+            let time = metrics::DB_OPERATION_TIME.with_label_values(&["etcd", op]);
+            assert!(time.get_sample_count() > 0, "{op}");
+        }
+        assert!(metrics::DB_LIST_PAGES.with_label_values(&["etcd"]).get() > 0);
+    }
+
     #[tokio::test]
     async fn test_etcd_count() {
         if get_config().common.local_mode {
@@ -712,4 +1447,187 @@ mod tests {
         let count = client.count("/test/count").await.unwrap();
         assert_eq!(count, 3);
     }
+
+    #[tokio::test]
+    async fn test_etcd_move_key() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let client = Etcd::default();
+        let (from, to) = ("/test/move/from", "/test/move/to");
+        client
+            .put(from, Bytes::from("dashboard"), false, None)
+            .await
+            .unwrap();
+        client.move_key(from, to).await.unwrap();
+        assert_eq!(client.get(to).await.unwrap(), Bytes::from("dashboard"));
+        assert_eq!(client.count(from).await.unwrap(), 0);
+        assert!(client.move_key(from, to).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_etcd_put_with_ttl() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let client = Etcd::default();
+        let key = "/test/ttl/node";
+        let guard = client
+            .put_with_ttl(key, Bytes::from("node"), 2)
+            .await
+            .unwrap();
+        // kept alive past the ttl while the guard is held
+        time::sleep(time::Duration::from_secs(4)).await;
+        assert_eq!(client.get(key).await.unwrap(), Bytes::from("node"));
+
+        drop(guard);
+        time::sleep(time::Duration::from_secs(3)).await;
+        assert_eq!(client.count(key).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_etcd_put_many() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let client = Etcd::default();
+        client
+            .delete_if_exists("/test/put_many/", true, false)
+            .await
+            .unwrap();
+        let items = (0..300)
+            .map(|i| (format!("/test/put_many/{i:03}"), Bytes::from(i.to_string())))
+            .collect::<Vec<_>>();
+        client.put_many(items.clone(), false).await.unwrap();
+        let values = client.list_values("/test/put_many/").await.unwrap();
+        assert_eq!(
+            values,
+            items
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+        );
+        let keys = client.list_keys("/test/put_many/").await.unwrap();
+        assert_eq!(client.delete_batch(keys).await.unwrap(), 300);
+    }
+
+    #[tokio::test]
+    async fn test_etcd_watch_key() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let client = Etcd::default();
+        let mut events = client.watch_key("/test/watch_key/a").await.unwrap();
+        let events = Arc::get_mut(&mut events).unwrap();
+        // give the watch time to be created
+        time::sleep(time::Duration::from_millis(500)).await;
+        for key in [
+            "/test/watch_key/ab",
+            "/test/watch_key/a/b",
+            "/test/watch_key/a",
+        ] {
+            client
+                .put(key, Bytes::from(key), false, None)
+                .await
+                .unwrap();
+        }
+        let ev = time::timeout(time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ev,
+            Event::Put(EventData {
+                key: "/test/watch_key/a".to_string(),
+                value: Some(Bytes::from("/test/watch_key/a")),
+                start_dt: None,
+            })
+        );
+        client
+            .delete_if_exists("/test/watch_key/", true, false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_etcd_compare_and_swap() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let client = Arc::new(Etcd::default());
+        let key = "/test/cas/key";
+        client.delete(key, false, false, None).await.unwrap();
+        assert!(
+            client
+                .put_if_absent(key, Bytes::from("0"), false)
+                .await
+                .unwrap()
+        );
+
+        let tasks = (1..=8).map(|i| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                client
+                    .compare_and_swap(
+                        key,
+                        Some(Bytes::from("0")),
+                        Bytes::from(i.to_string()),
+                        false,
+                    )
+                    .await
+                    .unwrap()
+            })
+        });
+        let won = futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .filter(|ret| *ret.as_ref().unwrap())
+            .count();
+        assert_eq!(won, 1);
+        assert_ne!(client.get(key).await.unwrap(), Bytes::from("0"));
+        assert!(
+            !client
+                .put_if_absent(key, Bytes::from("0"), false)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_etcd_try_lock() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let mut holder = Locker::new("/test/try_lock");
+        holder.lock(0).await.unwrap();
+        let mut other = Locker::new("/test/try_lock");
+        let start = std::time::Instant::now();
+        assert!(!other.try_lock().await.unwrap());
+        assert!(start.elapsed() < TRY_LOCK_TIMEOUT);
+        holder.unlock().await.unwrap();
+        assert!(other.try_lock().await.unwrap());
+        other.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_etcd_lock_ttl() {
+        if get_config().common.local_mode {
+            return;
+        }
+        let mut holder = Locker::new("/test/lock_ttl");
+        holder.lock_with_ttl(0, 2).await.unwrap();
+        // renewed while held
+        time::sleep(time::Duration::from_secs(4)).await;
+        let mut other = Locker::new("/test/lock_ttl");
+        assert!(!other.try_lock().await.unwrap());
+
+        // released within the ttl once the holder is gone without unlocking
+        drop(holder);
+        let start = std::time::Instant::now();
+        while !other.try_lock().await.unwrap() {
+            assert!(start.elapsed() < time::Duration::from_secs(5));
+            time::sleep(time::Duration::from_millis(100)).await;
+        }
+        other.unlock().await.unwrap();
+    }
 }
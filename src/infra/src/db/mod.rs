@@ -13,11 +13,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::{future::Future, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use config::{get_config, meta::meta_store::MetaStore};
+use futures::{stream::BoxStream, StreamExt};
 use hashbrown::HashMap;
 use tokio::sync::{mpsc, OnceCell};
 
@@ -32,6 +33,9 @@ pub mod sqlite;
 pub static NEED_WATCH: bool = true;
 pub static NO_NEED_WATCH: bool = false;
 
+/// How long [`Db::health`] waits for the store to answer
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
 static DEFAULT: OnceCell<Box<dyn Db>> = OnceCell::const_new();
 static CLUSTER_COORDINATOR: OnceCell<Box<dyn Db>> = OnceCell::const_new();
 static SUPER_CLUSTER: OnceCell<Box<dyn Db>> = OnceCell::const_new();
@@ -109,10 +113,41 @@ pub async fn create_table() -> Result<()> {
 pub type UpdateFn = dyn FnOnce(Option<Bytes>) -> Result<Option<(Option<Bytes>, Option<(String, Bytes, Option<i64>)>)>>
     + Send;
 
+/// Keeps a key written by [`Db::put_with_ttl`] alive
+#[must_use = "the key expires once the guard is dropped"]
+pub struct TtlGuard {
+    on_drop: Option<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl TtlGuard {
+    pub fn new(on_drop: impl FnOnce() + Send + Sync + 'static) -> Self {
+        Self {
+            on_drop: Some(Box::new(on_drop)),
+        }
+    }
+
+    /// A guard for a key that doesn't expire
+    pub fn noop() -> Self {
+        Self { on_drop: None }
+    }
+}
+
+impl Drop for TtlGuard {
+    fn drop(&mut self) {
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop();
+        }
+    }
+}
+
 #[async_trait]
 pub trait Db: Sync + Send + 'static {
     async fn create_table(&self) -> Result<()>;
     async fn stats(&self) -> Result<Stats>;
+
+    /// Checks the store is reachable right now with a cheap request, failing after
+    /// [`HEALTH_TIMEOUT`]. Used by the readiness probe.
+    async fn health(&self) -> Result<()>;
     async fn get(&self, key: &str) -> Result<Bytes>;
     async fn put(
         &self,
@@ -128,6 +163,70 @@ pub trait Db: Sync + Send + 'static {
         start_dt: Option<i64>,
         update_fn: Box<UpdateFn>,
     ) -> Result<()>;
+
+    /// Writes all `items`, stopping at the first failure with a [`DbError::BatchChunkFailed`]
+    /// naming the chunk. Etcd writes a transaction per `ZO_ETCD_MAX_TXN_OPS` items, so each
+    /// chunk is written entirely or not at all; the other stores write one item per chunk.
+    async fn put_many(&self, items: Vec<(String, Bytes)>, need_watch: bool) -> Result<()> {
+        write_in_chunks(items, 1, |chunk| async move {
+            for (key, value) in chunk {
+                self.put(&key, value, need_watch, None).await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Writes `value` at `key` for as long as the returned guard is held, the key expires
+    /// `ttl_secs` after the guard is dropped or the process dies. Only etcd supports it, with a
+    /// lease; the other stores put the key and it stays until it's deleted.
+    async fn put_with_ttl(&self, key: &str, value: Bytes, _ttl_secs: i64) -> Result<TtlGuard> {
+        self.put(key, value, NO_NEED_WATCH, None).await?;
+        Ok(TtlGuard::noop())
+    }
+
+    /// Writes `value` at `key` unless the key exists, returns whether it was written. See
+    /// [`Db::compare_and_swap`].
+    async fn put_if_absent(&self, key: &str, value: Bytes, need_watch: bool) -> Result<bool> {
+        self.compare_and_swap(key, None, value, need_watch).await
+    }
+
+    /// Writes `new` at `key` when the current value is `expected`, or when the key is missing
+    /// for `None`, and returns whether it was written. The compare and the write are atomic, of
+    /// concurrent swaps expecting the same value exactly one wins. `key` is exact, it isn't
+    /// resolved to the latest `start_dt` under it.
+    ///
+    /// Etcd and NATS compare on the server. The SQL stores run it in `get_for_update`, which
+    /// holds a lock on the key for the transaction.
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<Bytes>,
+        new: Bytes,
+        need_watch: bool,
+    ) -> Result<bool> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let new_key = key.to_string();
+        self.get_for_update(
+            key,
+            need_watch,
+            None,
+            Box::new(move |current| {
+                if current != expected {
+                    _ = tx.send(false);
+                    return Ok(None);
+                }
+                _ = tx.send(true);
+                match current {
+                    Some(_) => Ok(Some((Some(new), None))),
+                    None => Ok(Some((None, Some((new_key, new, None))))),
+                }
+            }),
+        )
+        .await?;
+        Ok(rx.await.unwrap_or_default())
+    }
+
     async fn delete(
         &self,
         key: &str,
@@ -144,7 +243,45 @@ pub trait Db: Sync + Send + 'static {
         }
     }
 
+    /// Deletes the exact keys, skipping the missing ones, and returns how many were deleted.
+    /// Etcd deletes them in a few transactions, the other stores one key at a time.
+    async fn delete_batch(&self, keys: Vec<String>) -> Result<u64> {
+        let mut deleted = 0;
+        for key in keys {
+            match self.get(&key).await {
+                Ok(_) => {}
+                Err(Error::DbError(DbError::KeyNotExists(_))) => continue,
+                Err(e) => return Err(e),
+            }
+            self.delete(&key, false, false, None).await?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Moves the value at `from` to `to`, failing when `from` doesn't exist. Etcd does it
+    /// in one transaction, the other stores write `to` and then delete `from`, which leaves both
+    /// keys behind if the second step fails.
+    async fn move_key(&self, from: &str, to: &str) -> Result<()> {
+        log::warn!("[DB] moving {from} to {to} is not atomic on this meta store");
+        let value = self.get(from).await?;
+        self.put(to, value, NO_NEED_WATCH, None).await?;
+        self.delete(from, false, NO_NEED_WATCH, None).await
+    }
+
     async fn list(&self, prefix: &str) -> Result<HashMap<String, Bytes>>;
+
+    /// Like `list`, but the entries are yielded as they are read so a large prefix isn't held in
+    /// memory at once. Only etcd reads them a page at a time, the other stores list the prefix
+    /// first.
+    async fn list_stream(
+        &self,
+        prefix: &str,
+    ) -> Result<BoxStream<'static, Result<(String, Bytes)>>> {
+        let items = self.list(prefix).await?;
+        Ok(futures::stream::iter(items.into_iter().map(Ok)).boxed())
+    }
+
     async fn list_keys(&self, prefix: &str) -> Result<Vec<String>>;
     async fn list_values(&self, prefix: &str) -> Result<Vec<Bytes>>;
     async fn list_values_by_start_dt(
@@ -152,12 +289,83 @@ pub trait Db: Sync + Send + 'static {
         prefix: &str,
         start_dt: Option<(i64, i64)>,
     ) -> Result<Vec<(i64, Bytes)>>;
+    /// The exact number of keys under `prefix`. Etcd and the SQL stores count on the server, NATS
+    /// lists the keys of the bucket.
     async fn count(&self, prefix: &str) -> Result<i64>;
+
+    /// An estimate of the number of keys under `prefix`, for sizing work without listing them.
+    /// It's the exact `count` unless the store can only count by listing the keys and has a
+    /// cheaper estimate.
+    async fn estimate_count(&self, prefix: &str) -> Result<i64> {
+        self.count(prefix).await
+    }
+
+    /// Sends the changes of every key starting with `prefix`.
     async fn watch(&self, prefix: &str) -> Result<Arc<mpsc::Receiver<Event>>>;
+
+    /// Sends the changes of exactly `key`, not of the keys it is a prefix of as `watch` does.
+    /// Etcd watches the single key, the other stores watch the prefix and drop the events of
+    /// the other keys.
+    async fn watch_key(&self, key: &str) -> Result<Arc<mpsc::Receiver<Event>>> {
+        let mut events = self.watch(key).await?;
+        let (tx, rx) = mpsc::channel(1024);
+        let key = key.to_string();
+        tokio::task::spawn(async move {
+            let events = Arc::get_mut(&mut events).unwrap();
+            while let Some(ev) = events.recv().await {
+                let matched = match &ev {
+                    Event::Put(data) | Event::Delete(data) => data.key == key,
+                    Event::Empty => false,
+                };
+                if matched && tx.send(ev).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Arc::new(rx))
+    }
+
     async fn close(&self) -> Result<()>;
     async fn add_start_dt_column(&self) -> Result<()>;
 }
 
+/// Writes `items` a chunk of `chunk_size` at a time with `write_chunk`, stopping at the first
+/// chunk that fails
+pub(crate) async fn write_in_chunks<T, F, Fut>(
+    items: Vec<T>,
+    chunk_size: usize,
+    mut write_chunk: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut items = items.into_iter().peekable();
+    let mut written = 0;
+    let mut chunk_id = 0;
+    while items.peek().is_some() {
+        let chunk = items.by_ref().take(chunk_size.max(1)).collect::<Vec<_>>();
+        let len = chunk.len();
+        if let Err(e) = write_chunk(chunk).await {
+            return Err(Error::from(DbError::BatchChunkFailed(
+                chunk_id,
+                written,
+                e.to_string(),
+            )));
+        }
+        written += len;
+        chunk_id += 1;
+    }
+    Ok(())
+}
+
+/// Fails a health probe that doesn't complete within [`HEALTH_TIMEOUT`]
+async fn with_health_timeout(probe: impl Future<Output = Result<()>>) -> Result<()> {
+    tokio::time::timeout(HEALTH_TIMEOUT, probe)
+        .await
+        .map_err(|_| Error::Message(format!("health check timed out after {HEALTH_TIMEOUT:?}")))?
+}
+
 pub fn parse_key(mut key: &str) -> (String, String, String) {
     let mut module = "".to_string();
     let mut key1 = "".to_string();
@@ -289,4 +497,215 @@ mod tests {
         assert_eq!(db.list_keys("/foo/del/").await.unwrap().len(), 3);
         assert_eq!(db.list_values("/foo/del/").await.unwrap().len(), 3);
     }
+
+    #[tokio::test]
+    async fn test_delete_batch() {
+        create_table().await.unwrap();
+        let db = get_db().await;
+        let hello = Bytes::from("hello");
+        for key in ["/foo/batch/bar1", "/foo/batch/bar2", "/foo/batch/bar3"] {
+            db.put(key, hello.clone(), false, None).await.unwrap();
+        }
+
+        let deleted = db
+            .delete_batch(vec![
+                "/foo/batch/bar1".to_string(),
+                "/foo/batch/bar3".to_string(),
+                "/foo/batch/missing".to_string(),
+                // exact keys, not prefixes
+                "/foo/batch/bar".to_string(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(
+            db.list_keys("/foo/batch/").await.unwrap(),
+            vec!["/foo/batch/bar2".to_string()]
+        );
+        assert_eq!(db.delete_batch(vec![]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_swap() {
+        create_table().await.unwrap();
+        let db = get_db().await;
+        let key = "/foo/cas/bar";
+        db.delete_if_exists(key, false, false).await.unwrap();
+
+        // concurrent writers race for a missing key, then for the value the winner wrote
+        let mut expected: Option<Bytes> = None;
+        for _ in 0..2 {
+            let mut tasks = Vec::new();
+            for i in 0..8 {
+                let expected = expected.clone();
+                tasks.push(tokio::spawn(async move {
+                    let value = Bytes::from(format!("writer{i}"));
+                    let won = match expected {
+                        None => db.put_if_absent(key, value.clone(), false).await,
+                        Some(expected) => {
+                            db.compare_and_swap(key, Some(expected), value.clone(), false)
+                                .await
+                        }
+                    };
+                    (won.unwrap(), value)
+                }));
+            }
+            let winners = futures::future::join_all(tasks)
+                .await
+                .into_iter()
+                .map(|ret| ret.unwrap())
+                .filter(|(won, _)| *won)
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>();
+            assert_eq!(winners.len(), 1, "{expected:?}");
+            assert_eq!(db.get(key).await.unwrap(), winners[0]);
+            expected = Some(winners[0].clone());
+        }
+
+        // a stale expectation or an existing key loses
+        let current = db.get(key).await.unwrap();
+        assert!(
+            !db.compare_and_swap(key, Some(Bytes::from("stale")), Bytes::from("x"), false)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !db.put_if_absent(key, Bytes::from("x"), false)
+                .await
+                .unwrap()
+        );
+        assert_eq!(db.get(key).await.unwrap(), current);
+    }
+
+    #[tokio::test]
+    async fn test_move_key() {
+        create_table().await.unwrap();
+        let db = get_db().await;
+        let (from, to) = ("/foo/move/from", "/foo/move/to");
+        db.delete_if_exists(to, false, false).await.unwrap();
+        db.put(from, Bytes::from("dashboard"), false, None)
+            .await
+            .unwrap();
+
+        db.move_key(from, to).await.unwrap();
+        assert_eq!(db.get(to).await.unwrap(), Bytes::from("dashboard"));
+        assert!(matches!(
+            db.get(from).await,
+            Err(Error::DbError(DbError::KeyNotExists(_)))
+        ));
+
+        // the source is gone now
+        assert!(db.move_key(from, to).await.is_err());
+        assert_eq!(db.get(to).await.unwrap(), Bytes::from("dashboard"));
+    }
+
+    #[tokio::test]
+    async fn test_put_with_ttl() {
+        create_table().await.unwrap();
+        let db = get_db().await;
+        let key = "/foo/ttl/bar";
+        let guard = db.put_with_ttl(key, Bytes::from("node"), 1).await.unwrap();
+        assert_eq!(db.get(key).await.unwrap(), Bytes::from("node"));
+        // without leases the key outlives the guard
+        drop(guard);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(db.get(key).await.unwrap(), Bytes::from("node"));
+        db.delete(key, false, false, None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_in_chunks() {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let count = |chunk: Vec<usize>| {
+            calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            async move {
+                match chunk.contains(&300) {
+                    true => Err(Error::Message("write failed".to_string())),
+                    false => Ok(()),
+                }
+            }
+        };
+        let samples = [
+            // (items, chunk size, calls, result)
+            (300, 128, 3, None),
+            (300, 1, 300, None),
+            (256, 128, 2, None),
+            (0, 128, 0, None),
+            (300, 0, 300, None),
+            (400, 128, 3, Some((2, 256))),
+            (400, 1, 301, Some((300, 300))),
+        ];
+        for (len, chunk_size, expected_calls, failed) in samples {
+            calls.store(0, std::sync::atomic::Ordering::Relaxed);
+            let ret = write_in_chunks((0..len).collect(), chunk_size, count).await;
+            assert_eq!(
+                calls.load(std::sync::atomic::Ordering::Relaxed),
+                expected_calls,
+                "{len} {chunk_size}"
+            );
+            match (ret, failed) {
+                (Ok(()), None) => {}
+                (
+                    Err(Error::DbError(DbError::BatchChunkFailed(chunk, written, _))),
+                    Some(expected),
+                ) => assert_eq!((chunk, written), expected, "{len} {chunk_size}"),
+                (ret, _) => panic!("{len} {chunk_size}: {ret:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_many() {
+        create_table().await.unwrap();
+        let db = get_db().await;
+        db.delete_if_exists("/foo/many/", true, false)
+            .await
+            .unwrap();
+        let items = (0..20)
+            .map(|i| (format!("/foo/many/{i:02}"), Bytes::from(i.to_string())))
+            .collect::<Vec<_>>();
+        db.put_many(items.clone(), false).await.unwrap();
+        for (key, value) in items {
+            assert_eq!(db.get(&key).await.unwrap(), value);
+        }
+        let keys = db.list_keys("/foo/many/").await.unwrap();
+        assert_eq!(db.delete_batch(keys).await.unwrap(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        create_table().await.unwrap();
+        get_db().await.health().await.unwrap();
+        get_coordinator().await.health().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_stream() {
+        use futures::TryStreamExt;
+
+        create_table().await.unwrap();
+        let db = get_db().await;
+        for key in ["/foo/stream/bar1", "/foo/stream/bar2", "/foo/stream/bar3"] {
+            db.put(key, Bytes::from(key), false, None).await.unwrap();
+        }
+        db.put("/foo/streams/bar", Bytes::from("other"), false, None)
+            .await
+            .unwrap();
+
+        let mut items: Vec<(String, Bytes)> = db
+            .list_stream("/foo/stream/")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        items.sort();
+        assert_eq!(
+            items,
+            ["/foo/stream/bar1", "/foo/stream/bar2", "/foo/stream/bar3"]
+                .into_iter()
+                .map(|key| (key.to_string(), Bytes::from(key)))
+                .collect::<Vec<_>>()
+        );
+    }
 }
@@ -179,6 +179,17 @@ impl super::Db for NatsDb {
         })
     }
 
+    async fn health(&self) -> Result<()> {
+        super::with_health_timeout(async {
+            let client = get_nats_client().await.clone();
+            client
+                .flush()
+                .await
+                .map_err(|e| Error::Message(format!("[NATS:health] flush error: {}", e)))
+        })
+        .await
+    }
+
     async fn get(&self, key: &str) -> Result<Bytes> {
         let (bucket, new_key) = get_bucket_by_key(&self.prefix, key).await?;
         let key = key_encode(new_key);
@@ -305,6 +316,47 @@ impl super::Db for NatsDb {
         ret
     }
 
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<Bytes>,
+        new: Bytes,
+        _need_watch: bool,
+    ) -> Result<bool> {
+        let (bucket, new_key) = get_bucket_by_key(&self.prefix, key).await?;
+        let key = key_encode(new_key);
+        let entry = |bucket: &jetstream::kv::Store| {
+            let key = key.clone();
+            let bucket = bucket.clone();
+            async move {
+                bucket.entry(key).await.map_err(|e| {
+                    Error::Message(format!("[NATS:compare_and_swap] bucket.entry error: {}", e))
+                })
+            }
+        };
+        // the revision is the sequence of the last message of the key, a delete or purge marker
+        // of a removed key included, 0 when it was never written
+        let (current, revision) = match entry(&bucket).await? {
+            Some(v) if v.operation == jetstream::kv::Operation::Put => (Some(v.value), v.revision),
+            Some(v) => (None, v.revision),
+            None => (None, 0),
+        };
+        if current != expected {
+            return Ok(false);
+        }
+        match bucket.update(&key, new, revision).await {
+            Ok(_) => Ok(true),
+            // the server rejects the update when the key was written since it was read
+            Err(e) => match entry(&bucket).await? {
+                Some(v) if v.revision != revision => Ok(false),
+                _ => Err(Error::Message(format!(
+                    "[NATS:compare_and_swap] bucket.update error: {}",
+                    e
+                ))),
+            },
+        }
+    }
+
     async fn delete(
         &self,
         key: &str,
@@ -549,6 +601,19 @@ impl super::Db for NatsDb {
         Ok(keys.len() as i64)
     }
 
+    /// A prefix covering a whole bucket is estimated by the message count of the bucket, which
+    /// also counts the kept history and delete markers. A prefix inside a bucket is counted.
+    async fn estimate_count(&self, prefix: &str) -> Result<i64> {
+        let (bucket, new_key) = get_bucket_by_key(&self.prefix, prefix).await?;
+        if !new_key.trim_matches('/').is_empty() {
+            return self.count(prefix).await;
+        }
+        let status = bucket.status().await.map_err(|e| {
+            Error::Message(format!("[NATS:estimate_count] bucket.status error: {}", e))
+        })?;
+        Ok(status.values() as i64)
+    }
+
     async fn watch(&self, prefix: &str) -> Result<Arc<mpsc::Receiver<Event>>> {
         let (tx, rx) = mpsc::channel(1024);
         let prefix = prefix.to_string();
@@ -683,6 +748,15 @@ impl Locker {
 
     /// lock with timeout, 0 means use default timeout, unit: second
     pub(crate) async fn lock(&mut self, timeout: u64) -> Result<()> {
+        self.acquire(timeout, true).await.map(|_| ())
+    }
+
+    /// Takes the lock only if nobody holds it, returns whether it was taken.
+    pub(crate) async fn try_lock(&mut self) -> Result<bool> {
+        self.acquire(0, false).await
+    }
+
+    async fn acquire(&mut self, timeout: u64, wait: bool) -> Result<bool> {
         let cfg = get_config();
         let (bucket, new_key) = get_bucket_by_key(&cfg.nats.prefix, &self.key).await?;
         let timeout = if timeout == 0 {
@@ -706,7 +780,14 @@ impl Locker {
             }
         };
         drop(local_mutex);
-        let _lock_guard = locker.lock().await;
+        let _lock_guard = if wait {
+            locker.lock().await
+        } else {
+            match locker.try_lock() {
+                Ok(guard) => guard,
+                Err(_) => return Ok(false),
+            }
+        };
 
         // check if the locker already expired, clean it
         if let Ok(Some(ret)) = bucket.get(&key).await {
@@ -731,12 +812,17 @@ impl Locker {
                 Err(err) => {
                     // created error, means the key locked by other thread, wait and retry
                     last_err = Some(err.to_string());
+                    if !wait {
+                        break;
+                    }
                     tokio::time::sleep(Duration::from_millis(10)).await;
                 }
             };
         }
         if let Some(err) = last_err {
-            if err.contains("key already exists") {
+            if !wait && err.contains("key already exists") {
+                Ok(false)
+            } else if err.contains("key already exists") {
                 Err(Error::Message(format!(
                     "nats lock for key: {}, accquire timeout in {timeout}s",
                     self.key
@@ -748,7 +834,7 @@ impl Locker {
                 )))
             }
         } else {
-            Ok(())
+            Ok(true)
         }
     }
 
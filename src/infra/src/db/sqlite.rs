@@ -188,6 +188,15 @@ impl super::Db for SqliteDb {
         })
     }
 
+    async fn health(&self) -> Result<()> {
+        let pool = CLIENT_RO.clone();
+        super::with_health_timeout(async move {
+            sqlx::query("SELECT 1").execute(&pool).await?;
+            Ok(())
+        })
+        .await
+    }
+
     async fn get(&self, key: &str) -> Result<Bytes> {
         let (module, key1, key2) = super::parse_key(key);
         let pool = CLIENT_RO.clone();
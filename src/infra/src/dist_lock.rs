@@ -46,6 +46,28 @@ pub async fn lock(key: &str, wait_ttl: u64) -> Result<Option<Locker>> {
     }
 }
 
+/// Takes the lock on `key` only if nobody holds it right now, without waiting. Returns whether
+/// it was taken together with the locker to pass to `unlock`, which is `None` in local mode.
+#[inline(always)]
+pub async fn try_lock(key: &str) -> Result<(bool, Option<Locker>)> {
+    let cfg = config::get_config();
+    if cfg.common.local_mode {
+        return Ok((true, None));
+    }
+    match cfg.common.cluster_coordinator.as_str() {
+        "nats" => {
+            let mut lock = nats::Locker::new(key);
+            let taken = lock.try_lock().await?;
+            Ok((taken, taken.then(|| Locker(LockerStore::Nats(lock)))))
+        }
+        _ => {
+            let mut lock = etcd::Locker::new(key);
+            let taken = lock.try_lock().await?;
+            Ok((taken, taken.then(|| Locker(LockerStore::Etcd(lock)))))
+        }
+    }
+}
+
 #[inline(always)]
 pub async fn unlock(locker: &Option<Locker>) -> Result<()> {
     if let Some(locker) = locker {
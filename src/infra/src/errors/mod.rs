@@ -84,6 +84,9 @@ pub enum DbError {
     KeyNotExists(String),
     #[error("error {0} performing operation on key {1}")]
     DBOperError(String, String),
+    /// A batch write stopped at chunk `{0}`, after `{1}` items of the earlier chunks were written
+    #[error("chunk {0} of the batch failed after {1} items were written: {2}")]
+    BatchChunkFailed(usize, usize, String),
 }
 
 #[derive(ThisError, Debug)]
@@ -26,7 +26,10 @@ use infra::{cache, db::get_db};
 use tokio::time;
 
 use crate::{
-    common::infra::{cluster::get_cached_online_nodes, config::USERS},
+    common::{
+        infra::{cluster::get_cached_online_nodes, config::USERS},
+        utils::auth::{login_failure_counts, password_hash_cache_len},
+    },
     service::db,
 };
 
@@ -181,6 +184,17 @@ async fn update_metadata_metrics() -> Result<(), anyhow::Error> {
             .set(count);
     }
 
+    metrics::META_NUM_PASSWORD_HASHES
+        .with_label_values(&[])
+        .set(password_hash_cache_len() as i64);
+    let (failing, locked) = login_failure_counts();
+    metrics::META_NUM_LOGIN_FAILURES
+        .with_label_values(&["failing"])
+        .set(failing as i64);
+    metrics::META_NUM_LOGIN_FAILURES
+        .with_label_values(&["locked"])
+        .set(locked as i64);
+
     metrics::META_NUM_FUNCTIONS.reset();
     let functions = db.list_keys("/function/").await?;
     for key in functions {
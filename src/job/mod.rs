@@ -17,12 +17,12 @@ use config::cluster;
 use infra::file_list as infra_file_list;
 #[cfg(feature = "enterprise")]
 use o2_enterprise::enterprise::common::infra::config::O2_CONFIG;
-use regex::Regex;
 
 use crate::{
     common::{
         infra::config::SYSLOG_ENABLED,
         meta::{organization::DEFAULT_ORG, user::UserRequest},
+        utils::auth::{is_valid_email, DEFAULT_EMAIL_REGEX},
     },
     service::{compact::stats::update_stats_from_file_list, db, usage, users},
 };
@@ -40,20 +40,25 @@ pub(crate) mod syslog_server;
 mod telemetry;
 
 pub async fn init() -> Result<(), anyhow::Error> {
-    let email_regex = Regex::new(
-        r"^([a-z0-9_+]([a-z0-9_+.-]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
-    )
-    .expect("Email regex is valid");
-
     let cfg = config::get_config();
     // init root user
     if !db::user::root_user_exists().await {
         if cfg.auth.root_user_email.is_empty()
-            || !email_regex.is_match(&cfg.auth.root_user_email)
+            || !is_valid_email(&cfg.auth.root_user_email)
             || cfg.auth.root_user_password.is_empty()
         {
             panic!(
-                "Please set root user email-id & password using ZO_ROOT_USER_EMAIL & ZO_ROOT_USER_PASSWORD environment variables. This can also indicate an invalid email ID. Email ID must comply with ([a-z0-9_+]([a-z0-9_+.-]*[a-z0-9_+])?)@([a-z0-9]+([\\-\\.]{{1}}[a-z0-9]+)*\\.[a-z]{{2,6}})"
+                "Please set root user email-id & password using ZO_ROOT_USER_EMAIL & ZO_ROOT_USER_PASSWORD environment variables. This can also indicate an invalid email ID. Email ID must comply with {}{}",
+                if cfg.auth.email_regex.is_empty() {
+                    DEFAULT_EMAIL_REGEX
+                } else {
+                    cfg.auth.email_regex.as_str()
+                },
+                if cfg.auth.email_domain_allowlist.is_empty() {
+                    "".to_string()
+                } else {
+                    format!(" and belong to {}", cfg.auth.email_domain_allowlist)
+                }
             );
         }
         let _ = users::create_root_user(
@@ -123,8 +128,11 @@ pub async fn init() -> Result<(), anyhow::Error> {
         || cluster::is_single_node(&cluster::LOCAL_NODE_ROLE)
     {
         tokio::task::spawn(async move { db::session::watch().await });
+        tokio::task::spawn(async move { crate::service::session::run_sweeper().await });
     }
 
+    tokio::task::spawn(async move { db::api_key::watch().await });
+
     tokio::task::yield_now().await; // yield let other tasks run
 
     // cache core metadata
@@ -199,6 +207,8 @@ pub async fn init() -> Result<(), anyhow::Error> {
             .expect("user session cache failed");
     }
 
+    db::api_key::cache().await.expect("api key cache failed");
+
     // check wal directory
     if cluster::is_ingester(&cluster::LOCAL_NODE_ROLE) {
         // create wal dir
@@ -108,7 +108,7 @@ pub async fn save(
     match db::alerts::destinations::set(org_id, &destination).await {
         Ok(_) => {
             if name.is_empty() {
-                set_ownership(org_id, "destinations", Authz::new(&destination.name)).await;
+                set_ownership(org_id, "destinations", Authz::new(&destination.name), false).await;
             }
             Ok(())
         }
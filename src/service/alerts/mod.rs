@@ -169,7 +169,7 @@ pub async fn save(
     match db::alerts::set(org_id, stream_type, stream_name, &alert, create).await {
         Ok(_) => {
             if name.is_empty() {
-                set_ownership(org_id, "alerts", Authz::new(&alert.name)).await;
+                set_ownership(org_id, "alerts", Authz::new(&alert.name), false).await;
             }
             Ok(())
         }
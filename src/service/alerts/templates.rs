@@ -60,7 +60,7 @@ pub async fn save(
     match db::alerts::templates::set(org_id, &mut template).await {
         Ok(_) => {
             if name.is_empty() {
-                set_ownership(org_id, "templates", Authz::new(&template.name)).await;
+                set_ownership(org_id, "templates", Authz::new(&template.name), false).await;
             }
             Ok(())
         }
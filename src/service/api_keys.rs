@@ -0,0 +1,83 @@
+// Copyright 2024 Zinc Labs Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use config::{get_config, ider, utils::rand::generate_random_string};
+
+use crate::{
+    common::{
+        infra::config::API_KEYS,
+        meta::user::{ApiKey, ApiKeyResponse},
+        utils::auth::get_hash,
+    },
+    service::db,
+};
+
+const API_KEY_PREFIX: &str = "o2k_";
+
+fn hash_api_key(api_key: &str) -> String {
+    get_hash(api_key, &get_config().auth.ext_auth_salt)
+}
+
+/// Creates an api key for the user in the org, the plain key is only returned here
+pub async fn create(
+    org_id: &str,
+    user_email: &str,
+    name: &str,
+) -> Result<ApiKeyResponse, anyhow::Error> {
+    let api_key = format!("{API_KEY_PREFIX}{}", generate_random_string(32));
+    let key = ApiKey {
+        key_id: ider::uuid(),
+        org_id: org_id.to_string(),
+        user_email: user_email.to_string(),
+        name: name.to_string(),
+        key_hash: hash_api_key(&api_key),
+        created_at: chrono::Utc::now().timestamp_micros(),
+    };
+    db::api_key::set(&key).await?;
+    let mut resp = ApiKeyResponse::from(&key);
+    resp.api_key = Some(api_key);
+    Ok(resp)
+}
+
+pub async fn list(org_id: &str, user_email: &str) -> Result<Vec<ApiKeyResponse>, anyhow::Error> {
+    Ok(db::api_key::list(org_id)
+        .await?
+        .iter()
+        .filter(|key| key.user_email == user_email)
+        .map(ApiKeyResponse::from)
+        .collect())
+}
+
+/// Revokes the api key, returns false if the user has no such key in the org
+pub async fn revoke(org_id: &str, user_email: &str, key_id: &str) -> Result<bool, anyhow::Error> {
+    let exists = db::api_key::list(org_id)
+        .await?
+        .iter()
+        .any(|key| key.key_id == key_id && key.user_email == user_email);
+    if exists {
+        db::api_key::delete(org_id, key_id).await?;
+    }
+    Ok(exists)
+}
+
+/// Returns the stored key matching the plain api key
+pub fn get(api_key: &str) -> Option<ApiKey> {
+    if !api_key.starts_with(API_KEY_PREFIX) {
+        return None;
+    }
+    API_KEYS
+        .get(&hash_api_key(api_key))
+        .map(|key| key.value().clone())
+}
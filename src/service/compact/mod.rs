@@ -23,15 +23,12 @@ use config::{
     },
 };
 use infra::{
-    dist_lock, file_list as infra_file_list,
+    file_list as infra_file_list,
     schema::{get_settings, unwrap_partition_time_level},
 };
 use tokio::sync::{mpsc, Semaphore};
 
-use crate::{
-    common::infra::cluster::{get_node_by_uuid, get_node_from_consistent_hash},
-    service::db,
-};
+use crate::{common::infra::cluster::get_node_from_consistent_hash, service::db};
 
 mod file_list;
 pub mod file_list_deleted;
@@ -371,42 +368,16 @@ pub async fn run_delay_deletion() -> Result<(), anyhow::Error> {
     let time_max = time_max.timestamp_micros();
     let orgs = db::schema::list_organizations_from_cache().await;
     for org_id in orgs {
-        // get the working node for the organization
-        let (_, node) = db::compact::organization::get_offset(&org_id, "file_list_deleted").await;
-        if !node.is_empty() && LOCAL_NODE_UUID.ne(&node) && get_node_by_uuid(&node).await.is_some()
-        {
+        // take the ownership of the organization, skip it when another live node is working on
+        let Some(offset) =
+            db::compact::organization::claim_offset(&org_id, "file_list_deleted").await?
+        else {
             continue;
-        }
-
-        // before start processing, set current node to lock the organization
-        let lock_key = format!("/compact/organization/{org_id}");
-        let locker = dist_lock::lock(&lock_key, 0).await?;
-        // check the working node for the organization again, maybe other node locked it
-        // first
-        let (offset, node) =
-            db::compact::organization::get_offset(&org_id, "file_list_deleted").await;
-        if !node.is_empty() && LOCAL_NODE_UUID.ne(&node) && get_node_by_uuid(&node).await.is_some()
-        {
-            dist_lock::unlock(&locker).await?;
-            continue;
-        }
-        let ret = if node.is_empty() || LOCAL_NODE_UUID.ne(&node) {
-            db::compact::organization::set_offset(
-                &org_id,
-                "file_list_deleted",
-                offset,
-                Some(&LOCAL_NODE_UUID.clone()),
-            )
-            .await
-        } else {
-            Ok(())
         };
-        // already bind to this node, we can unlock now
-        dist_lock::unlock(&locker).await?;
-        drop(locker);
-        ret?;
 
+        let node = LOCAL_NODE_UUID.clone();
         let batch_size = 10000;
+        let mut owned = true;
         loop {
             match file_list_deleted::delete(&org_id, offset, time_max, batch_size).await {
                 Ok(affected) => {
@@ -420,17 +391,37 @@ pub async fn run_delay_deletion() -> Result<(), anyhow::Error> {
                     break;
                 }
             };
+            // refresh the claim, so a run longer than the ownership ttl isn't taken over
+            owned = db::compact::organization::set_offset_if(
+                &org_id,
+                "file_list_deleted",
+                (offset, &node),
+                offset,
+                Some(&node),
+            )
+            .await?;
+            if !owned {
+                break;
+            }
             tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         }
 
-        // update offset
-        db::compact::organization::set_offset(
-            &org_id,
-            "file_list_deleted",
-            time_max,
-            Some(&LOCAL_NODE_UUID.clone()),
-        )
-        .await?;
+        // update offset, unless another node has taken the organization over meanwhile
+        if owned {
+            owned = db::compact::organization::set_offset_if(
+                &org_id,
+                "file_list_deleted",
+                (offset, &node),
+                time_max,
+                Some(&node),
+            )
+            .await?;
+        }
+        if !owned {
+            log::warn!(
+                "[COMPACTOR] file_list_deleted offset of org {org_id} was taken over by another node, stop deleting"
+            );
+        }
     }
 
     Ok(())
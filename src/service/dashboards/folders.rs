@@ -25,7 +25,7 @@ use crate::{
             dashboards::{Folder, FolderList, DEFAULT_FOLDER},
             http::HttpResponse as MetaHttpResponse,
         },
-        utils::auth::{remove_ownership, set_ownership},
+        utils::auth::{remove_ownership_cascade, set_ownership},
     },
     service::db,
 };
@@ -60,7 +60,7 @@ pub async fn save_folder(
 
     match db::dashboards::folders::put(org_id, folder).await {
         Ok(folder) => {
-            set_ownership(org_id, "folders", Authz::new(&folder.folder_id)).await;
+            set_ownership(org_id, "folders", Authz::new(&folder.folder_id), false).await;
             Ok(HttpResponse::Ok().json(folder))
         }
         Err(error) => Ok(
@@ -167,7 +167,17 @@ pub async fn delete_folder(org_id: &str, folder_id: &str) -> Result<HttpResponse
     }
     match db::dashboards::folders::delete(org_id, folder_id).await {
         Ok(_) => {
-            remove_ownership(org_id, "folders", Authz::new(folder_id)).await;
+            remove_ownership_cascade(org_id, "folders", Authz::new(folder_id), || async {
+                // dashboards saved since the emptiness check above
+                db::dashboards::list(org_id, folder_id)
+                    .await
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|d| d.dashboard_id())
+                    .map(|id| ("dashboards".to_string(), id.to_string()))
+                    .collect()
+            })
+            .await;
             Ok(HttpResponse::Ok().json(MetaHttpResponse::message(
                 http::StatusCode::OK.into(),
                 "Dashboard folder deleted".to_string(),
@@ -17,6 +17,7 @@ use std::io;
 
 use actix_web::{http, web, HttpResponse};
 use config::{ider, utils::json};
+use serde::Deserialize;
 
 use crate::{
     common::{
@@ -25,7 +26,10 @@ use crate::{
             dashboards::{Dashboards, Folder, DEFAULT_FOLDER},
             http::HttpResponse as MetaHttpResponse,
         },
-        utils::auth::{remove_ownership, set_ownership},
+        utils::auth::{
+            can_create_dashboard_in, move_ownership, move_ownership_with, remove_ownership,
+            set_ownership,
+        },
     },
     service::db::dashboards,
 };
@@ -36,9 +40,17 @@ pub mod reports;
 #[tracing::instrument(skip(body))]
 pub async fn create_dashboard(
     org_id: &str,
-    folder_id: &str,
+    query_folder: Option<&str>,
+    user_id: &str,
     body: web::Bytes,
 ) -> Result<HttpResponse, io::Error> {
+    let (auth_folder, folder_id) = resolve_folders(query_folder, &body);
+    let folder_id = folder_id.as_str();
+    // the request was only authorized against the folder of the query
+    if folder_id != auth_folder && !can_create_dashboard_in(org_id, user_id, folder_id).await {
+        return Ok(MetaHttpResponse::forbidden("Unauthorized Access"));
+    }
+
     // NOTE: Overwrite whatever `dashboard_id` the client has sent us
     // If folder is default folder & doesn't exist then create it
 
@@ -47,16 +59,7 @@ pub async fn create_dashboard(
             let dashboard_id = ider::generate();
             match save_dashboard(org_id, &dashboard_id, folder_id, body).await {
                 Ok(res) => {
-                    set_ownership(
-                        org_id,
-                        "dashboards",
-                        Authz {
-                            obj_id: dashboard_id,
-                            parent_type: "folders".to_owned(),
-                            parent: folder_id.to_owned(),
-                        },
-                    )
-                    .await;
+                    set_dashboard_ownership(org_id, &dashboard_id, &auth_folder, folder_id).await;
                     Ok(res)
                 }
                 Err(_) => todo!(),
@@ -73,16 +76,8 @@ pub async fn create_dashboard(
                 let dashboard_id = ider::generate();
                 match save_dashboard(org_id, &dashboard_id, folder_id, body).await {
                     Ok(res) => {
-                        set_ownership(
-                            org_id,
-                            "dashboards",
-                            Authz {
-                                obj_id: dashboard_id,
-                                parent_type: "folders".to_owned(),
-                                parent: folder_id.to_owned(),
-                            },
-                        )
-                        .await;
+                        set_dashboard_ownership(org_id, &dashboard_id, &auth_folder, folder_id)
+                            .await;
                         Ok(res)
                     }
                     Err(error) => Ok(HttpResponse::InternalServerError().json(
@@ -102,6 +97,79 @@ pub async fn create_dashboard(
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BodyFolder {
+    #[serde(default, alias = "folder_id")]
+    folder_id: Option<String>,
+}
+
+/// Returns the folder a new dashboard was authorized against and the folder it is stored in.
+/// The `folder` query param wins, without it the `folderId` of the body is used.
+fn resolve_folders(query_folder: Option<&str>, body: &[u8]) -> (String, String) {
+    let auth_folder = query_folder.unwrap_or(DEFAULT_FOLDER).to_string();
+    let folder_id = match query_folder {
+        Some(_) => None,
+        None => json::from_slice::<BodyFolder>(body)
+            .ok()
+            .and_then(|b| b.folder_id)
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty()),
+    };
+    let folder_id = folder_id.unwrap_or_else(|| auth_folder.clone());
+    (auth_folder, folder_id)
+}
+
+async fn set_dashboard_ownership(
+    org_id: &str,
+    dashboard_id: &str,
+    auth_folder: &str,
+    folder_id: &str,
+) {
+    set_dashboard_ownership_with(
+        dashboard_id,
+        auth_folder,
+        folder_id,
+        |obj_type, obj| async move { set_ownership(org_id, &obj_type, obj, true).await },
+        |obj_type, obj| async move { remove_ownership(org_id, &obj_type, obj).await },
+    )
+    .await;
+}
+
+/// The auth extractor can't read the body, so the tuples are created in the folder from the
+/// query and then moved to the folder the dashboard is stored in
+async fn set_dashboard_ownership_with<S, SFut, R, RFut>(
+    dashboard_id: &str,
+    auth_folder: &str,
+    folder_id: &str,
+    set: S,
+    remove: R,
+) where
+    S: Fn(String, Authz) -> SFut,
+    SFut: std::future::Future<Output = ()>,
+    R: Fn(String, Authz) -> RFut,
+    RFut: std::future::Future<Output = ()>,
+{
+    set(
+        "dashboards".to_owned(),
+        Authz {
+            obj_id: dashboard_id.to_owned(),
+            parent_type: "folders".to_owned(),
+            parent: auth_folder.to_owned(),
+        },
+    )
+    .await;
+    move_ownership_with(
+        "dashboards",
+        dashboard_id,
+        auth_folder,
+        folder_id,
+        set,
+        remove,
+    )
+    .await;
+}
+
 #[tracing::instrument(skip(body))]
 pub async fn update_dashboard(
     org_id: &str,
@@ -220,6 +288,7 @@ pub async fn move_dashboard(
 
         // delete the dashboard from the source folder
         let _ = dashboards::delete(org_id, dashboard_id, from_folder).await;
+        move_ownership(org_id, "dashboards", dashboard_id, from_folder, to_folder).await;
         Ok(Response::OkMessage("Dashboard moved successfully".to_string()).into())
     } else {
         Ok(Response::NotFound("Dashboard".to_string()).into())
@@ -253,3 +322,77 @@ impl From<Response> for HttpResponse {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_folders() {
+        let cases = [
+            (None, r#"{"version":3,"title":"t"}"#, "default", "default"),
+            (
+                None,
+                r#"{"version":3,"title":"t","folderId":"f1"}"#,
+                "default",
+                "f1",
+            ),
+            (
+                None,
+                r#"{"version":3,"title":"t","folder_id":"f1"}"#,
+                "default",
+                "f1",
+            ),
+            (
+                None,
+                r#"{"version":3,"title":"t","folderId":" "}"#,
+                "default",
+                "default",
+            ),
+            (
+                Some("f2"),
+                r#"{"version":3,"title":"t","folderId":"f1"}"#,
+                "f2",
+                "f2",
+            ),
+            (Some("f2"), r#"{"version":3,"title":"t"}"#, "f2", "f2"),
+            (None, "not json", "default", "default"),
+        ];
+        for (query_folder, body, auth_folder, folder_id) in cases {
+            assert_eq!(
+                resolve_folders(query_folder, body.as_bytes()),
+                (auth_folder.to_string(), folder_id.to_string()),
+                "{query_folder:?} {body}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_dashboard_ownership() {
+        for (auth_folder, folder_id) in [("default", "f1"), ("f1", "f1")] {
+            // the (object, parent) tuples
+            let tuples = std::sync::Mutex::new(std::collections::HashSet::new());
+            set_dashboard_ownership_with(
+                "d1",
+                auth_folder,
+                folder_id,
+                |obj_type, obj| {
+                    let tuple = (format!("{obj_type}:{}", obj.obj_id), obj.parent);
+                    tuples.lock().unwrap().insert(tuple);
+                    async {}
+                },
+                |obj_type, obj| {
+                    let tuple = (format!("{obj_type}:{}", obj.obj_id), obj.parent);
+                    assert!(tuples.lock().unwrap().remove(&tuple), "{tuple:?}");
+                    async {}
+                },
+            )
+            .await;
+            assert_eq!(
+                tuples.into_inner().unwrap(),
+                [("dashboards:d1".to_string(), folder_id.to_string())].into(),
+                "{auth_folder} {folder_id}"
+            );
+        }
+    }
+}
@@ -138,7 +138,7 @@ pub async fn save(
     match db::dashboards::reports::set(org_id, &report, create).await {
         Ok(_) => {
             if name.is_empty() {
-                set_ownership(org_id, "reports", Authz::new(&report.name)).await;
+                set_ownership(org_id, "reports", Authz::new(&report.name), false).await;
             }
             Ok(())
         }
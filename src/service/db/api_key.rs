@@ -0,0 +1,112 @@
+// Copyright 2024 Zinc Labs Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use config::utils::json;
+
+use crate::{
+    common::{infra::config::API_KEYS, meta::user::ApiKey},
+    service::db,
+};
+
+// DBKey of api keys, /api_keys/{org_id}/{key_id}
+pub const API_KEY_KEY: &str = "/api_keys/";
+
+fn mk_key(org_id: &str, key_id: &str) -> String {
+    format!("{API_KEY_KEY}{org_id}/{key_id}")
+}
+
+pub async fn set(key: &ApiKey) -> Result<(), anyhow::Error> {
+    db::put(
+        &mk_key(&key.org_id, &key.key_id),
+        json::to_vec(key).unwrap().into(),
+        db::NEED_WATCH,
+        None,
+    )
+    .await?;
+    API_KEYS.insert(key.key_hash.clone(), key.clone());
+    Ok(())
+}
+
+pub async fn delete(org_id: &str, key_id: &str) -> Result<(), anyhow::Error> {
+    API_KEYS.retain(|_, v| !(v.org_id == org_id && v.key_id == key_id));
+    Ok(db::delete(&mk_key(org_id, key_id), false, db::NEED_WATCH, None).await?)
+}
+
+pub async fn list(org_id: &str) -> Result<Vec<ApiKey>, anyhow::Error> {
+    let ret = db::list_values(&format!("{API_KEY_KEY}{org_id}/")).await?;
+    let mut keys = Vec::with_capacity(ret.len());
+    for item_value in ret {
+        keys.push(json::from_slice(&item_value)?);
+    }
+    Ok(keys)
+}
+
+pub async fn watch() -> Result<(), anyhow::Error> {
+    let key = API_KEY_KEY;
+    let cluster_coordinator = db::get_coordinator().await;
+    let mut events = cluster_coordinator.watch(key).await?;
+    let events = Arc::get_mut(&mut events).unwrap();
+    log::info!("Start watching api keys");
+    loop {
+        let ev = match events.recv().await {
+            Some(ev) => ev,
+            None => {
+                log::error!("watch_api_keys: event channel closed");
+                return Ok(());
+            }
+        };
+        match ev {
+            db::Event::Put(ev) => {
+                let item_value: ApiKey = if config::get_config().common.meta_store_external {
+                    match db::get(&ev.key).await {
+                        Ok(val) => match json::from_slice(&val) {
+                            Ok(val) => val,
+                            Err(e) => {
+                                log::error!("Error getting value: {}", e);
+                                continue;
+                            }
+                        },
+                        Err(e) => {
+                            log::error!("Error getting value: {}", e);
+                            continue;
+                        }
+                    }
+                } else {
+                    json::from_slice(&ev.value.unwrap()).unwrap()
+                };
+                API_KEYS.insert(item_value.key_hash.clone(), item_value);
+            }
+            db::Event::Delete(ev) => {
+                let item_key = ev.key.strip_prefix(key).unwrap();
+                if let Some((org_id, key_id)) = item_key.split_once('/') {
+                    API_KEYS.retain(|_, v| !(v.org_id == org_id && v.key_id == key_id));
+                }
+            }
+            db::Event::Empty => {}
+        }
+    }
+}
+
+pub async fn cache() -> Result<(), anyhow::Error> {
+    let ret = db::list_values(API_KEY_KEY).await?;
+    for item_value in ret {
+        let json_val: ApiKey = json::from_slice(&item_value)?;
+        API_KEYS.insert(json_val.key_hash.clone(), json_val);
+    }
+    log::info!("Api keys Cached");
+    Ok(())
+}
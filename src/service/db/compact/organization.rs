@@ -13,10 +13,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use config::RwHashMap;
+use config::{cluster::LOCAL_NODE_UUID, get_config, utils::time::now_micros, RwHashMap};
+use infra::errors::{DbError, Error};
 use once_cell::sync::Lazy;
 
-use crate::service::db;
+use crate::{common::infra::cluster::get_node_by_uuid, service::db};
 
 pub static STREAMS: Lazy<RwHashMap<String, RwHashMap<String, i64>>> = Lazy::new(Default::default);
 
@@ -24,20 +25,14 @@ fn mk_key(org_id: &str, module: &str) -> String {
     format!("/compact/organization/{org_id}/{module}")
 }
 
-pub async fn get_offset(org_id: &str, module: &str) -> (i64, String) {
+pub async fn get_offset(org_id: &str, module: &str) -> Result<(i64, String), anyhow::Error> {
     let key = mk_key(org_id, module);
     let value = match db::get(&key).await {
         Ok(ret) => String::from_utf8_lossy(&ret).to_string(),
         Err(_) => String::from("0"),
     };
-    if value.contains(';') {
-        let mut parts = value.split(';');
-        let offset: i64 = parts.next().unwrap().parse().unwrap();
-        let node = parts.next().unwrap().to_string();
-        (offset, node)
-    } else {
-        (value.parse().unwrap(), String::from(""))
-    }
+    let (offset, node, _) = parse_offset(&value)?;
+    Ok((offset, node))
 }
 
 pub async fn set_offset(
@@ -47,10 +42,219 @@ pub async fn set_offset(
     node: Option<&str>,
 ) -> Result<(), anyhow::Error> {
     let key = mk_key(org_id, module);
-    let val = if let Some(node) = node {
-        format!("{};{}", offset, node)
+    let val = mk_value(offset, node);
+    Ok(db::put(&key, val.into(), db::NO_NEED_WATCH, None).await?)
+}
+
+/// Set the offset only when the stored value still equals the `expected` (offset, node).
+///
+/// Returns `Ok(false)` when the stored value was changed by another node, the caller
+/// should re-read the offset and retry. A corrupt stored value is never replaced.
+pub async fn set_offset_if(
+    org_id: &str,
+    module: &str,
+    expected: (i64, &str),
+    offset: i64,
+    node: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let key = mk_key(org_id, module);
+    // swap against the exact stored bytes, their claim time isn't part of `expected`
+    let current = match db::get(&key).await {
+        Ok(v) => Some(v),
+        Err(Error::DbError(DbError::KeyNotExists(_))) => None,
+        Err(e) => return Err(e.into()),
+    };
+    let (offset_now, node_now) = match &current {
+        Some(v) => match parse_offset(&String::from_utf8_lossy(v)) {
+            Ok((offset, node, _)) => (offset, node),
+            Err(e) => {
+                log::error!("[COMPACTOR] {module} offset of org {org_id}: {e}");
+                return Ok(false);
+            }
+        },
+        None => (0, String::new()),
+    };
+    if (offset_now, node_now.as_str()) != expected {
+        return Ok(false);
+    }
+    let val = mk_value(offset, node);
+    Ok(db::compare_and_swap(&key, current, val.into(), db::NO_NEED_WATCH).await?)
+}
+
+/// Take the ownership of the offset for the local node and returns the offset.
+///
+/// The ownership can be taken when there is no owner, or the owner is not in the active
+/// node list anymore, or the owner has not refreshed the offset in `ZO_COMPACT_NODE_OWNERSHIP_TTL`.
+/// Returns `Ok(None)` when the offset is owned by another live node, or when the stored offset
+/// is corrupt.
+pub async fn claim_offset(org_id: &str, module: &str) -> Result<Option<i64>, anyhow::Error> {
+    let key = mk_key(org_id, module);
+    let value = match db::get(&key).await {
+        Ok(ret) => String::from_utf8_lossy(&ret).to_string(),
+        Err(_) => String::from("0"),
+    };
+    let (offset, node, claimed_at) = match parse_offset(&value) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("[COMPACTOR] refuse to claim {module} offset of org {org_id}: {e}");
+            return Ok(None);
+        }
+    };
+    if !node.is_empty() && LOCAL_NODE_UUID.ne(&node) {
+        let ttl = get_config().compact.node_ownership_ttl * 1_000_000;
+        let expired = claimed_at > 0 && ttl > 0 && now_micros() - claimed_at > ttl;
+        if !expired && get_node_by_uuid(&node).await.is_some() {
+            return Ok(None);
+        }
+        log::info!("[COMPACTOR] take over {module} offset of org {org_id} from stale node {node}");
+    }
+    if set_offset_if(
+        org_id,
+        module,
+        (offset, &node),
+        offset,
+        Some(&LOCAL_NODE_UUID.clone()),
+    )
+    .await?
+    {
+        Ok(Some(offset))
+    } else {
+        // other node claimed it first
+        Ok(None)
+    }
+}
+
+/// List all the compaction offsets of the organization as (module, offset, node)
+pub async fn list_offsets(org_id: &str) -> Result<Vec<(String, i64, String)>, anyhow::Error> {
+    let mut items = Vec::new();
+    let key = format!("/compact/organization/{org_id}/");
+    let ret = db::list(&key).await?;
+    for (item_key, item_value) in ret {
+        let module = item_key.strip_prefix(&key).unwrap();
+        let (offset, node, _) = parse_offset(&String::from_utf8_lossy(&item_value))?;
+        items.push((module.to_string(), offset, node));
+    }
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(items)
+}
+
+fn mk_value(offset: i64, node: Option<&str>) -> String {
+    if let Some(node) = node {
+        format!("{};{};{}", offset, node, now_micros())
     } else {
         offset.to_string()
+    }
+}
+
+/// Parse the value in the format of `offset;node;claimed_at`, the old formats `offset;node`
+/// and `offset` are also supported, the missing parts are returned as empty. A number that
+/// doesn't parse is an error rather than 0, which would restart the offset from the beginning.
+fn parse_offset(value: &str) -> Result<(i64, String, i64), anyhow::Error> {
+    let number = |part: &str| -> Result<i64, anyhow::Error> {
+        let part = part.trim();
+        if part.is_empty() {
+            return Ok(0);
+        }
+        part.parse()
+            .map_err(|_| anyhow::anyhow!("invalid offset value: {value}"))
     };
-    Ok(db::put(&key, val.into(), db::NO_NEED_WATCH, None).await?)
+    let mut parts = value.split(';');
+    let offset = number(parts.next().unwrap())?;
+    let node = parts.next().unwrap_or_default().to_string();
+    let claimed_at = number(parts.next().unwrap_or_default())?;
+    Ok((offset, node, claimed_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(
+            parse_offset("100;node1;1718000000000000").unwrap(),
+            (100, "node1".to_string(), 1718000000000000)
+        );
+        assert_eq!(
+            parse_offset("100;node1").unwrap(),
+            (100, "node1".to_string(), 0)
+        );
+        assert_eq!(parse_offset("100").unwrap(), (100, "".to_string(), 0));
+        assert_eq!(parse_offset("100;").unwrap(), (100, "".to_string(), 0));
+        assert_eq!(parse_offset("").unwrap(), (0, "".to_string(), 0));
+        // a corrupt number isn't read as 0
+        assert!(parse_offset("abc;node").is_err());
+        assert!(parse_offset("100;node1;abc").is_err());
+    }
+
+    #[test]
+    fn test_mk_value() {
+        assert_eq!(mk_value(100, None), "100");
+        let (offset, node, claimed_at) = parse_offset(&mk_value(100, Some("node1"))).unwrap();
+        assert_eq!((offset, node.as_str()), (100, "node1"));
+        assert!(claimed_at > 0);
+    }
+
+    #[tokio::test]
+    async fn test_set_offset_if() {
+        let (org_id, module) = ("test_set_offset_if", "file_list_deleted");
+        db::delete_if_exists(&mk_key(org_id, module), false, db::NO_NEED_WATCH)
+            .await
+            .unwrap();
+
+        // a missing key reads as (0, ""), only that expectation can create it
+        assert!(
+            !set_offset_if(org_id, module, (100, "node1"), 200, Some("node2"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            set_offset_if(org_id, module, (0, ""), 100, Some("node1"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            get_offset(org_id, module).await.unwrap(),
+            (100, "node1".to_string())
+        );
+
+        // a stale (offset, node) is rejected and leaves the stored value alone
+        for stale in [(0, ""), (100, "node2"), (200, "node1")] {
+            assert!(
+                !set_offset_if(org_id, module, stale, 300, Some("node2"))
+                    .await
+                    .unwrap(),
+                "{stale:?}"
+            );
+        }
+        assert_eq!(
+            get_offset(org_id, module).await.unwrap(),
+            (100, "node1".to_string())
+        );
+
+        // the current one is accepted
+        assert!(
+            set_offset_if(org_id, module, (100, "node1"), 200, Some("node2"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            get_offset(org_id, module).await.unwrap(),
+            (200, "node2".to_string())
+        );
+
+        // a corrupt value is neither claimed nor replaced
+        let key = mk_key(org_id, module);
+        db::put(&key, "abc;node2".into(), db::NO_NEED_WATCH, None)
+            .await
+            .unwrap();
+        assert!(
+            !set_offset_if(org_id, module, (0, "node2"), 300, Some("node1"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(claim_offset(org_id, module).await.unwrap(), None);
+        assert!(get_offset(org_id, module).await.is_err());
+        assert_eq!(db::get(&key).await.unwrap(), "abc;node2".as_bytes());
+    }
 }
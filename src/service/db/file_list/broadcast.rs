@@ -36,6 +36,10 @@ static EVENTS: Lazy<RwLock<HashMap<String, EventChannel>>> =
 
 type EventChannel = Arc<mpsc::UnboundedSender<Vec<FileKey>>>;
 
+/// File lists longer than this are streamed in batches of this size, so a large compaction
+/// doesn't exceed the max grpc message size
+const FILE_LIST_BATCH_SIZE: usize = 10_000;
+
 /// send an event to broadcast, will create a new channel for each nodes
 pub async fn send(items: &[FileKey], node_uuid: Option<String>) -> Result<(), anyhow::Error> {
     let cfg = get_config();
@@ -198,6 +202,17 @@ async fn send_to_node(
             for item in items.iter() {
                 req_query.items.push(cluster_rpc::FileKey::from(item));
             }
+            let batches = if req_query.items.len() > FILE_LIST_BATCH_SIZE {
+                req_query
+                    .items
+                    .chunks(FILE_LIST_BATCH_SIZE)
+                    .map(|items| cluster_rpc::FileList {
+                        items: items.to_vec(),
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                Vec::new()
+            };
             let mut wait_ttl = 1;
             let mut retry_ttl = 0;
             loop {
@@ -208,8 +223,25 @@ async fn send_to_node(
                     );
                     break;
                 }
-                let request = tonic::Request::new(req_query.clone());
-                match client.send_file_list(request).await {
+                let result = if batches.is_empty() {
+                    client
+                        .send_file_list(tonic::Request::new(req_query.clone()))
+                        .await
+                } else {
+                    match client
+                        .send_file_list_stream(futures::stream::iter(batches.clone()))
+                        .await
+                    {
+                        // the node doesn't support streaming yet
+                        Err(e) if e.code() == tonic::Code::Unimplemented => {
+                            client
+                                .send_file_list(tonic::Request::new(req_query.clone()))
+                                .await
+                        }
+                        result => result,
+                    }
+                };
+                match result {
                     Ok(_) => break,
                     Err(e) => {
                         if cluster::get_node_by_uuid(&node.uuid).await.is_none() {
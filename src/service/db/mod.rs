@@ -20,6 +20,7 @@ use infra::{db as infra_db, errors::Result};
 use {infra::errors::Error, o2_enterprise::enterprise::common::infra::config::O2_CONFIG};
 
 pub mod alerts;
+pub mod api_key;
 pub mod compact;
 pub mod dashboards;
 pub mod enrichment_table;
@@ -68,6 +69,31 @@ pub(crate) async fn put(
     Ok(())
 }
 
+#[inline]
+pub(crate) async fn get_for_update(
+    key: &str,
+    need_watch: bool,
+    start_dt: Option<i64>,
+    update_fn: Box<infra_db::UpdateFn>,
+) -> Result<()> {
+    let db = infra_db::get_db().await;
+    db.get_for_update(key, need_watch, start_dt, update_fn)
+        .await
+}
+
+/// Writes `new` at `key` when the current value is `expected`, or when the key is missing for
+/// `None`, and returns whether it was written
+#[inline]
+pub(crate) async fn compare_and_swap(
+    key: &str,
+    expected: Option<Bytes>,
+    new: Bytes,
+    need_watch: bool,
+) -> Result<bool> {
+    let db = infra_db::get_db().await;
+    db.compare_and_swap(key, expected, new, need_watch).await
+}
+
 #[inline]
 pub(crate) async fn delete(
     key: &str,
@@ -15,10 +15,11 @@
 
 use std::sync::Arc;
 
-use config::utils::json;
+use config::{get_config, utils::json, RwHashMap};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    common::infra::config::USER_SESSIONS,
+    common::{infra::config::USER_SESSIONS, meta::user::UserSession},
     service::db::{self},
 };
 
@@ -26,17 +27,292 @@ use crate::{
 pub const USER_SESSION_KEY: &str = "/user_sessions/";
 
 pub async fn get(session_id: &str) -> Result<String, anyhow::Error> {
-    match USER_SESSIONS.get(session_id) {
-        Some(val) => Ok(val.to_string()),
-        None => {
-            let val = db::get(&format!("{USER_SESSION_KEY}{session_id}")).await?;
+    let cfg = get_config();
+    let now = chrono::Utc::now().timestamp();
+    let (ttl, idle_timeout) = (cfg.auth.session_ttl, cfg.auth.session_idle_timeout);
+    let touch = needs_touch(&USER_SESSIONS, session_id, now, idle_timeout);
+    match lookup(&USER_SESSIONS, session_id, now, ttl, idle_timeout) {
+        Lookup::Found(token) => {
+            if touch {
+                store_last_used(session_id, now).await;
+            }
+            return Ok(token);
+        }
+        Lookup::Expired => {
+            delete_expired(session_id).await;
+            return Err(anyhow::anyhow!("session expired"));
+        }
+        // idle on this node only, the store knows whether it's in use on another one
+        Lookup::Idle | Lookup::Missing => {}
+    }
+    let key = format!("{USER_SESSION_KEY}{session_id}");
+    let val: StoredSession = json::from_slice(&db::get(&key).await?)?;
+    let mut session = record_created_at(&key, val, now).await.into_session(now);
+    if session.is_expired(now, ttl, idle_timeout) {
+        delete_expired(session_id).await;
+        return Err(anyhow::anyhow!("session expired"));
+    }
+    let touch = is_touch_due(session.last_used_at, now, idle_timeout);
+    session.last_used_at = now;
+    let token = session.token.clone();
+    USER_SESSIONS.insert(session_id.to_string(), session);
+    if touch {
+        store_last_used(session_id, now).await;
+    }
+    Ok(token)
+}
+
+/// The access token of a cached session. A session past its ttl is dropped from the cache and
+/// the store, an idle one from the cache only, both are treated as missing. Each lookup extends
+/// the idle timeout of the session.
+pub fn get_cached(session_id: &str) -> Option<String> {
+    let cfg = get_config();
+    let now = chrono::Utc::now().timestamp();
+    let touch = needs_touch(
+        &USER_SESSIONS,
+        session_id,
+        now,
+        cfg.auth.session_idle_timeout,
+    );
+    match lookup(
+        &USER_SESSIONS,
+        session_id,
+        now,
+        cfg.auth.session_ttl,
+        cfg.auth.session_idle_timeout,
+    ) {
+        Lookup::Found(token) => {
+            if touch {
+                let session_id = session_id.to_string();
+                spawn(async move { store_last_used(&session_id, now).await });
+            }
+            Some(token)
+        }
+        Lookup::Expired => {
+            let session_id = session_id.to_string();
+            spawn(async move { delete_expired(&session_id).await });
+            None
+        }
+        Lookup::Idle | Lookup::Missing => None,
+    }
+}
+
+fn spawn(task: impl std::future::Future<Output = ()> + Send + 'static) {
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        handle.spawn(task);
+    }
+}
+
+/// Drops the expired and idle sessions from the cache, returns how many were dropped. The
+/// nodes only see the requests they serve, a session is deleted from the store once its stored
+/// value shows it expired or idle.
+pub async fn sweep_expired() -> usize {
+    let cfg = get_config();
+    let now = chrono::Utc::now().timestamp();
+    let (ttl, idle_timeout) = (cfg.auth.session_ttl, cfg.auth.session_idle_timeout);
+    let expired = sweep(&USER_SESSIONS, now, ttl, idle_timeout);
+    for session_id in expired.iter() {
+        let key = format!("{USER_SESSION_KEY}{session_id}");
+        let Ok(val) = db::get(&key).await else {
+            continue;
+        };
+        let Ok(stored) = json::from_slice::<StoredSession>(&val) else {
+            continue;
+        };
+        if stored.into_session(now).is_expired(now, ttl, idle_timeout) {
+            delete_expired(session_id).await;
+        }
+    }
+    expired.len()
+}
+
+/// Deletes an expired session from the store, every node sweeps the sessions it has cached so
+/// the session may be gone already
+async fn delete_expired(session_id: &str) {
+    if let Err(e) = db::delete_if_exists(
+        &format!("{USER_SESSION_KEY}{session_id}"),
+        false,
+        db::NEED_WATCH,
+    )
+    .await
+    {
+        log::error!("Error deleting expired session: {}", e);
+    }
+}
+
+/// Whether a use at `now` of a session last used at `last_used_at` is written to the store, at
+/// most once per a quarter of the idle timeout so the other nodes see the session in use
+fn is_touch_due(last_used_at: i64, now: i64, idle_timeout: i64) -> bool {
+    if idle_timeout <= 0 {
+        return false;
+    }
+    let interval = (idle_timeout / 4).max(1);
+    now / interval != last_used_at / interval
+}
+
+fn needs_touch(
+    sessions: &RwHashMap<String, UserSession>,
+    session_id: &str,
+    now: i64,
+    idle_timeout: i64,
+) -> bool {
+    sessions
+        .get(session_id)
+        .is_some_and(|session| is_touch_due(session.last_used_at, now, idle_timeout))
+}
+
+/// Writes the last use of the session to the store. It's swapped against the value read, so a
+/// session deleted meanwhile isn't put back.
+async fn store_last_used(session_id: &str, now: i64) {
+    let key = format!("{USER_SESSION_KEY}{session_id}");
+    let Ok(current) = db::get(&key).await else {
+        return;
+    };
+    let Ok(StoredSession::User {
+        token,
+        user_id,
+        created_at,
+        last_used_at,
+    }) = json::from_slice(&current)
+    else {
+        return;
+    };
+    if last_used_at >= now {
+        return;
+    }
+    let val = StoredSession::User {
+        token,
+        user_id,
+        created_at,
+        last_used_at: now,
+    };
+    let val = json::to_vec(&val).unwrap().into();
+    if let Err(e) = db::compare_and_swap(&key, Some(current), val, db::NO_NEED_WATCH).await {
+        log::error!("Error storing session last use: {}", e);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Lookup {
+    Found(String),
+    /// past the ttl
+    Expired,
+    /// not used on this node for the idle timeout
+    Idle,
+    Missing,
+}
+
+fn lookup(
+    sessions: &RwHashMap<String, UserSession>,
+    session_id: &str,
+    now: i64,
+    ttl: i64,
+    idle_timeout: i64,
+) -> Lookup {
+    let Some(mut session) = sessions.get_mut(session_id) else {
+        return Lookup::Missing;
+    };
+    let expired = if session.is_expired(now, ttl, 0) {
+        Lookup::Expired
+    } else if session.is_expired(now, 0, idle_timeout) {
+        Lookup::Idle
+    } else {
+        session.last_used_at = now;
+        return Lookup::Found(session.token.clone());
+    };
+    drop(session);
+    sessions.remove(session_id);
+    expired
+}
 
-            Ok(json::to_string(&val).unwrap())
+/// Drops the expired and idle sessions, returns their ids
+fn sweep(
+    sessions: &RwHashMap<String, UserSession>,
+    now: i64,
+    ttl: i64,
+    idle_timeout: i64,
+) -> Vec<String> {
+    let mut expired = Vec::new();
+    sessions.retain(|session_id, session| {
+        if session.is_expired(now, ttl, idle_timeout) {
+            expired.push(session_id.clone());
+            false
+        } else {
+            true
         }
+    });
+    expired
+}
+
+/// The stored value of a session, the sessions stored before the user was recorded are the bare
+/// access token
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredSession {
+    User {
+        token: String,
+        user_id: String,
+        created_at: i64,
+        /// written at most once per a quarter of the idle timeout, 0 when never written
+        #[serde(default)]
+        last_used_at: i64,
+    },
+    Token(String),
+}
+
+impl StoredSession {
+    fn into_session(self, now: i64) -> UserSession {
+        match self {
+            StoredSession::User {
+                token,
+                user_id,
+                created_at,
+                last_used_at,
+            } => UserSession {
+                user_id,
+                created_at,
+                last_used_at: if last_used_at > 0 { last_used_at } else { now },
+                ..UserSession::new(token, now)
+            },
+            StoredSession::Token(token) => UserSession::new(token, now),
+        }
+    }
+}
+
+/// A session stored as the bare access token has no creation time, it would be taken as created
+/// on every load and never reach its ttl. Its first load stores `now` as its creation time.
+async fn record_created_at(key: &str, val: StoredSession, now: i64) -> StoredSession {
+    let StoredSession::Token(token) = val else {
+        return val;
+    };
+    let val = StoredSession::User {
+        token,
+        user_id: "".to_string(),
+        created_at: now,
+        last_used_at: 0,
+    };
+    if let Err(e) = db::put(
+        key,
+        json::to_vec(&val).unwrap().into(),
+        db::NO_NEED_WATCH,
+        None,
+    )
+    .await
+    {
+        log::error!("Error storing session creation time: {}", e);
     }
+    val
 }
 
-pub async fn set(session_id: &str, val: &str) -> Result<(), anyhow::Error> {
+/// Stores the session, it's cached right away so the session limit of the user counts it
+pub async fn set(session_id: &str, user_id: &str, token: &str) -> Result<(), anyhow::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let val = StoredSession::User {
+        token: token.to_string(),
+        user_id: user_id.to_string(),
+        created_at: now,
+        last_used_at: now,
+    };
     db::put(
         &format!("{USER_SESSION_KEY}{session_id}"),
         json::to_vec(&val).unwrap().into(),
@@ -44,10 +320,14 @@ pub async fn set(session_id: &str, val: &str) -> Result<(), anyhow::Error> {
         None,
     )
     .await?;
+    USER_SESSIONS.insert(session_id.to_string(), val.into_session(now));
     Ok(())
 }
 
+/// Deletes the session, it's dropped from the cache right away so it's rejected by this node
+/// before the other nodes are notified
 pub async fn delete(session_id: &str) -> Result<(), anyhow::Error> {
+    USER_SESSIONS.remove(session_id);
     Ok(db::delete(
         &format!("{USER_SESSION_KEY}{session_id}"),
         false,
@@ -74,7 +354,7 @@ pub async fn watch() -> Result<(), anyhow::Error> {
         match ev {
             db::Event::Put(ev) => {
                 let item_key = ev.key.strip_prefix(key).unwrap();
-                let item_value = if config::get_config().common.meta_store_external {
+                let item_value: StoredSession = if config::get_config().common.meta_store_external {
                     match db::get(&ev.key).await {
                         Ok(val) => match json::from_slice(&val) {
                             Ok(val) => val,
@@ -91,7 +371,10 @@ pub async fn watch() -> Result<(), anyhow::Error> {
                 } else {
                     json::from_slice(&ev.value.unwrap()).unwrap()
                 };
-                USER_SESSIONS.insert(item_key.to_string(), item_value);
+                USER_SESSIONS.insert(
+                    item_key.to_string(),
+                    item_value.into_session(chrono::Utc::now().timestamp()),
+                );
             }
             db::Event::Delete(ev) => {
                 let item_key = ev.key.strip_prefix(key).unwrap();
@@ -105,11 +388,199 @@ pub async fn watch() -> Result<(), anyhow::Error> {
 pub async fn cache() -> Result<(), anyhow::Error> {
     let key = USER_SESSION_KEY;
     let ret = db::list(key).await?;
+    let now = chrono::Utc::now().timestamp();
     for (item_key, item_value) in ret {
         let session_id = item_key.strip_prefix(key).unwrap();
-        let json_val: String = json::from_slice(&item_value).unwrap();
-        USER_SESSIONS.insert(session_id.to_owned(), json_val);
+        let json_val: StoredSession = json::from_slice(&item_value).unwrap();
+        let json_val = record_created_at(&item_key, json_val, now).await;
+        USER_SESSIONS.insert(session_id.to_owned(), json_val.into_session(now));
     }
     log::info!("User Sessions Cached");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_idle_expiry() {
+        let sessions = RwHashMap::default();
+        let (ttl, idle_timeout) = (1000, 100);
+        sessions.insert("s1".to_string(), UserSession::new("token".to_string(), 0));
+
+        // every lookup slides the idle timeout
+        assert_eq!(
+            lookup(&sessions, "s1", 99, ttl, idle_timeout),
+            Lookup::Found("token".to_string())
+        );
+        assert_eq!(
+            lookup(&sessions, "s1", 198, ttl, idle_timeout),
+            Lookup::Found("token".to_string())
+        );
+        assert_eq!(sessions.get("s1").unwrap().last_used_at, 198);
+
+        // unused on this node for the whole idle timeout
+        assert_eq!(
+            lookup(&sessions, "s1", 298, ttl, idle_timeout),
+            Lookup::Idle
+        );
+        assert!(sessions.get("s1").is_none());
+        assert_eq!(
+            lookup(&sessions, "s1", 298, ttl, idle_timeout),
+            Lookup::Missing
+        );
+        assert_eq!(
+            lookup(&sessions, "missing", 0, ttl, idle_timeout),
+            Lookup::Missing
+        );
+    }
+
+    #[test]
+    fn test_session_absolute_expiry() {
+        let sessions = RwHashMap::default();
+        let (ttl, idle_timeout) = (300, 100);
+        sessions.insert("s1".to_string(), UserSession::new("token".to_string(), 0));
+
+        // in use all the time, still expires once the ttl has passed
+        for now in (50..300).step_by(50) {
+            assert_eq!(
+                lookup(&sessions, "s1", now, ttl, idle_timeout),
+                Lookup::Found("token".to_string())
+            );
+        }
+        assert_eq!(
+            lookup(&sessions, "s1", 300, ttl, idle_timeout),
+            Lookup::Expired
+        );
+
+        // limits of 0 are disabled
+        sessions.insert("s2".to_string(), UserSession::new("token".to_string(), 0));
+        assert_eq!(
+            lookup(&sessions, "s2", i64::MAX, 0, 0),
+            Lookup::Found("token".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stored_session() {
+        let session = StoredSession::User {
+            token: "token".to_string(),
+            user_id: "user@example.com".to_string(),
+            created_at: 10,
+            last_used_at: 15,
+        };
+        let stored = json::to_vec(&session).unwrap();
+        let session: StoredSession = json::from_slice(&stored).unwrap();
+        assert_eq!(
+            session.into_session(20),
+            UserSession {
+                token: "token".to_string(),
+                user_id: "user@example.com".to_string(),
+                created_at: 10,
+                last_used_at: 15,
+            }
+        );
+
+        // stored before the last use was recorded
+        let session: StoredSession =
+            json::from_slice(br#"{"token":"token","user_id":"user@example.com","created_at":10}"#)
+                .unwrap();
+        assert_eq!(session.into_session(20).last_used_at, 20);
+
+        // stored before the user was recorded
+        let session: StoredSession = json::from_slice(br#""token""#).unwrap();
+        assert_eq!(
+            session.into_session(20),
+            UserSession::new("token".to_string(), 20)
+        );
+    }
+
+    #[test]
+    fn test_session_sweep() {
+        let sessions = RwHashMap::default();
+        let (ttl, idle_timeout) = (300, 100);
+        sessions.insert("old".to_string(), UserSession::new("a".to_string(), 0));
+        sessions.insert("idle".to_string(), UserSession::new("b".to_string(), 200));
+        sessions.insert("active".to_string(), UserSession::new("c".to_string(), 250));
+
+        let mut expired = sweep(&sessions, 310, ttl, idle_timeout);
+        expired.sort();
+        assert_eq!(expired, ["idle", "old"]);
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.contains_key("active"));
+    }
+
+    #[tokio::test]
+    async fn test_record_created_at() {
+        let key = format!("{USER_SESSION_KEY}test_record_created_at");
+        let legacy = StoredSession::Token("token".to_string());
+        let session = record_created_at(&key, legacy, 10).await;
+        let recorded = StoredSession::User {
+            token: "token".to_string(),
+            user_id: "".to_string(),
+            created_at: 10,
+            last_used_at: 0,
+        };
+        assert_eq!(session, recorded);
+        let stored: StoredSession = json::from_slice(&db::get(&key).await.unwrap()).unwrap();
+        assert_eq!(stored, recorded);
+
+        // recorded once, a later load keeps it
+        let session = record_created_at(&key, stored, 20).await;
+        assert_eq!(session, recorded);
+        db::delete(&key, false, db::NO_NEED_WATCH, None)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_is_touch_due() {
+        // (last_used_at, now, idle_timeout, due)
+        let cases = [
+            (0, 10, 100, false),
+            (0, 25, 100, true),
+            (30, 49, 100, false),
+            (49, 50, 100, true),
+            (0, 1000, 0, false),
+            (0, 1, 3, true),
+        ];
+        for (last_used_at, now, idle_timeout, due) in cases {
+            assert_eq!(
+                is_touch_due(last_used_at, now, idle_timeout),
+                due,
+                "{last_used_at} {now} {idle_timeout}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_last_used() {
+        let session_id = "test_store_last_used";
+        let key = format!("{USER_SESSION_KEY}{session_id}");
+        let session = StoredSession::User {
+            token: "token".to_string(),
+            user_id: "user@example.com".to_string(),
+            created_at: 10,
+            last_used_at: 10,
+        };
+        db::put(
+            &key,
+            json::to_vec(&session).unwrap().into(),
+            db::NO_NEED_WATCH,
+            None,
+        )
+        .await
+        .unwrap();
+        store_last_used(session_id, 50).await;
+        let stored: StoredSession = json::from_slice(&db::get(&key).await.unwrap()).unwrap();
+        assert_eq!(stored.into_session(60).last_used_at, 50);
+
+        // a session deleted meanwhile isn't put back
+        db::delete(&key, false, db::NO_NEED_WATCH, None)
+            .await
+            .unwrap();
+        store_last_used(session_id, 70).await;
+        assert!(db::get(&key).await.is_err());
+    }
+}
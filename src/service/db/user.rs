@@ -22,6 +22,9 @@ use crate::{
     common::{
         infra::config::{ROOT_USER, USERS, USERS_RUM_TOKEN},
         meta::user::{DBUser, User, UserOrg, UserRole},
+        utils::auth::{
+            cache_user, invalidate_bearer_users, invalidate_root_user_check, uncache_user,
+        },
     },
     service::db,
 };
@@ -113,8 +116,11 @@ pub async fn set(user: &DBUser) -> Result<(), anyhow::Error> {
             salt: user.salt.clone(),
             is_external: user.is_external,
             password_ext: user.password_ext.clone(),
+            token_expires_at: org.token_expires_at,
+            previous_token: org.previous_token.clone(),
+            allowed_networks: org.allowed_networks.clone(),
         };
-        USERS.insert(
+        cache_user(
             format!("{}/{}", org.name.clone(), user.email.clone()),
             user.clone(),
         );
@@ -184,7 +190,7 @@ pub async fn watch() -> Result<(), anyhow::Error> {
                     } else {
                         user.role = UserRole::Admin;
                     };
-                    USERS.insert(format!("{}/{}", user.org, item_key), user);
+                    cache_user(format!("{}/{}", user.org, item_key), user);
                 }
 
                 #[cfg(feature = "enterprise")]
@@ -192,8 +198,11 @@ pub async fn watch() -> Result<(), anyhow::Error> {
                     if user.role.eq(&UserRole::Root) {
                         ROOT_USER.insert("root".to_string(), user.clone());
                     }
-                    USERS.insert(format!("{}/{}", user.org, item_key), user);
+                    cache_user(format!("{}/{}", user.org, item_key), user);
                 }
+                // the orgs of the user may have changed
+                invalidate_bearer_users(item_key);
+                invalidate_root_user_check(item_key);
                 // Invalidate the entire RUM-TOKEN-CACHE
                 USERS_RUM_TOKEN.clear();
             }
@@ -201,10 +210,12 @@ pub async fn watch() -> Result<(), anyhow::Error> {
                 let item_key = ev.key.strip_prefix(key).unwrap();
                 for user in USERS.clone() {
                     if user.1.email.eq(item_key) {
-                        USERS.remove(&format!("{}/{}", user.1.org, user.1.email));
+                        uncache_user(&format!("{}/{}", user.1.org, user.1.email));
                         break;
                     }
                 }
+                invalidate_bearer_users(item_key);
+                invalidate_root_user_check(item_key);
                 // Invalidate the entire RUM-TOKEN-CACHE
                 USERS_RUM_TOKEN.clear();
             }
@@ -228,7 +239,7 @@ pub async fn cache() -> Result<(), anyhow::Error> {
             } else {
                 user.role = UserRole::Admin;
             }
-            USERS.insert(format!("{}/{}", user.org, user.email), user.clone());
+            cache_user(format!("{}/{}", user.org, user.email), user.clone());
             if let Some(rum_token) = &user.rum_token {
                 USERS_RUM_TOKEN
                     .clone()
@@ -241,7 +252,7 @@ pub async fn cache() -> Result<(), anyhow::Error> {
             if user.role.eq(&UserRole::Root) {
                 ROOT_USER.insert("root".to_string(), user.clone());
             }
-            USERS.insert(format!("{}/{}", user.org, user.email), user.clone());
+            cache_user(format!("{}/{}", user.org, user.email), user.clone());
             if let Some(rum_token) = &user.rum_token {
                 USERS_RUM_TOKEN
                     .clone()
@@ -312,6 +323,7 @@ mod tests {
                 name: org_id.clone(),
                 token: "Abcd".to_string(),
                 rum_token: Some("rumAbcd".to_string()),
+                ..Default::default()
             }],
             password_ext: Some("pass".to_string()),
         })
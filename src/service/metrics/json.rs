@@ -217,6 +217,7 @@ pub async fn ingest(org_id: &str, body: web::Bytes) -> Result<IngestionResponse>
                     org_id,
                     &StreamType::Metrics.to_string(),
                     Authz::new(&stream_name),
+                    false,
                 )
                 .await;
             }
@@ -21,6 +21,7 @@ use regex::Regex;
 use crate::common::meta::stream::StreamParams;
 
 pub mod alerts;
+pub mod api_keys;
 pub mod compact;
 pub mod dashboards;
 pub mod db;
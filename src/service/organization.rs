@@ -15,7 +15,7 @@
 
 use std::io::{Error, ErrorKind};
 
-use config::{meta::stream::StreamType, utils::rand::generate_random_string};
+use config::{get_config, meta::stream::StreamType, utils::rand::generate_random_string};
 
 use crate::{
     common::{
@@ -25,9 +25,9 @@ use crate::{
                 IngestionPasscode, IngestionTokensContainer, OrgSummary, Organization,
                 RumIngestionToken,
             },
-            user::UserOrg,
+            user::{PreviousToken, UserOrg},
         },
-        utils::auth::is_root_user,
+        utils::auth::{invalidate_password_hashes, is_root_user, passcode_expires_at},
     },
     service::{db, stream::get_streams},
 };
@@ -70,6 +70,7 @@ pub async fn get_passcode(
     Ok(IngestionPasscode {
         user: user.email,
         passcode: user.token,
+        expires_at: user.token_expires_at,
     })
 }
 
@@ -91,7 +92,7 @@ pub async fn update_rum_token(
     user_id: &str,
 ) -> Result<RumIngestionToken, anyhow::Error> {
     let is_rum_update = true;
-    match update_passcode_inner(org_id, user_id, is_rum_update).await {
+    match update_passcode_inner(org_id, user_id, is_rum_update, 0).await {
         Ok(IngestionTokensContainer::RumToken(response)) => Ok(response),
         _ => Err(anyhow::Error::msg("User not found")),
     }
@@ -102,16 +103,74 @@ pub async fn update_passcode(
     user_id: &str,
 ) -> Result<IngestionPasscode, anyhow::Error> {
     let is_rum_update = false;
-    match update_passcode_inner(org_id, user_id, is_rum_update).await {
+    match update_passcode_inner(org_id, user_id, is_rum_update, 0).await {
         Ok(IngestionTokensContainer::Passcode(response)) => Ok(response),
         _ => Err(anyhow::Error::msg("User not found")),
     }
 }
 
+/// Issues a new passcode like [`update_passcode`], but the replaced one stays valid for
+/// `auth.passcode_rotation_grace` seconds, so clients can switch over without failed requests.
+pub async fn rotate_passcode(
+    org_id: Option<&str>,
+    user_id: &str,
+) -> Result<IngestionPasscode, anyhow::Error> {
+    let is_rum_update = false;
+    let grace = get_config().auth.passcode_rotation_grace;
+    match update_passcode_inner(org_id, user_id, is_rum_update, grace).await {
+        Ok(IngestionTokensContainer::Passcode(response)) => Ok(response),
+        _ => Err(anyhow::Error::msg("User not found")),
+    }
+}
+
+/// The org with `token` as its new passcode. The replaced passcode is kept for `grace` seconds,
+/// but not past its own expiry.
+fn with_new_passcode(org: &UserOrg, token: &str, now: i64, ttl: i64, grace: i64) -> UserOrg {
+    let grace_ends_at = now.saturating_add(grace);
+    let grace_ends_at = org
+        .token_expires_at
+        .map_or(grace_ends_at, |exp| exp.min(grace_ends_at));
+    let previous_token = (!org.token.is_empty() && grace_ends_at > now).then(|| PreviousToken {
+        token: org.token.clone(),
+        expires_at: grace_ends_at,
+    });
+    UserOrg {
+        token: token.to_string(),
+        token_expires_at: passcode_expires_at(now, ttl),
+        previous_token,
+        ..org.clone()
+    }
+}
+
+/// Forgets the passcode replaced by a rotation once its grace period ended, unless another
+/// rotation replaced it already
+async fn expire_previous_passcode(org_id: String, user_id: String, previous: PreviousToken) {
+    let wait = previous.expires_at - chrono::Utc::now().timestamp();
+    if wait > 0 {
+        tokio::time::sleep(std::time::Duration::from_secs(wait as u64)).await;
+    }
+    if let Ok(mut db_user) = db::user::get_db_user(&user_id).await {
+        let mut changed = false;
+        for org in db_user.organizations.iter_mut() {
+            if org.name == org_id && org.previous_token.as_ref() == Some(&previous) {
+                org.previous_token = None;
+                changed = true;
+            }
+        }
+        if changed {
+            if let Err(e) = db::user::set(&db_user).await {
+                log::error!("Error expiring the previous passcode of {user_id}: {e}");
+            }
+        }
+    }
+    invalidate_password_hashes(&previous.token);
+}
+
 async fn update_passcode_inner(
     org_id: Option<&str>,
     user_id: &str,
     is_rum_update: bool,
+    rotation_grace: i64,
 ) -> Result<IngestionTokensContainer, anyhow::Error> {
     let mut local_org_id = "dummy";
     let Ok(mut db_user) = db::user::get_db_user(user_id).await else {
@@ -123,6 +182,8 @@ async fn update_passcode_inner(
     }
     let token = generate_random_string(16);
     let rum_token = format!("rum{}", generate_random_string(16));
+    let now = chrono::Utc::now().timestamp();
+    let ttl = get_config().auth.passcode_ttl;
 
     let updated_org = |existing_org: &UserOrg| {
         if is_rum_update {
@@ -131,13 +192,11 @@ async fn update_passcode_inner(
                 ..existing_org.clone()
             }
         } else {
-            UserOrg {
-                token: token.clone(),
-                ..existing_org.clone()
-            }
+            with_new_passcode(existing_org, &token, now, ttl, rotation_grace)
         }
     };
 
+    let mut replaced = None;
     let mut orgs = db_user.clone().organizations;
     let new_orgs = if !is_root_user(user_id) {
         let mut existing_org = orgs.clone();
@@ -158,6 +217,7 @@ async fn update_passcode_inner(
         ));
 
         let updated_org = updated_org(&existing_org[0]);
+        replaced = Some((existing_org[0].token.clone(), updated_org.clone()));
         orgs.push(updated_org);
         orgs
     } else {
@@ -172,12 +232,26 @@ async fn update_passcode_inner(
         ));
 
         let updated_org = updated_org(&existing_org);
+        replaced = Some((existing_org.token.clone(), updated_org.clone()));
         vec![updated_org]
     };
 
     db_user.organizations = new_orgs;
     let _ = db::user::set(&db_user).await;
 
+    if let Some((old_token, org)) = replaced.filter(|_| !is_rum_update) {
+        match org.previous_token {
+            Some(previous) => {
+                tokio::spawn(expire_previous_passcode(
+                    org.name,
+                    db_user.email.clone(),
+                    previous,
+                ));
+            }
+            None => invalidate_password_hashes(&old_token),
+        }
+    }
+
     let ret = if is_rum_update {
         IngestionTokensContainer::RumToken(RumIngestionToken {
             user: db_user.email,
@@ -187,6 +261,7 @@ async fn update_passcode_inner(
         IngestionTokensContainer::Passcode(IngestionPasscode {
             user: db_user.email,
             passcode: token,
+            expires_at: passcode_expires_at(now, ttl),
         })
     };
     Ok(ret)
@@ -257,4 +332,76 @@ mod tests {
         let resp = update_passcode(Some(org_id), user_id).await.unwrap();
         assert_ne!(resp.passcode, passcode);
     }
+
+    #[test]
+    fn test_with_new_passcode() {
+        use crate::common::{
+            meta::user::User,
+            utils::auth::{check_passcode, PasscodeCheck},
+        };
+
+        let org = UserOrg {
+            name: "default".to_string(),
+            token: "old".to_string(),
+            ..Default::default()
+        };
+        let check = |org: &UserOrg, passcode: &str, now: i64| {
+            let user = User {
+                email: "passcode@example.com".to_string(),
+                first_name: "".to_string(),
+                last_name: "".to_string(),
+                password: "".to_string(),
+                salt: "".to_string(),
+                token: org.token.clone(),
+                rum_token: None,
+                role: org.role.clone(),
+                org: org.name.clone(),
+                is_external: false,
+                password_ext: None,
+                token_expires_at: org.token_expires_at,
+                previous_token: org.previous_token.clone(),
+                allowed_networks: org.allowed_networks.clone(),
+            };
+            check_passcode(&user, passcode, now)
+        };
+
+        // the old passcode overlaps with the new one for the grace period
+        let rotated = with_new_passcode(&org, "new", 1000, 0, 100);
+        assert_eq!(
+            rotated.previous_token,
+            Some(PreviousToken {
+                token: "old".to_string(),
+                expires_at: 1100,
+            })
+        );
+        assert_eq!(rotated.token_expires_at, None);
+        for (passcode, now, expected) in [
+            ("new", 1000, PasscodeCheck::Valid),
+            ("old", 1000, PasscodeCheck::Valid),
+            ("old", 1099, PasscodeCheck::Valid),
+            ("old", 1100, PasscodeCheck::Expired),
+            ("new", 1100, PasscodeCheck::Valid),
+        ] {
+            assert_eq!(check(&rotated, passcode, now), expected, "{passcode} {now}");
+        }
+
+        // without a grace period the old passcode is replaced at once
+        let updated = with_new_passcode(&org, "new", 1000, 0, 0);
+        assert_eq!(updated.previous_token, None);
+        assert_eq!(check(&updated, "old", 1000), PasscodeCheck::Invalid);
+
+        // the new passcode expires after the ttl, the grace period not after the old expiry
+        let org = UserOrg {
+            token_expires_at: Some(1050),
+            ..org
+        };
+        let rotated = with_new_passcode(&org, "new", 1000, 500, 100);
+        assert_eq!(rotated.token_expires_at, Some(1500));
+        assert_eq!(rotated.previous_token.map(|p| p.expires_at), Some(1050));
+        assert_eq!(check(&rotated, "new", 1500), PasscodeCheck::Expired);
+
+        // an expired passcode gets no grace period
+        let rotated = with_new_passcode(&org, "new", 2000, 500, 100);
+        assert_eq!(rotated.previous_token, None);
+    }
 }
@@ -269,6 +269,7 @@ async fn handle_diff_schema(
             org_id,
             &stream_type.to_string(),
             Authz::new(stream_name),
+            false,
         )
         .await;
     }
@@ -63,7 +63,7 @@ pub async fn check_cache(
     }
 
     // Hack select for _timestamp
-    if !is_aggregate && parsed_sql.order_by.is_empty() && !origin_sql.contains('*') {
+    if !is_aggregate && !parsed_sql.explicit_order && !origin_sql.contains('*') {
         let caps = RE_SELECT_FROM.captures(origin_sql.as_str()).unwrap();
         let cap_str = caps.get(1).unwrap().as_str();
         if !cap_str.contains(&cfg.common.column_timestamp) {
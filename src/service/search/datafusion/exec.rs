@@ -464,7 +464,10 @@ async fn get_fast_mode_ctx(
     without_optimizer: bool,
 ) -> Result<(SessionContext, Arc<Schema>)> {
     let mut files = files.to_vec();
-    let desc = sql.meta.order_by.is_empty() || sql.meta.order_by[0].1;
+    let order_by = sql
+        .meta
+        .order_by_or_default((&get_config().common.column_timestamp, sql::OrderBy::Desc));
+    let desc = order_by[0].1 == sql::OrderBy::Desc;
     if desc {
         files.sort_by(|a, b| a.meta.min_ts.cmp(&b.meta.min_ts));
     } else {
@@ -22,7 +22,7 @@ use chrono::Duration;
 use config::{
     get_config,
     meta::{
-        sql::{Sql as MetaSql, SqlOperator},
+        sql::{strip_comments, MatchType, OrderBy, Sql as MetaSql, SqlOperator},
         stream::{FileKey, StreamPartition, StreamPartitionType, StreamType},
     },
     QUICK_MODEL_FIELDS,
@@ -131,14 +131,9 @@ impl Sql {
         }
 
         // parse sql
-        let mut origin_sql = req_query.sql.clone();
-        let mut rewrite_sql = req_query.sql.clone();
+        let mut rewrite_sql = strip_comments(&req_query.sql);
         // log::info!("origin_sql: {:?}", origin_sql);
-        origin_sql = origin_sql.replace('\n', " ");
-        origin_sql = origin_sql.trim().to_string();
-        if origin_sql.ends_with(';') {
-            origin_sql.pop();
-        }
+        let mut origin_sql = rewrite_sql.replace('\n', " ");
         origin_sql = split_sql_token(&origin_sql).join("");
         let mut meta = match MetaSql::new(&origin_sql) {
             Ok(meta) => meta,
@@ -152,6 +147,13 @@ impl Sql {
             }
         };
 
+        // search requests carry no parameters, so a `LIMIT $1` can't be bound
+        if let Some(param) = meta.limit_param.as_ref().or(meta.offset_param.as_ref()) {
+            return Err(Error::ErrorCode(ErrorCodes::SearchSQLNotValid(format!(
+                "LIMIT/OFFSET placeholder {param} has no bound value"
+            ))));
+        }
+
         // Hack for table name
         // DataFusion disallow use `k8s-logs-2022.09.11` as table name
         let stream_name = meta.source.clone();
@@ -270,7 +272,7 @@ impl Sql {
         }
 
         // Hack select for _timestamp, add _timestamp to select clause
-        if !sql_mode.eq(&SqlMode::Full) && meta.order_by.is_empty() && !origin_sql.contains('*') {
+        if !sql_mode.eq(&SqlMode::Full) && !meta.explicit_order && !origin_sql.contains('*') {
             let caps = RE_SELECT_FROM.captures(origin_sql.as_str()).unwrap();
             let cap_str = caps.get(1).unwrap().as_str();
             if !cap_str.contains(&cfg.common.column_timestamp) {
@@ -349,7 +351,7 @@ impl Sql {
                             where_str = where_str
                                 [0..where_str.to_lowercase().rfind(" having ").unwrap()]
                                 .to_string();
-                        } else if !meta.order_by.is_empty() {
+                        } else if meta.explicit_order {
                             where_str = where_str
                                 [0..where_str.to_lowercase().rfind(" order ").unwrap()]
                                 .to_string();
@@ -395,7 +397,7 @@ impl Sql {
             } else {
                 cfg.limit.query_default_limit * std::cmp::max(1, meta.group_by.len() as i64)
             };
-            origin_sql = if meta.order_by.is_empty()
+            origin_sql = if !meta.explicit_order
                 && (!sql_mode.eq(&SqlMode::Full)
                     || (meta.group_by.is_empty()
                         && !origin_sql
@@ -407,18 +409,19 @@ impl Sql {
                         && !origin_sql.to_lowercase().contains("distinct"))
             {
                 let sort_by = if req_query.sort_by.is_empty() {
-                    meta.order_by = vec![(cfg.common.column_timestamp.to_string(), true)];
+                    meta.order_by =
+                        meta.order_by_or_default((&cfg.common.column_timestamp, OrderBy::Desc));
                     format!("{} DESC", cfg.common.column_timestamp)
                 } else {
                     if req_query.sort_by.to_uppercase().ends_with(" DESC") {
                         meta.order_by = vec![(
                             req_query.sort_by[0..req_query.sort_by.len() - 5].to_string(),
-                            true,
+                            OrderBy::Desc,
                         )];
                     } else if req_query.sort_by.to_uppercase().ends_with(" ASC") {
                         meta.order_by = vec![(
                             req_query.sort_by[0..req_query.sort_by.len() - 4].to_string(),
-                            false,
+                            OrderBy::Asc,
                         )];
                     }
                     req_query.sort_by.clone()
@@ -771,15 +774,22 @@ impl Sql {
 }
 
 pub fn generate_filter_from_quick_text(
-    data: &[(String, String, SqlOperator)],
+    data: &[(String, String, SqlOperator, MatchType, Option<String>)],
 ) -> Vec<(&str, Vec<String>)> {
     let quick_text_len = data.len();
     let mut filters = HashMap::with_capacity(quick_text_len);
+    // fields matched by a pattern or through a function like lower() can't be used as exact
+    // value filters
+    let mut pattern_fields = HashSet::new();
     for i in 0..quick_text_len {
-        let (k, v, op) = &data[i];
+        let (k, v, op, match_type, wrapper) = &data[i];
         if op == &SqlOperator::And
             || (op == &SqlOperator::Or && (i + 1 == quick_text_len || k == &data[i + 1].0))
         {
+            if match_type != &MatchType::Exact || wrapper.is_some() {
+                pattern_fields.insert(k.as_str());
+                continue;
+            }
             let entry = filters.entry(k.as_str()).or_insert_with(Vec::new);
             entry.push(v.to_string());
         } else {
@@ -787,7 +797,10 @@ pub fn generate_filter_from_quick_text(
             break;
         }
     }
-    filters.into_iter().collect::<Vec<(_, _)>>()
+    filters
+        .into_iter()
+        .filter(|(k, _)| !pattern_fields.contains(k))
+        .collect::<Vec<(_, _)>>()
 }
 
 pub(crate) fn generate_quick_mode_fields(
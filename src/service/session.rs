@@ -19,10 +19,24 @@ pub async fn get_session(session_id: &str) -> Option<String> {
     db::session::get(session_id).await.ok()
 }
 
-pub async fn set_session(session_id: &str, val: &str) -> Option<()> {
-    db::session::set(session_id, val).await.ok()
+pub async fn set_session(session_id: &str, user_id: &str, val: &str) -> Option<()> {
+    db::session::set(session_id, user_id, val).await.ok()
 }
 
 pub async fn remove_session(session_id: &str) {
     let _ = db::session::delete(session_id).await;
 }
+
+/// Drops expired and idle sessions from the cache, and from the store when the stored value
+/// agrees. A session is also checked whenever it's used, this bounds what abandoned ones hold.
+pub async fn run_sweeper() {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+    interval.tick().await; // trigger the first run
+    loop {
+        interval.tick().await;
+        let removed = db::session::sweep_expired().await;
+        if removed > 0 {
+            log::debug!("Removed {removed} expired user sessions");
+        }
+    }
+}
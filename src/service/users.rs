@@ -30,7 +30,10 @@ use crate::{
                 DBUser, UpdateUser, User, UserList, UserOrg, UserRequest, UserResponse, UserRole,
             },
         },
-        utils::auth::{get_hash, is_root_user},
+        utils::auth::{
+            get_hash, hash_password, is_root_user, is_valid_email, is_valid_email_lenient,
+            needs_rehash, uncache_user, verify_password,
+        },
     },
     service::db,
 };
@@ -53,8 +56,15 @@ pub async fn post_user(
             db::user::get(Some(org_id), &usr_req.email).await
         };
         if existing_user.is_err() {
+            // the default email regex only matches lowercase
+            if !is_valid_email(&usr_req.email.to_lowercase()) {
+                return Ok(HttpResponse::BadRequest().json(MetaHttpResponse::error(
+                    http::StatusCode::BAD_REQUEST.into(),
+                    "Invalid email id or email domain is not allowed".to_string(),
+                )));
+            }
             let salt = ider::uuid();
-            let password = get_hash(&usr_req.password, &salt);
+            let password = hash_password(&usr_req.password, &salt);
             let password_ext = get_hash(&usr_req.password, &cfg.auth.ext_auth_salt);
             let token = generate_random_string(16);
             let rum_token = format!("rum{}", generate_random_string(16));
@@ -127,7 +137,7 @@ pub async fn update_db_user(mut db_user: DBUser) -> Result<(), anyhow::Error> {
     if db_user.password.is_empty() {
         let salt = ider::uuid();
         let generated_pass = generate_random_string(8);
-        let password = get_hash(&generated_pass, &salt);
+        let password = hash_password(&generated_pass, &salt);
         db_user.password = password;
         db_user.salt = salt;
     }
@@ -198,13 +208,10 @@ pub async fn update_user(
 
                 new_user = local_user.clone();
                 if self_update && user.old_password.is_some() && user.new_password.is_some() {
-                    if local_user.password.eq(&get_hash(
-                        &user.clone().old_password.unwrap(),
-                        &local_user.salt,
-                    )) {
+                    if verify_password(&user.clone().old_password.unwrap(), &local_user.password) {
                         let new_pass = user.new_password.unwrap();
 
-                        new_user.password = get_hash(&new_pass, &local_user.salt);
+                        new_user.password = hash_password(&new_pass, &local_user.salt);
                         new_user.password_ext = Some(get_hash(&new_pass, password_ext_salt));
                         log::info!("Password self updated for user: {}", email);
                         is_updated = true;
@@ -221,7 +228,7 @@ pub async fn update_user(
                 {
                     let new_pass = user.new_password.unwrap();
 
-                    new_user.password = get_hash(&new_pass, &local_user.salt);
+                    new_user.password = hash_password(&new_pass, &local_user.salt);
                     new_user.password_ext = Some(get_hash(&new_pass, password_ext_salt));
                     log::info!("Password by root updated for user: {}", email);
 
@@ -250,6 +257,32 @@ pub async fn update_user(
                 }
                 if user.token.is_some() {
                     new_user.token = user.token.unwrap();
+                    // the expiry and the grace period belonged to the replaced passcode
+                    new_user.token_expires_at = None;
+                    new_user.previous_token = None;
+                    is_org_updated = true;
+                }
+                if let Some(networks) = user.allowed_networks {
+                    #[cfg(feature = "enterprise")]
+                    let is_service_account = new_user.role.eq(&UserRole::ServiceAccount);
+                    #[cfg(not(feature = "enterprise"))]
+                    let is_service_account = false;
+                    if self_update || !is_service_account {
+                        return Ok(HttpResponse::BadRequest().json(MetaHttpResponse::message(
+                            http::StatusCode::BAD_REQUEST.into(),
+                            "Only an admin can limit the networks of a service account".to_string(),
+                        )));
+                    }
+                    if let Some(invalid) = networks
+                        .iter()
+                        .find(|v| v.trim().parse::<ipnetwork::IpNetwork>().is_err())
+                    {
+                        return Ok(HttpResponse::BadRequest().json(MetaHttpResponse::message(
+                            http::StatusCode::BAD_REQUEST.into(),
+                            format!("Invalid IP or CIDR: {invalid}"),
+                        )));
+                    }
+                    new_user.allowed_networks = networks;
                     is_org_updated = true;
                 }
                 if is_updated || is_org_updated {
@@ -268,6 +301,9 @@ pub async fn update_user(
                                         token: new_user.token,
                                         rum_token: new_user.rum_token,
                                         role: new_user.role,
+                                        token_expires_at: new_user.token_expires_at,
+                                        previous_token: new_user.previous_token,
+                                        allowed_networks: new_user.allowed_networks,
                                     }]
                                 } else {
                                     orgs.retain(|org| !org.name.eq(org_id));
@@ -276,6 +312,9 @@ pub async fn update_user(
                                         token: new_user.token,
                                         rum_token: new_user.rum_token,
                                         role: new_user.role,
+                                        token_expires_at: new_user.token_expires_at,
+                                        previous_token: new_user.previous_token,
+                                        allowed_networks: new_user.allowed_networks,
                                     });
                                     orgs
                                 };
@@ -313,6 +352,7 @@ pub async fn update_user(
                                             );
                                             update_user_role(&old_str, &new_str, email, org_id)
                                                 .await;
+                                            crate::common::utils::auth::invalidate_permission_decisions(org_id);
                                         }
                                     }
                                 }
@@ -362,6 +402,13 @@ pub async fn add_user_to_org(
     let existing_user = db::user::get_db_user(email).await;
     let root_user = ROOT_USER.clone();
     if existing_user.is_ok() {
+        // stored users may predate the current email checks
+        if !is_valid_email_lenient(&email.to_lowercase()) {
+            return Ok(HttpResponse::BadRequest().json(MetaHttpResponse::error(
+                http::StatusCode::BAD_REQUEST.into(),
+                "Invalid email id".to_string(),
+            )));
+        }
         let mut db_user = existing_user.unwrap();
         let local_org;
         let initiating_user = if is_root_user(initiator_id) {
@@ -384,6 +431,7 @@ pub async fn add_user_to_org(
                     token,
                     rum_token: Some(rum_token),
                     role: role.clone(),
+                    ..Default::default()
                 }]
             } else {
                 if db_user.is_external {
@@ -403,6 +451,7 @@ pub async fn add_user_to_org(
                     token,
                     rum_token: Some(rum_token),
                     role: role.clone(),
+                    ..Default::default()
                 });
                 orgs
             };
@@ -573,6 +622,7 @@ pub async fn remove_user_from_org(
                             if O2_CONFIG.openfga.enabled {
                                 log::debug!("delete user single org, role: {}", &user_fga_role);
                                 delete_user_from_org(org_id, email_id, &user_fga_role).await;
+                                crate::common::utils::auth::invalidate_permission_decisions(org_id);
                             }
                         }
                     } else {
@@ -595,7 +645,7 @@ pub async fn remove_user_from_org(
                         let resp = db::user::set(&user).await;
                         // special case as we cache flattened user struct
                         if resp.is_ok() {
-                            USERS.remove(&format!("{org_id}/{email_id}"));
+                            uncache_user(&format!("{org_id}/{email_id}"));
                             #[cfg(feature = "enterprise")]
                             {
                                 use o2_enterprise::enterprise::openfga::authorizer::authz::delete_user_from_org;
@@ -610,6 +660,9 @@ pub async fn remove_user_from_org(
                                         _user_fga_role.unwrap().as_str(),
                                     )
                                     .await;
+                                    crate::common::utils::auth::invalidate_permission_decisions(
+                                        org_id,
+                                    );
                                 }
                             }
                         }
@@ -675,10 +728,27 @@ pub fn is_user_from_org(orgs: Vec<UserOrg>, org_id: &str) -> (bool, UserOrg) {
     }
 }
 
+/// Re-hashes the stored password of the user with the current parameters, once a login proved
+/// `pass` is its password. Best effort, called after the login succeeded.
+pub(crate) async fn upgrade_password_hash(email: &str, pass: &str) {
+    let Ok(mut db_user) = db::user::get_db_user(email).await else {
+        return;
+    };
+    // the login may have been checked against a cached user that changed since
+    if !needs_rehash(&db_user.password) || !verify_password(pass, &db_user.password) {
+        return;
+    }
+    db_user.password = hash_password(pass, &db_user.salt);
+    match db::user::set(&db_user).await {
+        Ok(()) => log::info!("Upgraded the password hash of user: {email}"),
+        Err(e) => log::error!("Error upgrading the password hash of user {email}: {e}"),
+    }
+}
+
 pub(crate) async fn create_root_user(org_id: &str, usr_req: UserRequest) -> Result<(), Error> {
     let cfg = get_config();
     let salt = ider::uuid();
-    let password = get_hash(&usr_req.password, &salt);
+    let password = hash_password(&usr_req.password, &salt);
     let password_ext = get_hash(&usr_req.password, &cfg.auth.ext_auth_salt);
     let token = generate_random_string(16);
     let rum_token = format!("rum{}", generate_random_string(16));
@@ -716,6 +786,9 @@ mod tests {
                 org: "dummy".to_string(),
                 is_external: false,
                 password_ext: Some("pass#123".to_string()),
+                token_expires_at: None,
+                previous_token: None,
+                allowed_networks: vec![],
             },
         );
     }
@@ -777,6 +850,7 @@ mod tests {
                 new_password: Some("new_pass".to_string()),
                 role: Some(crate::common::meta::user::UserRole::Member),
                 change_password: false,
+                allowed_networks: None,
             },
         )
         .await;
@@ -796,6 +870,7 @@ mod tests {
                 new_password: None,
                 role: Some(crate::common::meta::user::UserRole::Admin),
                 change_password: false,
+                allowed_networks: None,
             },
         )
         .await;
@@ -816,4 +891,39 @@ mod tests {
 
         assert!(resp.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_upgrade_password_hash() {
+        infra_db::create_table().await.unwrap();
+        let salt = "upgrade-salt";
+        let legacy = get_hash("Complexpass#123", salt);
+        let user = DBUser {
+            email: "upgrade@zo.dev".to_string(),
+            first_name: "upgrade".to_string(),
+            last_name: "".to_string(),
+            password: legacy.clone(),
+            salt: salt.to_string(),
+            organizations: vec![UserOrg {
+                name: "dummy".to_string(),
+                token: "token".to_string(),
+                rum_token: None,
+                role: UserRole::Member,
+                ..Default::default()
+            }],
+            is_external: false,
+            password_ext: None,
+        };
+        db::user::set(&user).await.unwrap();
+
+        // a wrong password doesn't touch the stored hash
+        upgrade_password_hash("upgrade@zo.dev", "wrong").await;
+        let stored = db::user::get_db_user("upgrade@zo.dev").await.unwrap();
+        assert_eq!(stored.password, legacy);
+
+        upgrade_password_hash("upgrade@zo.dev", "Complexpass#123").await;
+        let stored = db::user::get_db_user("upgrade@zo.dev").await.unwrap();
+        assert!(stored.password.starts_with("$argon2id$"));
+        assert!(verify_password("Complexpass#123", &stored.password));
+        assert!(!needs_rehash(&stored.password));
+    }
 }